@@ -0,0 +1,510 @@
+//! Authenticated Code Modules (ACM)
+//!
+//! ACMs are signed by Intel and executed by the CPU in a protected
+//! environment. The Startup ACM, found through the `StartupACM` FIT entry,
+//! implements Boot Guard and is run before any host firmware. The Diagnostic
+//! ACM and the TXT BIOS/SINIT ACMs share the same format.
+//!
+//! For the data structures, see the Intel TXT Software Development Guide,
+//! Appendix A.1 "Authenticated Code Module Format", tboot `include/acmod.h`
+//! and <https://github.com/linuxboot/fiano/tree/main/pkg/intel/metadata/fit>.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+use zerocopy::{FromBytes, Ref};
+use zerocopy_derive::{FromBytes, Immutable, IntoBytes};
+
+use crate::bootguard::sig::{
+    ALG_RSAPSS, ALG_RSASSA, ALG_SHA256, ALG_SHA384, alg_name, check_pkcs1_v15, check_pss, hash,
+    hex, rsa_apply,
+};
+use crate::fit::{EntryType, Fit};
+
+pub const MODULE_TYPE_CHIPSET: u16 = 2;
+pub const VENDOR_INTEL: u32 = 0x8086;
+
+/// Header version for RSA-2048 keys with PKCS#1 v1.5 signatures
+pub const HEADER_VERSION_0: u32 = 0x0000_0000;
+/// Header version for RSA-3072 keys with RSA-PSS signatures
+pub const HEADER_VERSION_3: u32 = 0x0003_0000;
+
+/// The exponent is only stored in version 0 headers.
+const DEFAULT_EXPONENT: u32 = 0x10001;
+
+const FLAG_PRE_PRODUCTION: u16 = 1 << 14;
+const FLAG_DEBUG_SIGNED: u16 = 1 << 15;
+
+// The info table is at the start of the user area.
+const INFO_TABLE_UUID: [u8; 16] = [
+    0xaa, 0x3a, 0xc0, 0x7f, 0xa7, 0x46, 0xdb, 0x18, 0x2e, 0xac, 0x69, 0x8f, 0x8d, 0x41, 0x7f, 0x5a,
+];
+
+// There are typically only a few chipsets and processors per ACM.
+const MAX_IDS: usize = 64;
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct AcmHeader {
+    pub module_type: u16,
+    pub module_subtype: u16,
+    /// in dwords, without the scratch area
+    pub header_len: u32,
+    pub header_version: u32,
+    pub chipset_id: u16,
+    pub flags: u16,
+    pub vendor: u32,
+    /// BCD encoded, yyyymmdd
+    pub date: u32,
+    /// in dwords
+    pub size: u32,
+    pub txt_svn: u16,
+    pub se_svn: u16,
+    pub code_control: u32,
+    pub error_entry_point: u32,
+    pub gdt_limit: u32,
+    pub gdt_base: u32,
+    pub seg_sel: u32,
+    pub entry_point: u32,
+    #[serde(with = "serde_bytes")]
+    pub _reserved: [u8; 64],
+    /// in dwords
+    pub key_size: u32,
+    /// in dwords
+    pub scratch_size: u32,
+}
+
+pub const HEADER_SIZE: usize = core::mem::size_of::<AcmHeader>();
+
+impl AcmHeader {
+    pub fn header_len(&self) -> usize {
+        self.header_len as usize * 4
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize * 4
+    }
+
+    pub fn key_size(&self) -> usize {
+        self.key_size as usize * 4
+    }
+
+    pub fn scratch_size(&self) -> usize {
+        self.scratch_size as usize * 4
+    }
+
+    /// Offset of the user area, i.e., the code and info table
+    pub fn user_area_offset(&self) -> usize {
+        self.header_len() + self.scratch_size()
+    }
+
+    pub fn module_subtype_name(&self) -> &'static str {
+        match self.module_subtype {
+            0 => "TXT",
+            1 => "Startup",
+            _ => "unknown",
+        }
+    }
+
+    /// Format the BCD encoded date as yyyy-mm-dd.
+    pub fn date(&self) -> String {
+        let d = self.date;
+        format!("{:04x}-{:02x}-{:02x}", d >> 16, (d >> 8) & 0xff, d & 0xff)
+    }
+
+    pub fn is_debug_signed(&self) -> bool {
+        self.flags & FLAG_DEBUG_SIGNED != 0
+    }
+
+    pub fn is_pre_production(&self) -> bool {
+        self.flags & FLAG_PRE_PRODUCTION != 0
+    }
+}
+
+impl Display for AcmHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.module_type;
+        let st = self.module_subtype_name();
+        let hv = self.header_version;
+        let v = self.vendor;
+        let ven = match v {
+            VENDOR_INTEL => "Intel",
+            _ => "unknown",
+        };
+        let d = self.date();
+        let sz = self.size();
+        let svn = self.txt_svn;
+        let se = self.se_svn;
+        let c = self.chipset_id;
+        write!(
+            f,
+            "type {t} ({st}), header version {hv:08x}, vendor {ven} ({v:04x}), {d}, {sz:08x} bytes"
+        )?;
+        write!(f, "\n  TXT SVN {svn}, SE SVN {se}, chipset ID {c:04x}")?;
+        if self.is_debug_signed() {
+            write!(f, ", debug signed")?;
+        }
+        if self.is_pre_production() {
+            write!(f, ", pre-production")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct InfoTable {
+    pub uuid: [u8; 16],
+    /// 0 for BIOS ACMs, 1 for SINIT ACMs
+    pub chipset_acm_type: u8,
+    pub version: u8,
+    pub length: u16,
+    /// Offset from the start of the ACM
+    pub chipset_id_list: u32,
+    pub os_sinit_data_version: u32,
+    pub min_mle_header_version: u32,
+    pub capabilities: u32,
+    pub acm_version: u8,
+    pub acm_revision: [u8; 3],
+    /// Offset from the start of the ACM, version 4 and later
+    pub processor_id_list: u32,
+}
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct ChipsetId {
+    pub flags: u32,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub revision_id: u16,
+    pub _reserved: u16,
+    pub extended_id: u32,
+}
+
+impl Display for ChipsetId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = self.vendor_id;
+        let d = self.device_id;
+        let r = self.revision_id;
+        let fl = self.flags;
+        write!(f, "{v:04x}:{d:04x} revision {r:04x}, flags {fl:08x}")
+    }
+}
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct ProcessorId {
+    /// CPUID family, model and stepping
+    pub fms: u32,
+    pub fms_mask: u32,
+    pub platform_id: u64,
+    pub platform_mask: u64,
+}
+
+impl Display for ProcessorId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.fms;
+        let m = self.fms_mask;
+        let p = self.platform_id;
+        let pm = self.platform_mask;
+        write!(
+            f,
+            "CPUID {s:08x} mask {m:08x}, platform {p:016x} mask {pm:016x}"
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Acm {
+    pub header: AcmHeader,
+    /// Little-endian, as stored in the header
+    #[serde(with = "serde_bytes")]
+    pub modulus: Vec<u8>,
+    pub exponent: u32,
+    /// Little-endian, as stored in the header
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+    pub info_table: Option<InfoTable>,
+    pub chipset_ids: Vec<ChipsetId>,
+    pub processor_ids: Vec<ProcessorId>,
+    /// The data covered by the signature
+    #[serde(with = "serde_bytes")]
+    signed_data: Vec<u8>,
+}
+
+// Read a list of `T` prefixed with a count at the given offset.
+fn parse_id_list<T: FromBytes + zerocopy::Immutable + Clone>(
+    data: &[u8],
+    offset: usize,
+    what: &str,
+) -> Result<Vec<T>, String> {
+    let Some(d) = data.get(offset..) else {
+        return Err(format!("{what} list @ {offset:08x} out of bounds"));
+    };
+    let Ok((count, rest)) = u32::read_from_prefix(d) else {
+        return Err(format!("could not read {what} count @ {offset:08x}"));
+    };
+    let count = count as usize;
+    if count > MAX_IDS {
+        return Err(format!("{what} count {count} too big"));
+    }
+    let Ok((r, _)) = Ref::<_, [T]>::from_prefix_with_elems(rest, count) else {
+        return Err(format!("could not read {count} {what} entries"));
+    };
+    Ok(r.to_vec())
+}
+
+impl Acm {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let Ok((header, rest)) = AcmHeader::read_from_prefix(data) else {
+            return Err("could not read ACM header".into());
+        };
+        let t = header.module_type;
+        if t != MODULE_TYPE_CHIPSET {
+            return Err(format!("unexpected ACM module type {t:04x}"));
+        }
+        let size = header.size();
+        let Some(module) = data.get(..size) else {
+            return Err(format!(
+                "ACM size {size:08x} exceeds available {:08x}",
+                data.len()
+            ));
+        };
+        let key_size = header.key_size();
+        let hv = header.header_version;
+        let (exp_size, exponent) = match hv {
+            HEADER_VERSION_0 => {
+                let e = rest
+                    .get(key_size..key_size + 4)
+                    .and_then(|e| u32::read_from_bytes(e).ok());
+                let Some(e) = e else {
+                    return Err("could not read RSA exponent".into());
+                };
+                (4, e)
+            }
+            _ => (0, DEFAULT_EXPONENT),
+        };
+        let sig_offset = HEADER_SIZE + key_size + exp_size;
+        let user_area = header.user_area_offset();
+        if sig_offset + key_size > header.header_len() || user_area > size {
+            return Err(format!(
+                "ACM header length {:08x} inconsistent with key size {key_size:08x}",
+                header.header_len()
+            ));
+        }
+        let modulus = module[HEADER_SIZE..HEADER_SIZE + key_size].to_vec();
+        let signature = module[sig_offset..sig_offset + key_size].to_vec();
+        // The signature covers the fixed part of the header and the user
+        // area; the key, signature and scratch area are excluded.
+        let signed_data = [&module[..HEADER_SIZE], &module[user_area..]].concat();
+
+        let info_table = InfoTable::read_from_prefix(&module[user_area..])
+            .ok()
+            .map(|(t, _)| t)
+            .filter(|t| t.uuid == INFO_TABLE_UUID);
+        let (chipset_ids, processor_ids) = match &info_table {
+            Some(t) => {
+                let c = parse_id_list(module, t.chipset_id_list as usize, "chipset ID")?;
+                let p = if t.version >= 4 && t.processor_id_list != 0 {
+                    parse_id_list(module, t.processor_id_list as usize, "processor ID")?
+                } else {
+                    vec![]
+                };
+                (c, p)
+            }
+            None => (vec![], vec![]),
+        };
+
+        Ok(Self {
+            header,
+            modulus,
+            exponent,
+            signature,
+            info_table,
+            chipset_ids,
+            processor_ids,
+            signed_data,
+        })
+    }
+
+    /// Get the signature scheme and hash algorithm as per the header version.
+    pub fn signature_scheme(&self) -> (u16, u16) {
+        match self.header.header_version {
+            HEADER_VERSION_0 => (ALG_RSASSA, ALG_SHA256),
+            _ => (ALG_RSAPSS, ALG_SHA384),
+        }
+    }
+
+    /// Hash of the public key, i.e., the modulus
+    pub fn key_hash(&self, alg: u16) -> Result<Vec<u8>, String> {
+        hash(alg, &[&self.modulus])
+    }
+
+    /// Verify the signature against the embedded public key.
+    pub fn verify(&self) -> Result<(), String> {
+        let em = rsa_apply(&self.modulus, self.exponent, &self.signature)?;
+        let (scheme, hash_alg) = self.signature_scheme();
+        let h = hash(hash_alg, &[&self.signed_data])?;
+        match scheme {
+            ALG_RSASSA => check_pkcs1_v15(&em, hash_alg, &h),
+            _ => check_pss(&em, hash_alg, &h, self.modulus.len() * 8),
+        }
+    }
+}
+
+impl Display for Acm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let h = self.header;
+        writeln!(f, "{h}")?;
+        let (s, a) = self.signature_scheme();
+        let ks = self.modulus.len() * 8;
+        let e = self.exponent;
+        write!(
+            f,
+            "  RSA-{ks}, exponent {e}, signature {} with {}",
+            alg_name(s),
+            alg_name(a)
+        )?;
+        if let Ok(kh) = self.key_hash(ALG_SHA256) {
+            write!(f, "\n  key hash: {}", hex(&kh))?;
+        }
+        if let Some(t) = self.info_table {
+            let at = match t.chipset_acm_type & 0x7 {
+                0 => "BIOS",
+                1 => "SINIT",
+                _ => "unknown",
+            };
+            let v = t.version;
+            write!(f, "\n  info table version {v}, {at} ACM")?;
+        }
+        for c in &self.chipset_ids {
+            write!(f, "\n  chipset {c}")?;
+        }
+        for p in &self.processor_ids {
+            write!(f, "\n  processor {p}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse all ACMs referenced by the FIT, along with their physical addresses.
+pub fn from_fit(fit: &Fit, data: &[u8]) -> Vec<(u64, Result<Acm, String>)> {
+    fit.entries
+        .iter()
+        .filter(|e| {
+            matches!(
+                e.get_type(),
+                Ok(EntryType::StartupACM | EntryType::DiagnosticACM)
+            )
+        })
+        .map(|e| {
+            let addr = e.addr;
+            let r = fit.offset_for(addr).and_then(|o| match data.get(o..) {
+                Some(d) => Acm::parse(d),
+                None => Err(format!("{addr:08x} ({o:08x}) out of bounds")),
+            });
+            (addr, r)
+        })
+        .collect()
+}
+
+// Build a version 0 ACM, signed with the test key.
+#[cfg(test)]
+fn raw_acm_v0() -> Vec<u8> {
+    use crate::bootguard::sig::{TEST_EXPONENT, TEST_MODULUS, test_key_sign};
+    use zerocopy::IntoBytes;
+
+    let key_size = TEST_MODULUS.len();
+    let header_len = HEADER_SIZE + key_size + 4 + key_size;
+    let scratch_size = 0x40;
+    let user_area = header_len + scratch_size;
+    let size = user_area + 0x100;
+    let header = AcmHeader {
+        module_type: MODULE_TYPE_CHIPSET,
+        module_subtype: 1,
+        header_len: (header_len / 4) as u32,
+        header_version: HEADER_VERSION_0,
+        chipset_id: 0xb00c,
+        flags: 0,
+        vendor: VENDOR_INTEL,
+        date: 0x2021_0903,
+        size: (size / 4) as u32,
+        txt_svn: 2,
+        se_svn: 0,
+        code_control: 0,
+        error_entry_point: 0,
+        gdt_limit: 0,
+        gdt_base: 0,
+        seg_sel: 0,
+        entry_point: 0,
+        _reserved: [0; 64],
+        key_size: (key_size / 4) as u32,
+        scratch_size: (scratch_size / 4) as u32,
+    };
+    let info = InfoTable {
+        uuid: INFO_TABLE_UUID,
+        chipset_acm_type: 0,
+        version: 4,
+        length: core::mem::size_of::<InfoTable>() as u16,
+        chipset_id_list: (user_area + 0x40) as u32,
+        os_sinit_data_version: 0,
+        min_mle_header_version: 0,
+        capabilities: 0,
+        acm_version: 0,
+        acm_revision: [0; 3],
+        processor_id_list: (user_area + 0x80) as u32,
+    };
+    let chipset = ChipsetId {
+        flags: 0,
+        vendor_id: 0x8086,
+        device_id: 0xa143,
+        revision_id: 0x31,
+        _reserved: 0,
+        extended_id: 0,
+    };
+    let cpu = ProcessorId {
+        fms: 0x000906e0,
+        fms_mask: 0x0fff3ff0,
+        platform_id: 0,
+        platform_mask: 0,
+    };
+
+    let mut raw = vec![0u8; size];
+    raw[..HEADER_SIZE].copy_from_slice(header.as_bytes());
+    raw[HEADER_SIZE..HEADER_SIZE + key_size].copy_from_slice(&TEST_MODULUS);
+    raw[HEADER_SIZE + key_size..HEADER_SIZE + key_size + 4]
+        .copy_from_slice(&TEST_EXPONENT.to_le_bytes());
+    let i = info.as_bytes();
+    raw[user_area..user_area + i.len()].copy_from_slice(i);
+    let o = user_area + 0x40;
+    raw[o..o + 4].copy_from_slice(&1u32.to_le_bytes());
+    raw[o + 4..o + 20].copy_from_slice(chipset.as_bytes());
+    let o = user_area + 0x80;
+    raw[o..o + 4].copy_from_slice(&1u32.to_le_bytes());
+    raw[o + 4..o + 28].copy_from_slice(cpu.as_bytes());
+
+    let s = test_key_sign(&[&raw[..HEADER_SIZE], &raw[user_area..]]);
+    let o = HEADER_SIZE + key_size + 4;
+    raw[o..o + key_size].copy_from_slice(&s);
+    raw
+}
+
+#[test]
+fn parse_acm_v0() {
+    let raw = raw_acm_v0();
+    let acm = Acm::parse(&raw).unwrap();
+    assert_eq!(acm.header.date(), "2021-09-03");
+    assert_eq!(acm.exponent, 1);
+    assert!(acm.info_table.is_some());
+    assert_eq!(acm.chipset_ids.len(), 1);
+    assert_eq!(acm.processor_ids.len(), 1);
+    assert!(acm.verify().is_ok());
+}
+
+#[test]
+fn verify_acm_modified() {
+    let mut raw = raw_acm_v0();
+    let l = raw.len();
+    raw[l - 1] ^= 0xff;
+    let acm = Acm::parse(&raw).unwrap();
+    assert!(acm.verify().is_err());
+}
@@ -0,0 +1,226 @@
+//! Security posture audit of a full image
+//!
+//! The audit reports risky configuration rather than corruption: flash
+//! regions writable by masters that should not write them, debug features
+//! enabled in the soft straps, debug unlock tokens, missing Boot Guard
+//! manifests and (CS)ME manifests flagged as debug or pre-production. Keys
+//! not in the list of known production keys are noted as well; the list is
+//! incomplete, so that alone is not a risk. Findings are ranked by severity,
+//! the most severe first.
+
+use core::cmp::Reverse;
+
+use crate::check::Finding;
+use crate::dir::man::Manifest;
+use crate::fit::{Fit, FitError};
+use crate::ifd::{
+    IFD, RegionKind,
+    access::{Access, Master},
+};
+use crate::me::{Generation, ME};
+use crate::meta::get_meta_for_key;
+use crate::part::fpt::UTOK;
+use crate::{EMPTY, Firmware, bootguard::Manifests};
+
+// Manifest header flags, as decoded by ME Analyzer
+const MANIFEST_PRE_PRODUCTION: u32 = 1 << 30;
+const MANIFEST_DEBUG_SIGNED: u32 = 1 << 31;
+
+fn writes(ifd: &IFD, m: Master, r: RegionKind) -> bool {
+    let used = ifd.regions.get(r).is_some_and(|r| r.is_used());
+    used && matches!(ifd.access(m, r), Ok(Access { write: true, .. }))
+}
+
+/// Audit the flash master permissions and the soft straps.
+pub fn audit_ifd(ifd: &IFD, me_gen: Option<&Generation>) -> Vec<Finding> {
+    let mut res = vec![];
+    let (host, me) = (Master::Host, Master::Me);
+    let (desc, bios, me_region) = (RegionKind::Descriptor, RegionKind::Bios, RegionKind::Me);
+    if writes(ifd, host, desc) {
+        let m = "descriptor writable by host CPU/BIOS".to_string();
+        res.push(Finding::error("IFD", m));
+    }
+    if writes(ifd, me, desc) {
+        let m = "descriptor writable by (CS)ME".to_string();
+        res.push(Finding::warning("IFD", m));
+    }
+    if writes(ifd, host, me_region) {
+        let m = "ME region writable by host CPU/BIOS".to_string();
+        res.push(Finding::error("IFD", m));
+    }
+    if writes(ifd, me, bios) {
+        let m = "BIOS region writable by (CS)ME".to_string();
+        res.push(Finding::warning("IFD", m));
+    }
+    match me_gen {
+        Some(Generation::Gen1) => {
+            let d = ifd.ich_me_disabled();
+            let m = format!("ICH MeDisable bit {}", if d { "set" } else { "not set" });
+            res.push(Finding::info("straps", m));
+        }
+        Some(Generation::Gen2) => {
+            let d = ifd.alt_me_disabled();
+            let m = format!("AltMeDisable bit {}", if d { "set" } else { "not set" });
+            res.push(Finding::info("straps", m));
+        }
+        Some(Generation::Gen3) => {
+            let d = ifd.hap();
+            let m = format!("HAP bit {}", if d { "set" } else { "not set" });
+            res.push(Finding::info("straps", m));
+            // DCI only exists on the 100 series and later.
+            if ifd.dci() {
+                let m = "DCI enabled, allowing for debugging via USB".to_string();
+                res.push(Finding::error("straps", m));
+            }
+        }
+        _ => {}
+    }
+    res
+}
+
+fn audit_manifest(name: &str, m: &Manifest) -> Vec<Finding> {
+    let mut res = vec![];
+    let flags = m.header.flags;
+    if flags & MANIFEST_DEBUG_SIGNED != 0 {
+        res.push(Finding::error(name, "manifest debug signed".into()));
+    }
+    if flags & MANIFEST_PRE_PRODUCTION != 0 {
+        res.push(Finding::warning(name, "manifest pre-production".into()));
+    }
+    let h = m
+        .hash_key()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    if get_meta_for_key(&h).is_none() {
+        let msg = format!("key {h} not in known production key list");
+        res.push(Finding::info(name, msg));
+    }
+    res
+}
+
+/// Audit the (CS)ME firmware for debug tokens, debug or pre-production
+/// manifests and unknown signing keys.
+pub fn audit_me(me: &ME, data: &[u8]) -> Vec<Finding> {
+    let mut res = vec![];
+    let utok = me
+        .fpt_area
+        .fpt
+        .entries
+        .iter()
+        .find(|e| e.name() == UTOK && e.size() > 0);
+    if let Some(e) = utok {
+        let o = me.base + e.offset();
+        let r = o..o + e.size();
+        match data.get(r.clone()) {
+            Some(d) if d.iter().all(|b| *b == EMPTY || *b == 0) => {
+                let m = format!("debug token partition {r:08x?} present, but empty");
+                res.push(Finding::info(UTOK, m));
+            }
+            _ => {
+                let m = format!("debug unlock token partition {r:08x?} present");
+                res.push(Finding::warning(UTOK, m));
+            }
+        }
+    }
+    for (n, m) in me.manifests() {
+        res.extend(audit_manifest(&n, m));
+    }
+    res
+}
+
+/// Audit the FIT for Boot Guard manifests.
+pub fn audit_fit(fit: &Result<Fit, FitError>, data: &[u8]) -> Vec<Finding> {
+    let fit = match fit {
+        Ok(f) => f,
+        Err(_) => {
+            let m = "no FIT, Boot Guard not in use".to_string();
+            return vec![Finding::info("Boot Guard", m)];
+        }
+    };
+    let mut res = vec![];
+    let Manifests { km, bpm } = Manifests::from_fit(fit, data);
+    for (n, m) in [
+        ("Key Manifest", km.is_some()),
+        ("Boot Policy Manifest", bpm.is_some()),
+    ] {
+        if !m {
+            let m = format!("FIT lacks a {n}, Boot Guard not configured");
+            res.push(Finding::warning("Boot Guard", m));
+        }
+    }
+    if let Some(Err(e)) = km {
+        res.push(Finding::warning("Boot Guard", format!("Key Manifest: {e}")));
+    }
+    if let Some(Err(e)) = bpm {
+        let m = format!("Boot Policy Manifest: {e}");
+        res.push(Finding::warning("Boot Guard", m));
+    }
+    res
+}
+
+impl Firmware {
+    /// Audit the security posture of the firmware, given the data it was
+    /// parsed from. The findings are sorted by severity, most severe first.
+    pub fn audit(&self, data: &[u8]) -> Vec<Finding> {
+        let mut res = vec![];
+        let me = match &self.me {
+            Some(Ok(me)) => Some(me),
+            _ => None,
+        };
+        match &self.ifd {
+            Ok(ifd) => res.extend(audit_ifd(ifd, me.map(|m| &m.generation))),
+            Err(e) => {
+                let m = format!("not a full image, cannot audit permissions: {e:?}");
+                res.push(Finding::warning("IFD", m));
+            }
+        }
+        match me {
+            Some(me) => res.extend(audit_me(me, data)),
+            None => res.push(Finding::info("ME", "no ME firmware recognized".into())),
+        }
+        res.extend(audit_fit(&self.fit, data));
+        // A stable sort keeps the order within each severity.
+        res.sort_by_key(|f| Reverse(f.severity));
+        res
+    }
+}
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../tests/me11.ifd");
+
+#[test]
+fn audit_permissions() {
+    let mut ifd = IFD::parse(IFD_DATA).unwrap();
+    ifd.unlock().unwrap();
+    let f = audit_ifd(&ifd, Some(&Generation::Gen3));
+    assert!(
+        f.iter()
+            .any(|f| f.message.starts_with("descriptor writable by host"))
+    );
+    assert!(
+        f.iter()
+            .any(|f| f.message.starts_with("ME region writable by host"))
+    );
+    ifd.lock().unwrap();
+    let f = audit_ifd(&ifd, Some(&Generation::Gen3));
+    assert!(!crate::check::has_errors(&f));
+    ifd.set_dci(true);
+    let f = audit_ifd(&ifd, Some(&Generation::Gen3));
+    assert!(f.iter().any(|f| f.message.starts_with("DCI enabled")));
+}
+
+#[test]
+fn audit_sorted() {
+    let mut ifd = IFD::parse(IFD_DATA).unwrap();
+    ifd.unlock().unwrap();
+    let fw = Firmware {
+        ifd: Ok(ifd),
+        me: None,
+        fit: Fit::new(&[]),
+        gbe: None,
+    };
+    let f = fw.audit(&[]);
+    assert!(f.windows(2).all(|w| w[0].severity >= w[1].severity));
+    assert_eq!(f[0].severity, crate::check::Severity::Error);
+}
@@ -0,0 +1,155 @@
+//! Intel Boot Guard
+//!
+//! Boot Guard verifies the Initial Boot Block (IBB) of the host firmware
+//! before the CPU executes it. The root of trust is a hash of the OEM key
+//! fused into the PCH, which authenticates the Key Manifest (KM). The KM in
+//! turn holds a hash of the key that signs the Boot Policy Manifest (BPM),
+//! which carries the digest of the IBB and platform policies.
+//! Both manifests are found through their respective FIT entries.
+//!
+//! There are two major formats: Boot Guard 1.0 (Skylake through Coffee Lake),
+//! whose structures have fixed sizes, and Boot Guard 2.x as part of
+//! Converged Boot Guard and TXT (CBnT), whose elements carry their own sizes.
+//! For references regarding data structures, see
+//! <https://github.com/linuxboot/fiano/tree/main/pkg/intel/metadata>
+//! and coreboot `util/cbfstool/` and `src/soc/intel/common/`.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+use zerocopy::FromBytes;
+
+use crate::fit::{EntryType, Fit};
+
+pub mod bpm;
+pub mod km;
+pub mod sig;
+pub mod verify;
+
+use bpm::BootPolicyManifest;
+use km::KeyManifest;
+
+/// Structure versions below this are Boot Guard 1.0.
+const FORMAT_V2_MIN_VERSION: u8 = 0x20;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ManifestFormat {
+    V1,
+    V2,
+}
+
+impl ManifestFormat {
+    fn from_struct_version(v: u8) -> Self {
+        if v < FORMAT_V2_MIN_VERSION {
+            Self::V1
+        } else {
+            Self::V2
+        }
+    }
+}
+
+impl Display for ManifestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = match self {
+            Self::V1 => "1.0",
+            Self::V2 => "2.x",
+        };
+        write!(f, "Boot Guard {v}")
+    }
+}
+
+/// Minimal cursor over little-endian data with bounds checks
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn read<T: FromBytes>(&mut self, what: &str) -> Result<T, String> {
+        let p = self.pos;
+        let Some(slice) = self.data.get(p..) else {
+            return Err(format!("{what} @ {p:08x} out of bounds"));
+        };
+        match T::read_from_prefix(slice) {
+            Ok((r, _)) => {
+                self.pos += core::mem::size_of::<T>();
+                Ok(r)
+            }
+            Err(_) => Err(format!("cannot read {what} @ {p:08x}")),
+        }
+    }
+
+    pub(crate) fn bytes(&mut self, size: usize, what: &str) -> Result<&'a [u8], String> {
+        let p = self.pos;
+        let Some(slice) = self.data.get(p..p + size) else {
+            return Err(format!("{what} @ {p:08x} ({size} bytes) out of bounds"));
+        };
+        self.pos += size;
+        Ok(slice)
+    }
+
+    pub(crate) fn skip(&mut self, size: usize, what: &str) -> Result<(), String> {
+        self.bytes(size, what).map(|_| ())
+    }
+}
+
+/// Boot Guard manifests as referenced by the FIT
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Manifests {
+    pub km: Option<Result<KeyManifest, String>>,
+    pub bpm: Option<Result<BootPolicyManifest, String>>,
+}
+
+// Get the data of the first FIT entry of a given type.
+pub(crate) fn fit_entry_data<'a>(
+    fit: &Fit,
+    data: &'a [u8],
+    t: EntryType,
+) -> Option<Result<&'a [u8], String>> {
+    let e = fit
+        .entries
+        .iter()
+        .find(|e| matches!(e.get_type(), Ok(et) if et == t))?;
+    let addr = e.addr;
+    let o = match fit.offset_for(addr) {
+        Ok(o) => o,
+        Err(e) => return Some(Err(e)),
+    };
+    match data.get(o..) {
+        Some(d) if !d.is_empty() => Some(Ok(d)),
+        _ => Some(Err(format!("{addr:08x} ({o:08x}) out of bounds"))),
+    }
+}
+
+impl Manifests {
+    /// Follow the KM and BPM FIT entries and parse the manifests.
+    pub fn from_fit(fit: &Fit, data: &[u8]) -> Self {
+        let km = fit_entry_data(fit, data, EntryType::KeyManifestRecord)
+            .map(|r| r.and_then(KeyManifest::parse));
+        let bpm = fit_entry_data(fit, data, EntryType::BootPolicyManifest)
+            .map(|r| r.and_then(BootPolicyManifest::parse));
+        Self { km, bpm }
+    }
+}
+
+impl Display for Manifests {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.km {
+            Some(Ok(km)) => writeln!(f, "{km}")?,
+            Some(Err(e)) => writeln!(f, "Key Manifest could not be parsed: {e}")?,
+            None => writeln!(f, "No Key Manifest in FIT")?,
+        }
+        match &self.bpm {
+            Some(Ok(bpm)) => write!(f, "{bpm}"),
+            Some(Err(e)) => write!(f, "Boot Policy Manifest could not be parsed: {e}"),
+            None => write!(f, "No Boot Policy Manifest in FIT"),
+        }
+    }
+}
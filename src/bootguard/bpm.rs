@@ -0,0 +1,478 @@
+//! Boot Guard Boot Policy Manifest (BPM)
+//!
+//! The BPM consists of a header followed by a list of elements, each starting
+//! with an 8-byte ID. The IBB element (IBBS) lists the flash segments that
+//! make up the Initial Boot Block and their expected digest. The BPM is
+//! terminated by its signature element (PMSG).
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+use zerocopy_derive::{FromBytes, Immutable, IntoBytes};
+
+use crate::bootguard::{
+    ManifestFormat, Reader,
+    sig::{Digest, KeySignature, hex},
+};
+
+pub const BPM_MAGIC: &str = "__ACBP__";
+pub const BPM_MAGIC_BYTES: &[u8] = BPM_MAGIC.as_bytes();
+
+pub const IBBS_ID: &str = "__IBBS__";
+pub const TXTS_ID: &str = "__TXTS__";
+pub const PMDA_ID: &str = "__PMDA__";
+pub const PMSG_ID: &str = "__PMSG__";
+
+const ID_SIZE: usize = 8;
+// Element ID, structure version, variable byte and element size
+const V2_STRUCT_INFO_SIZE: usize = ID_SIZE + 4;
+// A BPM typically has a handful of elements.
+const MAX_ELEMENTS: usize = 32;
+// Platform manufacturer data is small; anything bigger is bogus.
+const MAX_PMDA_SIZE: usize = 0x1_0000;
+
+/// Segment flag: the segment is not part of the IBB digest
+pub const SEGMENT_NOT_HASHED: u16 = 1 << 0;
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct IbbSegment {
+    _reserved: u16,
+    pub flags: u16,
+    /// Physical address, as mapped below 4GB
+    pub base: u32,
+    pub size: u32,
+}
+
+impl IbbSegment {
+    pub fn is_hashed(&self) -> bool {
+        self.flags & SEGMENT_NOT_HASHED == 0
+    }
+}
+
+impl Display for IbbSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = self.base;
+        let s = self.size;
+        let e = b as u64 + s as u64;
+        let h = if self.is_hashed() {
+            ""
+        } else {
+            " (not hashed)"
+        };
+        write!(f, "{b:08x}:{e:08x} ({s:08x}){h}")
+    }
+}
+
+fn parse_segments(r: &mut Reader) -> Result<Vec<IbbSegment>, String> {
+    let count = r.read::<u8>("segment count")?;
+    (0..count)
+        .map(|_| r.read::<IbbSegment>("segment"))
+        .collect()
+}
+
+/// Initial Boot Block element
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ibbs {
+    pub struct_version: u8,
+    /// Boot Guard 2.x only
+    pub set_number: u8,
+    pub flags: u32,
+    pub mchbar: u64,
+    pub vtd_bar: u64,
+    pub dma_prot_base0: u32,
+    pub dma_prot_limit0: u32,
+    pub dma_prot_base1: u64,
+    pub dma_prot_limit1: u64,
+    pub post_ibb_hash: Digest,
+    pub entry_point: u32,
+    /// One digest in Boot Guard 1.0, one per algorithm in 2.x
+    pub digests: Vec<Digest>,
+    /// Boot Guard 2.x only
+    pub obb_hash: Option<Digest>,
+    pub segments: Vec<IbbSegment>,
+}
+
+impl Ibbs {
+    fn parse(r: &mut Reader, format: ManifestFormat, struct_version: u8) -> Result<Self, String> {
+        let set_number = match format {
+            ManifestFormat::V1 => {
+                r.skip(3, "IBBS reserved")?;
+                0
+            }
+            ManifestFormat::V2 => {
+                r.skip(1, "IBBS reserved")?;
+                let n = r.read::<u8>("IBBS set number")?;
+                // reserved and PBET value
+                r.skip(2, "IBBS reserved")?;
+                n
+            }
+        };
+        let flags = r.read::<u32>("IBBS flags")?;
+        let mchbar = r.read::<u64>("IBBS MCHBAR")?;
+        let vtd_bar = r.read::<u64>("IBBS VT-d BAR")?;
+        let dma_prot_base0 = r.read::<u32>("IBBS DMA protection base 0")?;
+        let dma_prot_limit0 = r.read::<u32>("IBBS DMA protection limit 0")?;
+        let dma_prot_base1 = r.read::<u64>("IBBS DMA protection base 1")?;
+        let dma_prot_limit1 = r.read::<u64>("IBBS DMA protection limit 1")?;
+        let (post_ibb_hash, entry_point, digests, obb_hash) = match format {
+            ManifestFormat::V1 => {
+                let p = Digest::parse_fixed(r)?;
+                let e = r.read::<u32>("IBB entry point")?;
+                let d = Digest::parse_fixed(r)?;
+                (p, e, vec![d], None)
+            }
+            ManifestFormat::V2 => {
+                let p = Digest::parse(r)?;
+                let e = r.read::<u32>("IBB entry point")?;
+                let d = Digest::parse_list(r)?;
+                let o = Digest::parse(r)?;
+                r.skip(3, "IBBS reserved")?;
+                (p, e, d, Some(o))
+            }
+        };
+        let segments = parse_segments(r)?;
+        Ok(Self {
+            struct_version,
+            set_number,
+            flags,
+            mchbar,
+            vtd_bar,
+            dma_prot_base0,
+            dma_prot_limit0,
+            dma_prot_base1,
+            dma_prot_limit1,
+            post_ibb_hash,
+            entry_point,
+            digests,
+            obb_hash,
+            segments,
+        })
+    }
+}
+
+impl Display for Ibbs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fl = self.flags;
+        let ep = self.entry_point;
+        writeln!(f, "  IBB, flags {fl:08x}, entry point {ep:08x}")?;
+        writeln!(
+            f,
+            "    MCHBAR {:016x}, VT-d BAR {:016x}",
+            self.mchbar, self.vtd_bar
+        )?;
+        writeln!(f, "    post-IBB hash: {}", self.post_ibb_hash)?;
+        for d in &self.digests {
+            writeln!(f, "    IBB digest:    {d}")?;
+        }
+        if let Some(o) = &self.obb_hash {
+            writeln!(f, "    OBB hash:      {o}")?;
+        }
+        for s in &self.segments {
+            writeln!(f, "    segment {s}")?;
+        }
+        write!(f, "")
+    }
+}
+
+/// TXT element, Boot Guard 2.x only
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Txts {
+    pub struct_version: u8,
+    pub set_number: u8,
+    pub sinit_min_svn: u8,
+    pub control_flags: u32,
+    /// in units of 5 seconds
+    pub pwr_down_interval: u16,
+    pub ptt_cmos_offset0: u8,
+    pub ptt_cmos_offset1: u8,
+    pub acpi_base_offset: u16,
+    pub pwrm_base_offset: u32,
+    pub digests: Vec<Digest>,
+    pub segments: Vec<IbbSegment>,
+}
+
+impl Txts {
+    fn parse(r: &mut Reader, struct_version: u8) -> Result<Self, String> {
+        r.skip(1, "TXTS reserved")?;
+        let set_number = r.read::<u8>("TXTS set number")?;
+        let sinit_min_svn = r.read::<u8>("TXTS SINIT min SVN")?;
+        r.skip(1, "TXTS reserved")?;
+        let control_flags = r.read::<u32>("TXTS control flags")?;
+        let pwr_down_interval = r.read::<u16>("TXTS power down interval")?;
+        r.skip(1, "TXTS reserved")?;
+        let ptt_cmos_offset0 = r.read::<u8>("TXTS PTT CMOS offset 0")?;
+        let ptt_cmos_offset1 = r.read::<u8>("TXTS PTT CMOS offset 1")?;
+        let acpi_base_offset = r.read::<u16>("TXTS ACPI base offset")?;
+        r.skip(2, "TXTS reserved")?;
+        let pwrm_base_offset = r.read::<u32>("TXTS PWRM base offset")?;
+        let digests = Digest::parse_list(r)?;
+        r.skip(3, "TXTS reserved")?;
+        let segments = parse_segments(r)?;
+        Ok(Self {
+            struct_version,
+            set_number,
+            sinit_min_svn,
+            control_flags,
+            pwr_down_interval,
+            ptt_cmos_offset0,
+            ptt_cmos_offset1,
+            acpi_base_offset,
+            pwrm_base_offset,
+            digests,
+            segments,
+        })
+    }
+}
+
+impl Display for Txts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = self.control_flags;
+        let s = self.sinit_min_svn;
+        writeln!(f, "  TXT, control flags {c:08x}, SINIT minimum SVN {s}")?;
+        for d in &self.digests {
+            writeln!(f, "    digest: {d}")?;
+        }
+        for s in &self.segments {
+            writeln!(f, "    segment {s}")?;
+        }
+        write!(f, "")
+    }
+}
+
+/// Platform manufacturer data element
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Pmda {
+    pub struct_version: u8,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl Pmda {
+    fn parse(r: &mut Reader, format: ManifestFormat, struct_version: u8) -> Result<Self, String> {
+        if format == ManifestFormat::V2 {
+            r.skip(2, "PMDA reserved")?;
+        }
+        let size = r.read::<u16>("PMDA size")? as usize;
+        if size > MAX_PMDA_SIZE {
+            return Err(format!("PMDA size {size} too big"));
+        }
+        let data = r.bytes(size, "PMDA data")?.to_vec();
+        Ok(Self {
+            struct_version,
+            data,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Element {
+    Ibbs(Ibbs),
+    Txts(Txts),
+    Pmda(Pmda),
+    Pmsg(KeySignature),
+    /// Boot Guard 2.x elements we do not know, skipped based on their size
+    Unknown {
+        id: String,
+        size: usize,
+    },
+}
+
+impl Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ibbs(e) => write!(f, "{e}"),
+            Self::Txts(e) => write!(f, "{e}"),
+            Self::Pmda(e) => write!(f, "  Platform manufacturer data: {}", hex(&e.data)),
+            Self::Pmsg(e) => write!(f, "  Signature: {e}"),
+            Self::Unknown { id, size } => write!(f, "  Unknown element {id}, {size} bytes"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BootPolicyManifest {
+    pub format: ManifestFormat,
+    pub struct_version: u8,
+    pub header_struct_version: u8,
+    pub revision: u8,
+    pub svn: u8,
+    pub acm_svn: u8,
+    /// Size of the No-Eviction Mode data stack, in 4K pages
+    pub nem_data_stack: u16,
+    pub elements: Vec<Element>,
+    /// Offset of the signature structure, i.e., the size of the signed data
+    pub signature_offset: usize,
+    pub size: usize,
+}
+
+impl BootPolicyManifest {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut r = Reader::new(data);
+        let magic = r.bytes(BPM_MAGIC_BYTES.len(), "BPM magic")?;
+        if magic != BPM_MAGIC_BYTES {
+            return Err(format!(
+                "BPM magic not found: wanted {BPM_MAGIC_BYTES:02x?} ({BPM_MAGIC}), got {magic:02x?}"
+            ));
+        }
+        let struct_version = r.read::<u8>("BPM structure version")?;
+        let format = ManifestFormat::from_struct_version(struct_version);
+        let header_struct_version = r.read::<u8>("BPM header structure version")?;
+        let header_signature_offset = match format {
+            ManifestFormat::V1 => None,
+            ManifestFormat::V2 => {
+                let _header_size = r.read::<u16>("BPM header size")?;
+                Some(r.read::<u16>("BPM signature offset")? as usize)
+            }
+        };
+        let revision = r.read::<u8>("BPM revision")?;
+        let svn = r.read::<u8>("BPM SVN")?;
+        let acm_svn = r.read::<u8>("ACM SVN")?;
+        r.skip(1, "BPM reserved")?;
+        let nem_data_stack = r.read::<u16>("NEM data stack size")?;
+
+        let mut elements = vec![];
+        let mut signature_offset = None;
+        let signature_offset = loop {
+            if elements.len() >= MAX_ELEMENTS {
+                return Err("too many BPM elements".into());
+            }
+            let start = r.pos();
+            let id = r.bytes(ID_SIZE, "BPM element ID")?;
+            let id = String::from_utf8_lossy(id).to_string();
+            let ver = r.read::<u8>("BPM element version")?;
+            let element_size = match format {
+                ManifestFormat::V1 => None,
+                ManifestFormat::V2 => {
+                    r.skip(1, "BPM element reserved")?;
+                    Some(r.read::<u16>("BPM element size")? as usize)
+                }
+            };
+            let e = match id.as_str() {
+                IBBS_ID => Element::Ibbs(Ibbs::parse(&mut r, format, ver)?),
+                TXTS_ID if format == ManifestFormat::V2 => Element::Txts(Txts::parse(&mut r, ver)?),
+                PMDA_ID => Element::Pmda(Pmda::parse(&mut r, format, ver)?),
+                PMSG_ID => {
+                    signature_offset = Some(r.pos());
+                    Element::Pmsg(KeySignature::parse(&mut r)?)
+                }
+                _ => match element_size {
+                    Some(size) if size > V2_STRUCT_INFO_SIZE => {
+                        r.skip(size - V2_STRUCT_INFO_SIZE, "BPM element")?;
+                        Element::Unknown { id, size }
+                    }
+                    _ => return Err(format!("unknown BPM element {id} @ {start:08x}")),
+                },
+            };
+            elements.push(e);
+            // The signature terminates the BPM.
+            if let Some(o) = signature_offset {
+                break o;
+            }
+        };
+        if let Some(o) = header_signature_offset
+            && o != signature_offset
+        {
+            return Err(format!(
+                "BPM signature offset {o:04x} does not match signature @ {signature_offset:04x}"
+            ));
+        }
+        Ok(Self {
+            format,
+            struct_version,
+            header_struct_version,
+            revision,
+            svn,
+            acm_svn,
+            nem_data_stack,
+            elements,
+            signature_offset,
+            size: r.pos(),
+        })
+    }
+
+    pub fn ibbs(&self) -> Vec<&Ibbs> {
+        self.elements
+            .iter()
+            .filter_map(|e| match e {
+                Element::Ibbs(i) => Some(i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn signature(&self) -> Option<&KeySignature> {
+        self.elements.iter().find_map(|e| match e {
+            Element::Pmsg(s) => Some(s),
+            _ => None,
+        })
+    }
+}
+
+impl Display for BootPolicyManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fo = self.format;
+        let v = self.struct_version;
+        let r = self.revision;
+        let s = self.svn;
+        let a = self.acm_svn;
+        let n = self.nem_data_stack;
+        let sz = self.size;
+        writeln!(f, "== Boot Policy Manifest ({fo}) ==")?;
+        writeln!(
+            f,
+            "  structure version {v:02x}, revision {r}, SVN {s}, {sz} bytes"
+        )?;
+        writeln!(f, "  ACM SVN {a}, NEM data stack {n} pages")?;
+        for e in &self.elements {
+            writeln!(f, "{e}")?;
+        }
+        write!(f, "")
+    }
+}
+
+/// Build a raw Boot Guard 1.0 BPM with an IBBS element for one segment.
+#[cfg(test)]
+pub(crate) fn raw_bpm_v1(segment: (u32, u32), digest: &[u8; 32], modulus: &[u8]) -> Vec<u8> {
+    use crate::bootguard::sig::{ALG_SHA256, raw_key_signature};
+    let hash = |d: &[u8; 32]| [&ALG_SHA256.to_le_bytes()[..], &32u16.to_le_bytes(), d].concat();
+    let (base, size) = segment;
+    [
+        BPM_MAGIC_BYTES,
+        &[0x10, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01],
+        IBBS_ID.as_bytes(),
+        &[0x10, 0, 0, 0],
+        &[0; 4 + 8 + 8 + 4 + 4 + 8 + 8],
+        &hash(&[0; 32]),
+        &0xffff_fff0u32.to_le_bytes(),
+        &hash(digest),
+        &[1, 0, 0, 0, 0],
+        &base.to_le_bytes(),
+        &size.to_le_bytes(),
+        PMSG_ID.as_bytes(),
+        &[0x10],
+        &raw_key_signature(modulus, 0x10001, &[0x55; 256]),
+    ]
+    .concat()
+}
+
+#[test]
+fn parse_bpm_v1() {
+    let raw = raw_bpm_v1((0xffff_0000, 0x1_0000), &[0x42; 32], &[0xaa; 256]);
+    let bpm = BootPolicyManifest::parse(&raw).unwrap();
+    assert_eq!(bpm.format, ManifestFormat::V1);
+    assert_eq!(bpm.size, raw.len());
+    assert_eq!(bpm.elements.len(), 2);
+    let ibbs = bpm.ibbs();
+    assert_eq!(ibbs.len(), 1);
+    assert_eq!(ibbs[0].digests[0].digest, [0x42; 32]);
+    assert_eq!(ibbs[0].segments.len(), 1);
+    assert!(bpm.signature().is_some());
+}
+
+#[test]
+fn parse_bpm_v1_unknown_element() {
+    let mut raw = raw_bpm_v1((0xffff_0000, 0x1_0000), &[0x42; 32], &[0xaa; 256]);
+    // Corrupt the IBBS element ID; without sizes, it cannot be skipped.
+    raw[16] = b'X';
+    assert!(BootPolicyManifest::parse(&raw).is_err());
+}
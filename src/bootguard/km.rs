@@ -0,0 +1,186 @@
+//! Boot Guard Key Manifest (KM)
+//!
+//! The KM is signed with the OEM key whose hash is fused into the platform.
+//! It authorizes further keys by their hashes, most notably the key that
+//! signs the Boot Policy Manifest.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+use crate::bootguard::{
+    ManifestFormat, Reader,
+    sig::{Digest, KeySignature, alg_name},
+};
+
+pub const KM_MAGIC: &str = "__KEYM__";
+pub const KM_MAGIC_BYTES: &[u8] = KM_MAGIC.as_bytes();
+
+/// Key usage bit for the Boot Policy Manifest key
+pub const USAGE_BPM: u64 = 1 << 0;
+
+// A KM typically authorizes a handful of keys.
+const MAX_KEY_HASHES: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyHash {
+    /// Bit mask of what the key may be used for
+    pub usage: u64,
+    pub digest: Digest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyManifest {
+    pub format: ManifestFormat,
+    pub struct_version: u8,
+    pub revision: u8,
+    pub svn: u8,
+    pub id: u8,
+    /// Boot Guard 2.x only; the algorithm used for the OEM key hash
+    pub pub_key_hash_alg: Option<u16>,
+    /// Hashes of the keys authorized by this KM
+    pub key_hashes: Vec<KeyHash>,
+    pub signature: KeySignature,
+    /// Offset of the signature structure, i.e., the size of the signed data
+    pub signature_offset: usize,
+    pub size: usize,
+}
+
+impl KeyManifest {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut r = Reader::new(data);
+        let magic = r.bytes(KM_MAGIC_BYTES.len(), "KM magic")?;
+        if magic != KM_MAGIC_BYTES {
+            return Err(format!(
+                "KM magic not found: wanted {KM_MAGIC_BYTES:02x?} ({KM_MAGIC}), got {magic:02x?}"
+            ));
+        }
+        let struct_version = r.read::<u8>("KM structure version")?;
+        let format = ManifestFormat::from_struct_version(struct_version);
+        match format {
+            ManifestFormat::V1 => {
+                let revision = r.read::<u8>("KM version")?;
+                let svn = r.read::<u8>("KM SVN")?;
+                let id = r.read::<u8>("KM ID")?;
+                let digest = Digest::parse_fixed(&mut r)?;
+                let key_hashes = vec![KeyHash {
+                    usage: USAGE_BPM,
+                    digest,
+                }];
+                let signature_offset = r.pos();
+                let signature = KeySignature::parse(&mut r)?;
+                Ok(Self {
+                    format,
+                    struct_version,
+                    revision,
+                    svn,
+                    id,
+                    pub_key_hash_alg: None,
+                    key_hashes,
+                    signature,
+                    signature_offset,
+                    size: r.pos(),
+                })
+            }
+            ManifestFormat::V2 => {
+                r.skip(3, "KM reserved")?;
+                let signature_offset = r.read::<u16>("KM signature offset")? as usize;
+                r.skip(3, "KM reserved")?;
+                let revision = r.read::<u8>("KM revision")?;
+                let svn = r.read::<u8>("KM SVN")?;
+                let id = r.read::<u8>("KM ID")?;
+                let pub_key_hash_alg = Some(r.read::<u16>("KM public key hash algorithm")?);
+                let count = r.read::<u16>("KM key count")? as usize;
+                if count > MAX_KEY_HASHES {
+                    return Err(format!("KM key count {count} too big"));
+                }
+                let mut key_hashes = vec![];
+                for _ in 0..count {
+                    let usage = r.read::<u64>("KM key usage")?;
+                    let digest = Digest::parse(&mut r)?;
+                    key_hashes.push(KeyHash { usage, digest });
+                }
+                let p = r.pos();
+                if p != signature_offset {
+                    return Err(format!(
+                        "KM signature offset {signature_offset:04x} does not match end of hashes {p:04x}"
+                    ));
+                }
+                let signature = KeySignature::parse(&mut r)?;
+                Ok(Self {
+                    format,
+                    struct_version,
+                    revision,
+                    svn,
+                    id,
+                    pub_key_hash_alg,
+                    key_hashes,
+                    signature,
+                    signature_offset,
+                    size: r.pos(),
+                })
+            }
+        }
+    }
+
+    /// Get the hash of the key authorized to sign the Boot Policy Manifest.
+    pub fn bpm_key_hash(&self) -> Option<&Digest> {
+        self.key_hashes
+            .iter()
+            .find(|h| h.usage & USAGE_BPM != 0)
+            .map(|h| &h.digest)
+    }
+}
+
+impl Display for KeyManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fo = self.format;
+        let v = self.struct_version;
+        let r = self.revision;
+        let s = self.svn;
+        let i = self.id;
+        let sz = self.size;
+        writeln!(f, "== Key Manifest ({fo}) ==")?;
+        writeln!(
+            f,
+            "  structure version {v:02x}, revision {r}, SVN {s}, ID {i}, {sz} bytes"
+        )?;
+        if let Some(a) = self.pub_key_hash_alg {
+            writeln!(f, "  OEM key hash algorithm: {}", alg_name(a))?;
+        }
+        for h in &self.key_hashes {
+            writeln!(f, "  key hash, usage {:016x}: {}", h.usage, h.digest)?;
+        }
+        write!(f, "  {}", self.signature)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn raw_km_v1(bpm_key_hash: &[u8; 32]) -> Vec<u8> {
+    use crate::bootguard::sig::{ALG_SHA256, raw_key_signature};
+    [
+        KM_MAGIC_BYTES,
+        &[0x10, 0x01, 0x02, 0x03],
+        &ALG_SHA256.to_le_bytes(),
+        &32u16.to_le_bytes(),
+        bpm_key_hash,
+        &raw_key_signature(&[0xaa; 256], 0x10001, &[0x55; 256]),
+    ]
+    .concat()
+}
+
+#[test]
+fn parse_km_v1() {
+    let raw = raw_km_v1(&[0x42; 32]);
+    let km = KeyManifest::parse(&raw).unwrap();
+    assert_eq!(km.format, ManifestFormat::V1);
+    assert_eq!(km.svn, 2);
+    assert_eq!(km.size, raw.len());
+    assert_eq!(km.signature_offset, 48);
+    assert_eq!(km.bpm_key_hash().unwrap().digest, [0x42; 32]);
+}
+
+#[test]
+fn parse_km_no_magic() {
+    let raw = vec![0xff; 0x400];
+    assert!(KeyManifest::parse(&raw).is_err());
+}
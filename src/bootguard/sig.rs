@@ -0,0 +1,468 @@
+//! Hashes, keys and signatures as used in Boot Guard manifests
+//!
+//! Algorithm identifiers are those from the TPM 2.0 specification
+//! (`TPM_ALG_ID`), shared by both manifest formats.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+use crate::bootguard::Reader;
+
+pub const ALG_RSA: u16 = 0x0001;
+pub const ALG_SHA1: u16 = 0x0004;
+pub const ALG_SHA256: u16 = 0x000b;
+pub const ALG_SHA384: u16 = 0x000c;
+pub const ALG_SHA512: u16 = 0x000d;
+pub const ALG_NULL: u16 = 0x0010;
+pub const ALG_SM3: u16 = 0x0012;
+pub const ALG_RSASSA: u16 = 0x0014;
+pub const ALG_RSAPSS: u16 = 0x0016;
+pub const ALG_ECDSA: u16 = 0x0018;
+pub const ALG_SM2: u16 = 0x001b;
+pub const ALG_ECC: u16 = 0x0023;
+
+// Boot Guard 1.0 only knows SHA-256 and reserves exactly that much space.
+const FIXED_DIGEST_SIZE: usize = 32;
+// SHA-512 is the largest digest in use.
+const MAX_DIGEST_SIZE: usize = 64;
+// Sanity limit for key sizes, in bits
+const MAX_KEY_SIZE: usize = 4096;
+
+pub fn alg_name(alg: u16) -> &'static str {
+    match alg {
+        ALG_RSA => "RSA",
+        ALG_SHA1 => "SHA-1",
+        ALG_SHA256 => "SHA-256",
+        ALG_SHA384 => "SHA-384",
+        ALG_SHA512 => "SHA-512",
+        ALG_NULL => "none",
+        ALG_SM3 => "SM3",
+        ALG_RSASSA => "RSASSA-PKCS1-v1_5",
+        ALG_RSAPSS => "RSASSA-PSS",
+        ALG_ECDSA => "ECDSA",
+        ALG_SM2 => "SM2",
+        ALG_ECC => "ECC",
+        _ => "unknown",
+    }
+}
+
+pub(crate) fn hex(d: &[u8]) -> String {
+    d.iter().map(|b| format!("{b:02x}")).collect::<String>()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Digest {
+    pub alg: u16,
+    #[serde(with = "serde_bytes")]
+    pub digest: Vec<u8>,
+}
+
+impl Digest {
+    /// Boot Guard 1.0: algorithm, size and a fixed-size buffer
+    pub(crate) fn parse_fixed(r: &mut Reader) -> Result<Self, String> {
+        let alg = r.read::<u16>("hash algorithm")?;
+        let size = r.read::<u16>("hash size")? as usize;
+        let buf = r.bytes(FIXED_DIGEST_SIZE, "hash")?;
+        let digest = buf[..size.min(FIXED_DIGEST_SIZE)].to_vec();
+        Ok(Self { alg, digest })
+    }
+
+    /// Boot Guard 2.x: algorithm, size and a buffer of that size
+    pub(crate) fn parse(r: &mut Reader) -> Result<Self, String> {
+        let alg = r.read::<u16>("hash algorithm")?;
+        let size = r.read::<u16>("hash size")? as usize;
+        if size > MAX_DIGEST_SIZE {
+            return Err(format!("hash size {size} too big"));
+        }
+        let digest = r.bytes(size, "hash")?.to_vec();
+        Ok(Self { alg, digest })
+    }
+
+    /// Boot Guard 2.x: list of hashes, prefixed with total size and count
+    pub(crate) fn parse_list(r: &mut Reader) -> Result<Vec<Self>, String> {
+        let _size = r.read::<u16>("hash list size")?;
+        let count = r.read::<u16>("hash list count")? as usize;
+        // There is one hash per algorithm at most.
+        if count > 8 {
+            return Err(format!("hash list count {count} too big"));
+        }
+        (0..count).map(|_| Self::parse(r)).collect()
+    }
+
+    /// Tell whether the digest is all zero, i.e., unset.
+    pub fn is_zero(&self) -> bool {
+        self.digest.iter().all(|b| *b == 0)
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let a = alg_name(self.alg);
+        write!(f, "{a} {}", hex(&self.digest))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum PublicKey {
+    Rsa {
+        exponent: u32,
+        /// Little-endian, as stored in the manifest
+        #[serde(with = "serde_bytes")]
+        modulus: Vec<u8>,
+    },
+    Ecc {
+        #[serde(with = "serde_bytes")]
+        x: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        y: Vec<u8>,
+    },
+}
+
+/// A public key and a signature made with the corresponding private key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeySignature {
+    pub version: u8,
+    pub key_alg: u16,
+    pub key_version: u8,
+    /// in bits
+    pub key_size: u16,
+    pub key: PublicKey,
+    pub sig_scheme: u16,
+    pub sig_version: u8,
+    pub hash_alg: u16,
+    /// Little-endian, as stored in the manifest; for ECC, R followed by S
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+// Get the size in bytes for a size in bits, with sanity checks.
+fn key_bytes(bits: u16) -> Result<usize, String> {
+    let b = bits as usize;
+    if b == 0 || b > MAX_KEY_SIZE || !b.is_multiple_of(8) {
+        return Err(format!("invalid key size {b}"));
+    }
+    Ok(b / 8)
+}
+
+impl KeySignature {
+    pub(crate) fn parse(r: &mut Reader) -> Result<Self, String> {
+        let version = r.read::<u8>("key signature version")?;
+        let key_alg = r.read::<u16>("key algorithm")?;
+        let key_version = r.read::<u8>("key version")?;
+        let key_size = r.read::<u16>("key size")?;
+        let s = key_bytes(key_size)?;
+        let key = match key_alg {
+            ALG_RSA => {
+                let exponent = r.read::<u32>("RSA exponent")?;
+                let modulus = r.bytes(s, "RSA modulus")?.to_vec();
+                PublicKey::Rsa { exponent, modulus }
+            }
+            ALG_ECC => {
+                let x = r.bytes(s, "ECC X")?.to_vec();
+                let y = r.bytes(s, "ECC Y")?.to_vec();
+                PublicKey::Ecc { x, y }
+            }
+            _ => return Err(format!("unsupported key algorithm {key_alg:04x}")),
+        };
+        let sig_scheme = r.read::<u16>("signature scheme")?;
+        let sig_version = r.read::<u8>("signature version")?;
+        let sig_size = r.read::<u16>("signature key size")?;
+        let s = key_bytes(sig_size)?;
+        let hash_alg = r.read::<u16>("signature hash algorithm")?;
+        let signature = match key_alg {
+            ALG_ECC => r.bytes(2 * s, "ECC signature")?.to_vec(),
+            _ => r.bytes(s, "RSA signature")?.to_vec(),
+        };
+        Ok(Self {
+            version,
+            key_alg,
+            key_version,
+            key_size,
+            key,
+            sig_scheme,
+            sig_version,
+            hash_alg,
+            signature,
+        })
+    }
+}
+
+impl Display for KeySignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ka = alg_name(self.key_alg);
+        let ks = self.key_size;
+        let ss = alg_name(self.sig_scheme);
+        let ha = alg_name(self.hash_alg);
+        let k = match &self.key {
+            PublicKey::Rsa { exponent, .. } => format!("{ka}-{ks}, exponent {exponent}"),
+            PublicKey::Ecc { .. } => format!("{ka}-{ks}"),
+        };
+        write!(f, "{k}, signature {ss} with {ha}")
+    }
+}
+
+/// Compute a digest over the given parts with the given algorithm.
+pub fn hash(alg: u16, parts: &[&[u8]]) -> Result<Vec<u8>, String> {
+    use sha2::{Sha256, Sha384, Sha512};
+
+    fn h<D: sha2::Digest>(parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = D::new();
+        for p in parts {
+            hasher.update(p);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    match alg {
+        ALG_SHA256 => Ok(h::<Sha256>(parts)),
+        ALG_SHA384 => Ok(h::<Sha384>(parts)),
+        ALG_SHA512 => Ok(h::<Sha512>(parts)),
+        _ => Err(format!("unsupported hash algorithm {}", alg_name(alg))),
+    }
+}
+
+// ASN.1 DER encoded DigestInfo prefixes, see RFC 8017 section 9.2
+pub(crate) const DIGEST_INFO_SHA256: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+const DIGEST_INFO_SHA384: &[u8] = &[
+    0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02, 0x05,
+    0x00, 0x04, 0x30,
+];
+const DIGEST_INFO_SHA512: &[u8] = &[
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05,
+    0x00, 0x04, 0x40,
+];
+
+/// Apply the RSA public key to a signature, yielding the encoded message.
+///
+/// Modulus and signature are little-endian, as stored in the structures.
+/// The result is big-endian and has the size of the modulus.
+pub fn rsa_apply(modulus: &[u8], exponent: u32, signature: &[u8]) -> Result<Vec<u8>, String> {
+    use num_bigint::BigUint;
+
+    let n = BigUint::from_bytes_le(modulus);
+    let s = BigUint::from_bytes_le(signature);
+    if s >= n {
+        return Err("signature out of range for modulus".into());
+    }
+    let e = BigUint::from(exponent);
+    let m = s.modpow(&e, &n).to_bytes_be();
+    let k = modulus.len();
+    let mut res = vec![0u8; k - m.len()];
+    res.extend_from_slice(&m);
+    Ok(res)
+}
+
+/// Check an encoded message for RSASSA-PKCS1-v1_5 padding around the hash.
+pub fn check_pkcs1_v15(em: &[u8], hash_alg: u16, hash: &[u8]) -> Result<(), String> {
+    let prefix = match hash_alg {
+        ALG_SHA256 => DIGEST_INFO_SHA256,
+        ALG_SHA384 => DIGEST_INFO_SHA384,
+        ALG_SHA512 => DIGEST_INFO_SHA512,
+        _ => return Err(format!("unsupported hash algorithm {}", alg_name(hash_alg))),
+    };
+    let t = [prefix, hash].concat();
+    // 0x00 0x01, at least 8 bytes of 0xff padding, 0x00, then T
+    if em.len() < t.len() + 11 {
+        return Err("encoded message too short".into());
+    }
+    let ps_end = em.len() - t.len() - 1;
+    if em[0] != 0x00 || em[1] != 0x01 || em[ps_end] != 0x00 {
+        return Err("invalid PKCS#1 v1.5 padding".into());
+    }
+    if em[2..ps_end].iter().any(|b| *b != 0xff) {
+        return Err("invalid PKCS#1 v1.5 padding".into());
+    }
+    if em[ps_end + 1..] != t {
+        return Err("hash mismatch".into());
+    }
+    Ok(())
+}
+
+fn mgf1(hash_alg: u16, seed: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let mut res = vec![];
+    let mut c = 0u32;
+    while res.len() < len {
+        res.extend(hash(hash_alg, &[seed, &c.to_be_bytes()])?);
+        c += 1;
+    }
+    res.truncate(len);
+    Ok(res)
+}
+
+/// Check an encoded message for RSASSA-PSS encoding of the hash, see
+/// RFC 8017 section 9.1.2. Any salt length is accepted.
+///
+/// `mod_bits` is the size of the RSA modulus in bits.
+pub fn check_pss(
+    em: &[u8],
+    hash_alg: u16,
+    hash_value: &[u8],
+    mod_bits: usize,
+) -> Result<(), String> {
+    let h_len = hash_value.len();
+    let em_bits = mod_bits - 1;
+    let em_len = em_bits.div_ceil(8);
+    // The encoded message may be one byte shorter than the modulus.
+    let em = match em.len() - em_len {
+        0 => em,
+        1 if em[0] == 0 => &em[1..],
+        _ => return Err("invalid PSS encoded message size".into()),
+    };
+    if em_len < h_len + 2 {
+        return Err("encoded message too short".into());
+    }
+    if em[em_len - 1] != 0xbc {
+        return Err("invalid PSS trailer".into());
+    }
+    let db_len = em_len - h_len - 1;
+    let (masked_db, h) = (&em[..db_len], &em[db_len..em_len - 1]);
+    let top_bits = 8 * em_len - em_bits;
+    let top_mask = 0xffu8 >> top_bits;
+    if masked_db[0] & !top_mask != 0 {
+        return Err("invalid PSS encoding".into());
+    }
+    let mask = mgf1(hash_alg, h, db_len)?;
+    let mut db: Vec<u8> = masked_db.iter().zip(mask).map(|(a, b)| a ^ b).collect();
+    db[0] &= top_mask;
+    let Some(p) = db.iter().position(|b| *b != 0) else {
+        return Err("invalid PSS padding".into());
+    };
+    if db[p] != 0x01 {
+        return Err("invalid PSS padding".into());
+    }
+    let salt = &db[p + 1..];
+    let hh = hash(hash_alg, &[&[0u8; 8], hash_value, salt])?;
+    if hh != h {
+        return Err("hash mismatch".into());
+    }
+    Ok(())
+}
+
+impl KeySignature {
+    /// Hash of the public key, as referenced by a Key Manifest or fuses.
+    ///
+    /// For RSA, this is over the modulus, and optionally the exponent.
+    pub fn key_hash(&self, alg: u16, with_exponent: bool) -> Result<Vec<u8>, String> {
+        match &self.key {
+            PublicKey::Rsa { exponent, modulus } if with_exponent => {
+                hash(alg, &[modulus, &exponent.to_le_bytes()])
+            }
+            PublicKey::Rsa { modulus, .. } => hash(alg, &[modulus]),
+            PublicKey::Ecc { x, y } => hash(alg, &[x, y]),
+        }
+    }
+
+    /// Verify the signature over the given data.
+    pub fn verify(&self, data: &[u8]) -> Result<(), String> {
+        let PublicKey::Rsa { exponent, modulus } = &self.key else {
+            return Err(format!(
+                "unsupported key algorithm {}",
+                alg_name(self.key_alg)
+            ));
+        };
+        let em = rsa_apply(modulus, *exponent, &self.signature)?;
+        let h = hash(self.hash_alg, &[data])?;
+        match self.sig_scheme {
+            ALG_RSASSA => check_pkcs1_v15(&em, self.hash_alg, &h),
+            ALG_RSAPSS => check_pss(&em, self.hash_alg, &h, modulus.len() * 8),
+            s => Err(format!("unsupported signature scheme {}", alg_name(s))),
+        }
+    }
+}
+
+/// Build a raw RSA-2048 key signature structure with the given parts.
+#[cfg(test)]
+pub(crate) fn raw_key_signature(modulus: &[u8], exponent: u32, sig: &[u8]) -> Vec<u8> {
+    let size = (modulus.len() as u16 * 8).to_le_bytes();
+    [
+        &[0x10][..],
+        &ALG_RSA.to_le_bytes(),
+        &[0x10],
+        &size,
+        &exponent.to_le_bytes(),
+        modulus,
+        &ALG_RSASSA.to_le_bytes(),
+        &[0x10],
+        &size,
+        &ALG_SHA256.to_le_bytes(),
+        sig,
+    ]
+    .concat()
+}
+
+// With an exponent of 1 and a modulus of all 1's, the signature is just the
+// encoded message, so test data can be signed without a private key.
+#[cfg(test)]
+pub(crate) const TEST_MODULUS: [u8; 256] = [0xff; 256];
+#[cfg(test)]
+pub(crate) const TEST_EXPONENT: u32 = 1;
+
+/// Sign data with the test key as per RSASSA-PKCS1-v1_5 with SHA-256, giving
+/// the signature in little endian, as stored in the firmware.
+#[cfg(test)]
+pub(crate) fn test_key_sign(parts: &[&[u8]]) -> Vec<u8> {
+    let k = TEST_MODULUS.len();
+    let h = hash(ALG_SHA256, parts).unwrap();
+    let t = [DIGEST_INFO_SHA256, &h].concat();
+    let mut em = vec![0xff; k];
+    em[0] = 0x00;
+    em[1] = 0x01;
+    em[k - t.len() - 1] = 0x00;
+    em[k - t.len()..].copy_from_slice(&t);
+    em.reverse();
+    em
+}
+
+#[test]
+fn parse_key_signature() {
+    let raw = raw_key_signature(&[0xaa; 256], 0x10001, &[0x55; 256]);
+    let mut r = Reader::new(&raw);
+    let ks = KeySignature::parse(&mut r).unwrap();
+    assert_eq!(r.pos(), raw.len());
+    assert_eq!(ks.key_size, 2048);
+    assert!(matches!(
+        ks.key,
+        PublicKey::Rsa {
+            exponent: 0x10001,
+            ..
+        }
+    ));
+}
+
+// Encode a hash as per EMSA-PSS with a fixed salt and a 2048-bit modulus.
+#[cfg(test)]
+fn pss_encode(hash_alg: u16, hash_value: &[u8], salt: &[u8]) -> Vec<u8> {
+    let em_len = 256;
+    let h = hash(hash_alg, &[&[0u8; 8], hash_value, salt]).unwrap();
+    let db_len = em_len - h.len() - 1;
+    let mut db = vec![0u8; db_len - salt.len() - 1];
+    db.push(0x01);
+    db.extend_from_slice(salt);
+    let mask = mgf1(hash_alg, &h, db_len).unwrap();
+    let mut em: Vec<u8> = db.iter().zip(mask).map(|(a, b)| a ^ b).collect();
+    em[0] &= 0x7f;
+    em.extend(h);
+    em.push(0xbc);
+    em
+}
+
+#[test]
+fn check_pss_ok() {
+    let h = hash(ALG_SHA384, &[b"data"]).unwrap();
+    let em = pss_encode(ALG_SHA384, &h, &[0x42; 48]);
+    assert!(check_pss(&em, ALG_SHA384, &h, 2048).is_ok());
+    let other = hash(ALG_SHA384, &[b"other"]).unwrap();
+    assert!(check_pss(&em, ALG_SHA384, &other, 2048).is_err());
+}
+
+#[test]
+fn parse_key_signature_invalid_size() {
+    let mut raw = raw_key_signature(&[0xaa; 256], 0x10001, &[0x55; 256]);
+    // key size in bits, not a multiple of 8
+    raw[4] = 0x01;
+    let mut r = Reader::new(&raw);
+    assert!(KeySignature::parse(&mut r).is_err());
+}
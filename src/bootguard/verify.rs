@@ -0,0 +1,262 @@
+//! Offline Boot Guard verification
+//!
+//! This mimics what the startup ACM checks in verified boot mode: the KM
+//! signature, the BPM key against the hash in the KM, the BPM signature, and
+//! finally the IBB digest over the segments listed in the BPM. The one thing
+//! we cannot check is the OEM key hash fused into the PCH, so we only report
+//! the hash of the KM key, to be compared with the fuses or a known value.
+
+use crate::bootguard::{
+    bpm::{BootPolicyManifest, Ibbs},
+    fit_entry_data,
+    km::KeyManifest,
+    sig::{ALG_NULL, ALG_SHA256, alg_name, hash, hex},
+};
+use crate::check::Finding;
+use crate::fit::{EntryType, Fit};
+
+/// Compute the IBB digest over all hashed segments with the given algorithm.
+///
+/// The segments are given as physical addresses, which are mapped to offsets
+/// in the image the same way as FIT pointers.
+pub fn ibb_digest(ibbs: &Ibbs, fit: &Fit, data: &[u8], alg: u16) -> Result<Vec<u8>, String> {
+    let mut parts = vec![];
+    for s in ibbs.segments.iter().filter(|s| s.is_hashed()) {
+        let b = s.base;
+        let o = fit.offset_for(b as u64)?;
+        let end = o + s.size as usize;
+        let Some(d) = data.get(o..end) else {
+            return Err(format!("segment {s} ({o:08x}..{end:08x}) out of bounds"));
+        };
+        parts.push(d);
+    }
+    if parts.is_empty() {
+        return Err("no segments to hash".into());
+    }
+    hash(alg, &parts)
+}
+
+fn verify_km(km_data: &[u8], res: &mut Vec<Finding>) -> Option<KeyManifest> {
+    let km = match KeyManifest::parse(km_data) {
+        Ok(km) => km,
+        Err(e) => {
+            res.push(Finding::error("KM", e));
+            return None;
+        }
+    };
+    match km.signature.verify(&km_data[..km.signature_offset]) {
+        Ok(()) => res.push(Finding::info("KM", "signature is valid".into())),
+        Err(e) => res.push(Finding::error("KM", format!("signature error: {e}"))),
+    }
+    match km.signature.key_hash(ALG_SHA256, true) {
+        Ok(h) => {
+            let m = format!("key hash (compare with OEM key fuses): {}", hex(&h));
+            res.push(Finding::info("KM", m));
+        }
+        Err(e) => res.push(Finding::warning("KM", e)),
+    }
+    Some(km)
+}
+
+fn verify_bpm_key(km: &KeyManifest, bpm: &BootPolicyManifest, res: &mut Vec<Finding>) {
+    let Some(kh) = km.bpm_key_hash() else {
+        res.push(Finding::error(
+            "BPM",
+            "KM does not authorize a BPM key".into(),
+        ));
+        return;
+    };
+    let Some(sig) = bpm.signature() else {
+        res.push(Finding::error("BPM", "no signature found".into()));
+        return;
+    };
+    // Implementations differ in whether the exponent is part of the hash.
+    let matches = [false, true]
+        .iter()
+        .any(|e| matches!(sig.key_hash(kh.alg, *e), Ok(h) if h == kh.digest));
+    if matches {
+        res.push(Finding::info("BPM", "key matches hash in KM".into()));
+    } else {
+        res.push(Finding::error(
+            "BPM",
+            "key does not match hash in KM".into(),
+        ));
+    }
+}
+
+fn verify_bpm(bpm_data: &[u8], res: &mut Vec<Finding>) -> Option<BootPolicyManifest> {
+    let bpm = match BootPolicyManifest::parse(bpm_data) {
+        Ok(bpm) => bpm,
+        Err(e) => {
+            res.push(Finding::error("BPM", e));
+            return None;
+        }
+    };
+    let signed = &bpm_data[..bpm.signature_offset];
+    match bpm.signature().map(|s| s.verify(signed)) {
+        Some(Ok(())) => res.push(Finding::info("BPM", "signature is valid".into())),
+        Some(Err(e)) => res.push(Finding::error("BPM", format!("signature error: {e}"))),
+        None => res.push(Finding::error("BPM", "no signature found".into())),
+    }
+    Some(bpm)
+}
+
+fn verify_ibb(bpm: &BootPolicyManifest, fit: &Fit, data: &[u8], res: &mut Vec<Finding>) {
+    let ibbs = bpm.ibbs();
+    if ibbs.is_empty() {
+        res.push(Finding::error("IBB", "BPM has no IBB element".into()));
+    }
+    for i in ibbs {
+        for d in i
+            .digests
+            .iter()
+            .filter(|d| d.alg != ALG_NULL && !d.is_zero())
+        {
+            let a = alg_name(d.alg);
+            match ibb_digest(i, fit, data, d.alg) {
+                Ok(h) if h == d.digest => {
+                    res.push(Finding::info("IBB", format!("{a} digest matches")));
+                }
+                Ok(h) => {
+                    let m = format!("{a} digest is {}, BPM says {}", hex(&h), hex(&d.digest));
+                    res.push(Finding::error("IBB", m));
+                }
+                Err(e) => res.push(Finding::warning("IBB", format!("{a}: {e}"))),
+            }
+        }
+    }
+}
+
+/// Verify the Boot Guard chain of trust as far as possible offline.
+pub fn verify(fit: &Fit, data: &[u8]) -> Vec<Finding> {
+    let mut res = vec![];
+    let km_data = fit_entry_data(fit, data, EntryType::KeyManifestRecord);
+    let bpm_data = fit_entry_data(fit, data, EntryType::BootPolicyManifest);
+    let (km_data, bpm_data) = match (km_data, bpm_data) {
+        (None, None) => {
+            res.push(Finding::info("Boot Guard", "no manifests in FIT".into()));
+            return res;
+        }
+        (Some(Ok(k)), Some(Ok(b))) => (k, b),
+        (k, b) => {
+            for (n, r) in [("KM", k), ("BPM", b)] {
+                match r {
+                    None => res.push(Finding::error(n, "missing in FIT".into())),
+                    Some(Err(e)) => res.push(Finding::error(n, e)),
+                    Some(Ok(_)) => {}
+                }
+            }
+            return res;
+        }
+    };
+    let km = verify_km(km_data, &mut res);
+    let bpm = verify_bpm(bpm_data, &mut res);
+    if let (Some(km), Some(bpm)) = (&km, &bpm) {
+        verify_bpm_key(km, bpm, &mut res);
+    }
+    if let Some(bpm) = &bpm {
+        verify_ibb(bpm, fit, data, &mut res);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test_data {
+    use crate::bootguard::{
+        bpm::{BootPolicyManifest, raw_bpm_v1},
+        km::{KeyManifest, raw_km_v1},
+        sig::{ALG_SHA256, TEST_EXPONENT, TEST_MODULUS, hash, test_key_sign},
+    };
+    use crate::fit::{Fit, FitEntry, FitHeader, Mapping};
+
+    pub const SIZE: usize = 0x1_0000;
+    pub const KM_OFFSET: usize = 0x1000;
+    pub const BPM_OFFSET: usize = 0x2000;
+    pub const IBB_OFFSET: usize = 0x8000;
+    pub const IBB_SIZE: usize = 0x1000;
+
+    // Put the test key into the key signature and sign everything before it.
+    fn sign(raw: &mut [u8], signature_offset: usize) {
+        let o = signature_offset + 6;
+        let k = TEST_MODULUS.len();
+        raw[o..o + 4].copy_from_slice(&TEST_EXPONENT.to_le_bytes());
+        raw[o + 4..o + 4 + k].copy_from_slice(&TEST_MODULUS);
+        let s = test_key_sign(&[&raw[..signature_offset]]);
+        let l = raw.len();
+        raw[l - k..].copy_from_slice(&s);
+    }
+
+    fn entry(addr: u64, t: u8) -> FitEntry {
+        FitEntry {
+            addr,
+            size: [0; 3],
+            _11: 0,
+            version: 0x0100,
+            checksum_valid_and_type: t,
+            checksum: 0,
+        }
+    }
+
+    pub fn image() -> (Fit, Vec<u8>) {
+        let mut data = vec![0u8; SIZE];
+        for (i, b) in data[IBB_OFFSET..IBB_OFFSET + IBB_SIZE]
+            .iter_mut()
+            .enumerate()
+        {
+            *b = i as u8;
+        }
+        let ibb = &data[IBB_OFFSET..IBB_OFFSET + IBB_SIZE];
+        let digest: [u8; 32] = hash(ALG_SHA256, &[ibb]).unwrap().try_into().unwrap();
+        let segment = (0xffff_0000 | IBB_OFFSET as u32, IBB_SIZE as u32);
+        let mut bpm = raw_bpm_v1(segment, &digest, &TEST_MODULUS);
+        let o = BootPolicyManifest::parse(&bpm).unwrap().signature_offset;
+        sign(&mut bpm, o);
+
+        let key_hash = hash(ALG_SHA256, &[&TEST_MODULUS])
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let mut km = raw_km_v1(&key_hash);
+        let o = KeyManifest::parse(&km).unwrap().signature_offset;
+        sign(&mut km, o);
+
+        data[KM_OFFSET..KM_OFFSET + km.len()].copy_from_slice(&km);
+        data[BPM_OFFSET..BPM_OFFSET + bpm.len()].copy_from_slice(&bpm);
+        let header = FitHeader {
+            magic: *b"_FIT_   ",
+            entries: 3,
+            version: 0x0100,
+            checksum_valid_and_type: 0,
+            checksum: 0,
+        };
+        let entries = vec![
+            entry(0xffff_0000 | KM_OFFSET as u64, 0x0b),
+            entry(0xffff_0000 | BPM_OFFSET as u64, 0x0c),
+        ];
+        let fit = Fit {
+            header,
+            entries,
+            mapping: Mapping::whole(SIZE),
+            offset: 0,
+        };
+        (fit, data)
+    }
+}
+
+#[test]
+fn verify_ok() {
+    use crate::check::has_errors;
+    let (fit, data) = test_data::image();
+    let findings = verify(&fit, &data);
+    assert!(!has_errors(&findings), "{findings:#?}");
+}
+
+#[test]
+fn verify_modified_ibb() {
+    use crate::check::has_errors;
+    let (fit, mut data) = test_data::image();
+    data[test_data::IBB_OFFSET] ^= 0xff;
+    let findings = verify(&fit, &data);
+    assert!(has_errors(&findings));
+    assert!(findings.iter().any(|f| f.subject == "IBB"));
+}
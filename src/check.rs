@@ -0,0 +1,392 @@
+//! Integrity checks for full images and ME regions
+//!
+//! Each check yields [`Finding`]s with a [`Severity`], so that callers can
+//! decide what to do with them, e.g., fail a CI job on any error.
+//! The checks are read-only and never modify the given firmware.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+use crate::fit::{ChecksumStatus, Fit, FitError};
+use crate::ifd::IFD;
+use crate::me::ME;
+use crate::part::{
+    fpt::{FPTEntry, FTPR, NFTP, PartitionKind},
+    gen2::Gen2Partition,
+    gen3::Gen3Partition,
+    partitions::Partitions,
+};
+use crate::ver::Version;
+use crate::{Firmware, acm, bootguard, microcode};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    /// What the finding is about, e.g., a partition name
+    pub subject: String,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn new(severity: Severity, subject: &str, message: String) -> Self {
+        Self {
+            severity,
+            subject: subject.to_string(),
+            message,
+        }
+    }
+
+    pub fn info(subject: &str, message: String) -> Self {
+        Self::new(Severity::Info, subject, message)
+    }
+
+    pub fn warning(subject: &str, message: String) -> Self {
+        Self::new(Severity::Warning, subject, message)
+    }
+
+    pub fn error(subject: &str, message: String) -> Self {
+        Self::new(Severity::Error, subject, message)
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = format!("[{}]", self.severity);
+        write!(f, "{s:9} {}: {}", self.subject, self.message)
+    }
+}
+
+/// Tell whether any of the findings is an error.
+pub fn has_errors(findings: &[Finding]) -> bool {
+    findings.iter().any(|f| f.severity == Severity::Error)
+}
+
+/// Check that the regions we rely on are within the image, and that the
+/// flash components add up to its size.
+pub fn check_ifd(ifd: &IFD, size: usize) -> Vec<Finding> {
+    let mut res = vec![];
+    let regions = [
+        ("IFD", ifd.regions.ifd_range()),
+        ("BIOS", ifd.regions.bios_range()),
+        ("ME", ifd.regions.me_range()),
+    ];
+    for (n, r) in regions {
+        // Unused regions have a base above their limit.
+        if r.start >= r.end {
+            continue;
+        }
+        if r.end > size {
+            let m = format!("region {r:08x?} exceeds image size {size:08x}");
+            res.push(Finding::error(n, m));
+        }
+    }
+    if let Err(e) = ifd.check_components(size) {
+        res.push(Finding::warning("IFD", format!("flash components: {e}")));
+    }
+    res
+}
+
+// Partitions that are not backed by flash, or are empty, have no range.
+fn has_range(e: &FPTEntry) -> bool {
+    let f = e.flags;
+    e.size() > 0 && f.kind() != PartitionKind::NVRAM
+}
+
+/// Check the FPT entries against the size of the ME region.
+fn check_fpt_bounds(me: &ME, region_size: usize) -> Vec<Finding> {
+    let mut res = vec![];
+    for e in me.fpt_area.fpt.entries.iter().filter(|e| has_range(e)) {
+        let o = e.offset();
+        let end = o + e.size();
+        if end > region_size {
+            let m = format!("{o:08x}..{end:08x} exceeds ME region size {region_size:08x}");
+            res.push(Finding::error(&e.name(), m));
+        }
+    }
+    res
+}
+
+/// Check for partially overlapping partitions.
+///
+/// Some partitions are known to contain others, e.g., FTUP covers NFTP, so
+/// ranges fully contained in another one are fine.
+fn check_overlaps(me: &ME) -> Vec<Finding> {
+    let mut res = vec![];
+    let entries = me
+        .fpt_area
+        .fpt
+        .get_sorted_entries()
+        .into_iter()
+        .filter(has_range)
+        .collect::<Vec<FPTEntry>>();
+    for (i, a) in entries.iter().enumerate() {
+        let a_end = a.offset() + a.size();
+        for b in &entries[i + 1..] {
+            let b_start = b.offset();
+            let b_end = b_start + b.size();
+            if b_start >= a_end {
+                break;
+            }
+            if b_end > a_end {
+                let (an, bn) = (a.name(), b.name());
+                let m = format!("overlaps {bn} @ {b_start:08x}..{a_end:08x}");
+                res.push(Finding::error(&an, m));
+            }
+        }
+    }
+    res
+}
+
+/// Check that all CPD entries are within their directories.
+fn check_cpd_bounds(me: &ME) -> Vec<Finding> {
+    let mut res = vec![];
+    let Partitions::Gen3(parts) = &me.fpt_area.partitions else {
+        return res;
+    };
+    for p in parts {
+        let Gen3Partition::Dir(d) = p else {
+            continue;
+        };
+        let size = d.cpd.size;
+        for e in &d.cpd.entries {
+            let o = e.flags_and_offset.offset() as usize;
+            let end = o + e.size as usize;
+            if end > size {
+                let n = format!("{}/{}", d.cpd.name, e.name());
+                let m = format!("{o:08x}..{end:08x} exceeds directory size {size:08x}");
+                res.push(Finding::error(&n, m));
+            }
+        }
+    }
+    res
+}
+
+// Get the manifest version of a code partition.
+fn dir_version(me: &ME, part_name: &str) -> Option<Version> {
+    match &me.fpt_area.partitions {
+        Partitions::Gen2(parts) => parts.iter().find_map(|p| match p {
+            Gen2Partition::Dir(d) if d.entry.name() == part_name => {
+                Some(d.dir.manifest.header.version)
+            }
+            _ => None,
+        }),
+        Partitions::Gen3(parts) => parts.iter().find_map(|p| match p {
+            Gen3Partition::Dir(d) if d.entry.name() == part_name => match &d.cpd.manifest {
+                Ok(m) => Some(m.header.version),
+                Err(_) => None,
+            },
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Check that the main and additional code partitions are of the same version.
+fn check_versions(me: &ME) -> Vec<Finding> {
+    let mut res = vec![];
+    if let (Some(fv), Some(nv)) = (dir_version(me, FTPR), dir_version(me, NFTP)) {
+        if fv == nv {
+            res.push(Finding::info(NFTP, format!("version {nv} matches {FTPR}")));
+        } else {
+            let m = format!("version {nv} does not match {FTPR} version {fv}");
+            res.push(Finding::error(NFTP, m));
+        }
+    }
+    res
+}
+
+/// Run all checks on the ME firmware.
+///
+/// `region_size` is the size of the ME region that the FPT must fit into.
+pub fn check_me(me: &ME, region_size: usize) -> Vec<Finding> {
+    let mut res = vec![];
+    let fpt = &me.fpt_area.fpt;
+    let cs = fpt.header_checksum();
+    if cs == fpt.header.checksum {
+        res.push(Finding::info("FPT", "checksum is correct".into()));
+    } else {
+        let m = format!(
+            "checksum is {:02x}, should be {cs:02x}",
+            fpt.header.checksum
+        );
+        res.push(Finding::error("FPT", m));
+    }
+    match me.fpt_area.check_ftpr_presence() {
+        Ok(()) => res.push(Finding::info(FTPR, "exists".into())),
+        Err(e) => res.push(Finding::error(FTPR, e)),
+    }
+    for (n, r) in me.fpt_area.check_dir_sigs() {
+        match r {
+            Ok(()) => res.push(Finding::info(&n, "signature is valid".into())),
+            Err(e) => res.push(Finding::error(&n, format!("signature error: {e}"))),
+        }
+    }
+    res.extend(check_fpt_bounds(me, region_size));
+    res.extend(check_overlaps(me));
+    res.extend(check_cpd_bounds(me));
+    res.extend(check_versions(me));
+    res
+}
+
+/// Check the FIT header and entry checksums, if they are claimed to be valid.
+pub fn check_fit(fit: &Fit, data: &[u8]) -> Vec<Finding> {
+    let mut res = vec![];
+    for (i, s) in fit.verify(data).into_iter().enumerate() {
+        let n = match i {
+            0 => "FIT".to_string(),
+            _ => format!("FIT entry {}", i - 1),
+        };
+        match s {
+            ChecksumStatus::NotApplicable => {}
+            ChecksumStatus::Valid => res.push(Finding::info(&n, s.to_string())),
+            _ => res.push(Finding::error(&n, s.to_string())),
+        }
+    }
+    res
+}
+
+/// Check the checksums of all microcode updates referenced by the FIT.
+pub fn check_microcode(fit: &Fit, data: &[u8]) -> Vec<Finding> {
+    let mut res = vec![];
+    for (addr, r) in microcode::from_fit(fit, data) {
+        let n = format!("microcode @ {addr:08x}");
+        match r {
+            Ok(m) => {
+                if !m.checksum_valid {
+                    res.push(Finding::error(&n, "checksum is invalid".into()));
+                }
+                if let Some(t) = m.ext_table {
+                    if !t.checksum_valid {
+                        let e = "extended signature table checksum is invalid";
+                        res.push(Finding::error(&n, e.into()));
+                    }
+                    let sigs = t.signatures.iter().zip(t.signature_checksums_valid);
+                    for (s, _) in sigs.filter(|(_, v)| !v) {
+                        let s = s.signature;
+                        let e = format!("extended signature {s:08x} checksum is invalid");
+                        res.push(Finding::error(&n, e));
+                    }
+                }
+            }
+            Err(e) => res.push(Finding::error(&n, e)),
+        }
+    }
+    res
+}
+
+/// Verify the signatures of all ACMs referenced by the FIT against their
+/// embedded keys.
+pub fn check_acms(fit: &Fit, data: &[u8]) -> Vec<Finding> {
+    let mut res = vec![];
+    for (addr, r) in acm::from_fit(fit, data) {
+        let n = format!("ACM @ {addr:08x}");
+        match r.and_then(|a| a.verify()) {
+            Ok(()) => res.push(Finding::info(&n, "signature is valid".into())),
+            Err(e) => res.push(Finding::error(&n, e)),
+        }
+    }
+    res
+}
+
+impl Firmware {
+    /// Run all integrity checks on the firmware, given the data it was parsed
+    /// from.
+    pub fn check(&self, data: &[u8]) -> Vec<Finding> {
+        let mut res = vec![];
+        let region_size = match &self.ifd {
+            Ok(ifd) => {
+                res.extend(check_ifd(ifd, data.len()));
+                ifd.regions.me_range().len()
+            }
+            Err(e) => {
+                res.push(Finding::info("IFD", format!("not a full image: {e:?}")));
+                match &self.me {
+                    Some(Ok(me)) => me.fpt_area.original_size,
+                    _ => data.len(),
+                }
+            }
+        };
+        match &self.me {
+            Some(Ok(me)) => res.extend(check_me(me, region_size)),
+            Some(Err(e)) => res.push(Finding::error("ME", e.clone())),
+            None => res.push(Finding::error("ME", "no ME firmware recognized".into())),
+        }
+        match &self.fit {
+            Ok(fit) => {
+                res.extend(check_fit(fit, data));
+                res.extend(check_microcode(fit, data));
+                res.extend(check_acms(fit, data));
+                res.extend(bootguard::verify::verify(fit, data));
+            }
+            // Older platforms and ME region dumps simply have no FIT.
+            Err(FitError::InvalidPointer(e)) => res.push(Finding::info("FIT", e.clone())),
+            // Without an IFD, whatever is at the FIT pointer location need
+            // not be a pointer at all.
+            Err(FitError::UnmappablePointer(e)) if self.ifd.is_err() => {
+                res.push(Finding::info("FIT", e.clone()))
+            }
+            Err(e) => res.push(Finding::error("FIT", format!("{e:?}"))),
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+static FPT_DATA: &[u8] = include_bytes!("../tests/me11.fpt");
+
+#[test]
+fn fpt_fixture_exceeds_region() {
+    let me = ME::parse(FPT_DATA, 0, false).unwrap().unwrap();
+    let findings = check_me(&me, FPT_DATA.len());
+    let find = |s: &str| findings.iter().find(|f| f.subject == s).unwrap();
+    let f = find("FPT");
+    assert_eq!(f.severity, Severity::Info);
+    assert_eq!(f.message, "checksum is correct");
+    // The fixture only has the FPT, not the partitions it lists.
+    let f = find("MFS");
+    assert_eq!(f.severity, Severity::Error);
+    assert_eq!(
+        f.message,
+        "000a8000..0010c000 exceeds ME region size 00000200"
+    );
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error);
+    assert_eq!(errors.count(), 8);
+    // FTUP and NFTP share the same range, which is not an overlap error.
+    assert!(check_overlaps(&me).is_empty());
+}
+
+#[test]
+fn fpt_fixture_within_region() {
+    let me = ME::parse(FPT_DATA, 0, false).unwrap().unwrap();
+    assert!(check_fpt_bounds(&me, 0x0020_0000).is_empty());
+}
+
+#[test]
+fn fpt_fixture_unmappable_fit_pointer() {
+    let mut data = FPT_DATA.to_vec();
+    let p = data.len() - 0x40;
+    data[p..p + 4].copy_from_slice(&0x1234_5670_u32.to_le_bytes());
+    let fw = Firmware::parse(&data, false);
+    assert!(matches!(fw.fit, Err(FitError::UnmappablePointer(_))));
+    let findings = fw.check(&data);
+    let f = findings.iter().find(|f| f.subject == "FIT").unwrap();
+    assert_eq!(f.severity, Severity::Info);
+}
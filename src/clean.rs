@@ -2,13 +2,17 @@ use intel_fw::{
     EMPTY,
     ifd::{IFD, IfdError},
     me::ME,
+    meta::Variant,
     part::generic::ClearOptions,
 };
-use log::warn;
+use log::{info, warn};
 
 pub struct Options {
     pub keep_modules: bool,
     pub relocate: bool,
+    pub descriptor: bool,
+    pub truncate: bool,
+    pub extend_bios: bool,
     pub parts_force_retention: Vec<String>,
     pub parts_force_deletion: Vec<String>,
     pub disable_me: bool,
@@ -25,7 +29,9 @@ pub fn clean(
         && let Ok(ifd) = ifd
     {
         let mut new_ifd = ifd.clone();
-        if let Err(e) = new_ifd.disable_me(&me.generation, &me.version) {
+        // Without a known signing key, assume the regular (CS)ME.
+        let variant = me.variant().unwrap_or(Variant::ME);
+        if let Err(e) = new_ifd.disable_me(&me.generation, variant, &me.version) {
             let msg = format!("Could not disable ME: {e}");
             if options.disable_me_only {
                 return Err(msg);
@@ -61,8 +67,51 @@ pub fn clean(
             let original_size = new_me.fpt_area.original_size;
             data[me.base + size..me.base + original_size].fill(EMPTY);
 
+            if options.descriptor {
+                match ifd {
+                    Ok(_) => restrict_me_access(data)?,
+                    Err(_) => warn!("Cannot remove ME access to other regions without descriptor"),
+                }
+            }
+            if options.truncate && ifd.is_ok() {
+                adjust_regions(data, size, options.extend_bios)?;
+            }
+
             Ok((data.to_vec(), Some(cleaned)))
         }
         Err(e) => Err(e),
     }
 }
+
+/// Shrink the ME region in the descriptor to the truncated ME size and,
+/// optionally, give the space freed up to the BIOS region.
+fn adjust_regions(data: &mut [u8], me_size: usize, extend_bios: bool) -> Result<(), String> {
+    // Parse again to retain changes made to the descriptor so far.
+    let mut ifd = IFD::parse(data).map_err(|e| format!("{e:?}"))?;
+    let old = ifd.regions.me_range();
+    let new = ifd
+        .shrink_me(me_size, extend_bios)
+        .map_err(|e| format!("Could not adjust regions: {e}"))?;
+    info!("ME region now {new:08x?}, was {old:08x?}");
+    if extend_bios {
+        info!("BIOS region now {:08x?}", ifd.regions.bios_range());
+    }
+    data[new.end..old.end].fill(EMPTY);
+    let new_ifd = ifd.to_vec();
+    let size = new_ifd.len();
+    data[..size].copy_from_slice(&new_ifd);
+    Ok(())
+}
+
+/// Remove the ME's access to other flash regions in the descriptor.
+fn restrict_me_access(data: &mut [u8]) -> Result<(), String> {
+    // Parse again to retain changes made to the descriptor so far.
+    let mut ifd = IFD::parse(data).map_err(|e| format!("{e:?}"))?;
+    ifd.restrict_me_access()
+        .map_err(|e| format!("Could not remove ME access to other regions: {e}"))?;
+    info!("Removed ME access to other flash regions");
+    let new_ifd = ifd.to_vec();
+    let size = new_ifd.len();
+    data[..size].copy_from_slice(&new_ifd);
+    Ok(())
+}
@@ -5,15 +5,23 @@
 //! <https://cdrdv2-public.intel.com/599500/599500_FW_Interface_Table_BIOS_Spec_Rev1p6.pdf>
 
 use core::fmt::{self, Display};
+use core::num::Wrapping;
+use core::ops::Range;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use zerocopy::{FromBytes, Ref};
+use zerocopy::{FromBytes, IntoBytes, Ref};
 use zerocopy_derive::{FromBytes, Immutable, IntoBytes};
 
+use crate::ifd::IFD;
+
+pub mod edit;
+pub mod policy;
+
 // firmware-interface-table-bios-specification-r1p2p1.pdf
 const FIT_MAGIC: &str = "_FIT_   ";
 const FIT_MAGIC_BYTES: &[u8] = FIT_MAGIC.as_bytes();
 
-#[derive(IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
 #[repr(C, packed)]
 pub struct FitHeader {
     pub magic: [u8; 8],
@@ -23,6 +31,12 @@ pub struct FitHeader {
     pub checksum: u8,
 }
 
+impl FitHeader {
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum_valid_and_type & CHECKSUM_VALID > 0
+    }
+}
+
 impl Display for FitHeader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Header counts as entry, but we want the actual number of entries
@@ -36,7 +50,7 @@ impl Display for FitHeader {
     }
 }
 
-#[derive(IntoBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(IntoBytes, Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u8)]
 pub enum EntryType {
     Header = 0x00,
@@ -72,12 +86,51 @@ pub struct FitEntry {
 pub struct Fit {
     pub header: FitHeader,
     pub entries: Vec<FitEntry>,
-    pub mapping: usize,
+    pub mapping: Mapping,
     pub offset: usize,
 }
 
 const FIT_HEADER_SIZE: usize = core::mem::size_of::<FitHeader>();
 
+// The C_V bit tells whether the checksum field is to be validated.
+const CHECKSUM_VALID: u8 = 0x80;
+
+// Component sizes are given in multiples of 16 bytes.
+const SIZE_UNIT: usize = 16;
+
+/// Result of validating the checksum of the FIT header or an entry
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum ChecksumStatus {
+    /// The C_V bit is not set, so there is nothing to check.
+    NotApplicable,
+    Valid,
+    Invalid {
+        expected: u8,
+        actual: u8,
+    },
+    /// The component the entry points to is not within the image.
+    OutOfBounds(String),
+}
+
+impl Display for ChecksumStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotApplicable => write!(f, "no checksum"),
+            Self::Valid => write!(f, "checksum is correct"),
+            Self::Invalid { expected, actual } => {
+                write!(f, "checksum is {actual:02x}, should be {expected:02x}")
+            }
+            Self::OutOfBounds(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+// Two's complement of the byte sum, so that the sum including it is 0.
+fn checksum8(data: &[u8]) -> u8 {
+    let sum = data.iter().map(|e| Wrapping(*e)).sum::<Wrapping<u8>>();
+    (-sum).0
+}
+
 // FIXME: This duplication is very tedious and prone to error.
 // It is too easy to forget to add something here that was added to the enum.
 impl TryFrom<u8> for EntryType {
@@ -110,22 +163,76 @@ impl TryFrom<u8> for EntryType {
     }
 }
 
-const MAP_8M: usize = 0x007f_ffff;
-const MAP_16M: usize = 0x00ff_ffff;
-const MAP_32M: usize = 0x01ff_ffff;
-
-const SIZE_8M: usize = 8 * 1024 * 1024;
-const SIZE_16M: usize = 16 * 1024 * 1024;
-const SIZE_32M: usize = 32 * 1024 * 1024;
-
-// The flash is mapped so that it ends at 0xffff_ffff, so we need to
-// map it to resolve pointers.
-fn get_mapping(size: usize) -> usize {
-    match size {
-        SIZE_8M => MAP_8M,
-        SIZE_16M => MAP_16M,
-        SIZE_32M => MAP_32M,
-        _ => MAP_16M,
+/// How the BIOS region is mapped into the physical address space
+///
+/// The BIOS region is mapped so that it ends at 4GB, i.e., 0xffff_ffff.
+/// Only pointers into that window of the region size can be resolved, so
+/// the region does not need to start at an aligned offset within the image.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Mapping {
+    /// Offset of the BIOS region within the image
+    pub base: usize,
+    /// Size of the BIOS region
+    pub size: usize,
+}
+
+const FOUR_GB: u64 = 1 << 32;
+
+impl Mapping {
+    /// Map all of the given image, i.e., a BIOS region dump.
+    pub fn whole(size: usize) -> Self {
+        Self { base: 0, size }
+    }
+
+    /// Map the BIOS region as found in the IFD of a full image.
+    pub fn from_bios_range(r: Range<usize>) -> Self {
+        Self {
+            base: r.start,
+            size: r.len(),
+        }
+    }
+
+    /// Derive the mapping from the IFD, if there is one, otherwise assume a
+    /// BIOS region only image.
+    pub fn for_image(data: &[u8]) -> Self {
+        match IFD::parse(data) {
+            Ok(ifd) => {
+                let r = ifd.regions.bios_range();
+                if r.start < r.end && r.end <= data.len() {
+                    return Self::from_bios_range(r);
+                }
+                warn!("BIOS region {r:08x?} invalid, mapping whole image");
+                Self::whole(data.len())
+            }
+            Err(_) => Self::whole(data.len()),
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.base..self.base + self.size
+    }
+
+    /// Resolve a physical address to an offset within the image.
+    pub fn offset_for(&self, addr: u64) -> Result<usize, String> {
+        if self.size == 0 {
+            return Err("no BIOS region to map".into());
+        }
+        if addr >= FOUR_GB {
+            return Err(format!("address {addr:016x} is above 4GB"));
+        }
+        // The BIOS region is mapped right below 4GB.
+        let start = FOUR_GB - self.size as u64;
+        if addr < start {
+            return Err(format!(
+                "address {addr:08x} is below the BIOS region mapped @ {start:08x}"
+            ));
+        }
+        Ok(self.base + (addr - start) as usize)
+    }
+
+    /// Get the physical address for an offset within the image.
+    pub fn address_for(&self, offset: usize) -> u64 {
+        FOUR_GB - self.size as u64 + offset as u64 - self.base as u64
     }
 }
 
@@ -141,13 +248,34 @@ pub enum FitError {
     HeaderReadError(String),
     HeaderNoMagic(String),
     EntryReadError(String),
+    UnmappablePointer(String),
 }
 
 impl Fit {
+    /// Parse the FIT, deriving the mapping from the IFD if there is one.
+    ///
+    /// Without an IFD, a FIT pointer below the size of the image is taken
+    /// as a plain offset, as in excerpts that do not carry a real mapping.
     pub fn new(data: &[u8]) -> Result<Self, FitError> {
-        let fitp_pos = data.len() - FIT_POINTER_BOTTOM_OFFSET;
+        let excerpt = IFD::parse(data).is_err();
+        Self::parse(data, Mapping::for_image(data), excerpt)
+    }
+
+    /// Parse the FIT with a given mapping of the BIOS region.
+    pub fn with_mapping(data: &[u8], mapping: Mapping) -> Result<Self, FitError> {
+        Self::parse(data, mapping, false)
+    }
+
+    fn parse(data: &[u8], mapping: Mapping, excerpt: bool) -> Result<Self, FitError> {
+        let r = mapping.range();
+        if r.end > data.len() || mapping.size < FIT_POINTER_BOTTOM_OFFSET {
+            return Err(FitError::PointerReadError(format!(
+                "BIOS region {r:08x?} invalid for image size {:08x}",
+                data.len()
+            )));
+        }
+        let fitp_pos = r.end - FIT_POINTER_BOTTOM_OFFSET;
         let fitp = &data[fitp_pos..fitp_pos + 4];
-        let mapping = get_mapping(data.len());
         let Ok((fp, _)) = u32::read_from_prefix(fitp) else {
             return Err(FitError::PointerReadError(format!(
                 "Cannot read FIT pointer @ {fitp_pos:08x}"
@@ -156,7 +284,11 @@ impl Fit {
         if fp == 0xffff_ffff {
             return Err(FitError::InvalidPointer(format!("Not a FIT: {fp:08x}")));
         }
-        let offset = mapping & fp as usize;
+        let offset = match mapping.offset_for(fp as u64) {
+            Ok(o) => o,
+            Err(_) if excerpt && (fp as usize) < data.len() => fp as usize,
+            Err(e) => return Err(FitError::UnmappablePointer(e)),
+        };
         // NOTE: FIT is usually aligned. The spec does not mandate it though.
         if !offset.is_multiple_of(0x10) {
             return Err(FitError::InvalidPointer(format!(
@@ -201,10 +333,119 @@ impl Fit {
     }
 }
 
+impl Fit {
+    /// Resolve a physical address, e.g., from a FIT entry, to an offset
+    /// within the image.
+    pub fn offset_for(&self, addr: u64) -> Result<usize, String> {
+        self.mapping.offset_for(addr)
+    }
+
+    /// Get the physical address for an offset within the image, i.e., the
+    /// inverse of [`Fit::offset_for`].
+    pub fn address_for(&self, offset: usize) -> u64 {
+        self.mapping.address_for(offset)
+    }
+
+    /// Two's complement of the sum of the bytes of the header and all entries
+    pub fn checksum(&self) -> u8 {
+        let mut h = self.header;
+        // Initial checksum field itself must be 0.
+        h.checksum = 0;
+        let d = [h.as_bytes(), self.entries.as_bytes()].concat();
+        checksum8(&d)
+    }
+
+    /// Get the data of the component that an entry points to.
+    pub fn component<'a>(&self, e: &FitEntry, data: &'a [u8]) -> Result<&'a [u8], String> {
+        let addr = e.addr;
+        let o = self.offset_for(addr)?;
+        let end = o + e.component_size();
+        match data.get(o..end) {
+            Some(d) => Ok(d),
+            None => Err(format!(
+                "component {addr:08x} ({o:08x}..{end:08x}) out of bounds"
+            )),
+        }
+    }
+
+    /// Two's complement of the sum of the bytes of the component that an
+    /// entry points to
+    pub fn entry_checksum(&self, e: &FitEntry, data: &[u8]) -> Result<u8, String> {
+        self.component(e, data).map(checksum8)
+    }
+
+    /// Validate the checksums of the header and all entries, given the image
+    /// that the FIT was parsed from.
+    ///
+    /// As in the table itself, the header is the first result, followed by
+    /// one result per entry.
+    pub fn verify(&self, data: &[u8]) -> Vec<ChecksumStatus> {
+        let h = self.header;
+        let hs = if h.is_checksum_valid() {
+            let expected = self.checksum();
+            let actual = h.checksum;
+            if expected == actual {
+                ChecksumStatus::Valid
+            } else {
+                ChecksumStatus::Invalid { expected, actual }
+            }
+        } else {
+            ChecksumStatus::NotApplicable
+        };
+        let es = self.entries.iter().map(|e| {
+            if !e.is_checksum_valid() {
+                return ChecksumStatus::NotApplicable;
+            }
+            match self.entry_checksum(e, data) {
+                Ok(expected) if expected == e.checksum => ChecksumStatus::Valid,
+                Ok(expected) => ChecksumStatus::Invalid {
+                    expected,
+                    actual: e.checksum,
+                },
+                Err(e) => ChecksumStatus::OutOfBounds(e),
+            }
+        });
+        [hs].into_iter().chain(es).collect()
+    }
+
+    /// Recompute the checksums of all entries that have the C_V bit set, and
+    /// then the header checksum, if its C_V bit is set.
+    ///
+    /// Components that are out of bounds are left as they are.
+    pub fn recompute_checksums(&mut self, data: &[u8]) {
+        let checksums = self
+            .entries
+            .iter()
+            .map(|e| match e.is_checksum_valid() {
+                true => self.entry_checksum(e, data).ok(),
+                false => None,
+            })
+            .collect::<Vec<Option<u8>>>();
+        for (e, c) in self.entries.iter_mut().zip(checksums) {
+            if let Some(c) = c {
+                e.checksum = c;
+            }
+        }
+        if self.header.is_checksum_valid() {
+            self.header.checksum = self.checksum();
+        }
+    }
+
+    /// Serialize the header and all entries, as they are to be written to
+    /// the image at the FIT offset.
+    pub fn to_vec(&self) -> Vec<u8> {
+        [self.header.as_bytes(), self.entries.as_bytes()].concat()
+    }
+}
+
 impl FitEntry {
+    /// Raw entry type, without the C_V bit
+    pub fn type_id(&self) -> u8 {
+        self.checksum_valid_and_type & !CHECKSUM_VALID
+    }
+
     pub fn get_type(&self) -> Result<EntryType, &str> {
-        let t = self.checksum_valid_and_type & 0xef;
-        EntryType::try_from(t)
+        EntryType::try_from(self.type_id())
     }
 
     pub fn get_type_name(&self) -> &str {
@@ -231,7 +472,13 @@ impl FitEntry {
     }
 
     pub fn is_checksum_valid(&self) -> bool {
-        self.checksum_valid_and_type & 0x80 > 0
+        self.checksum_valid_and_type & CHECKSUM_VALID > 0
+    }
+
+    /// Size of the component in bytes
+    pub fn component_size(&self) -> usize {
+        let s = self.size;
+        u32::from_le_bytes([s[0], s[1], s[2], 0]) as usize * SIZE_UNIT
     }
 }
 
@@ -266,8 +513,98 @@ fn parse_no_magic() {
     assert!(matches!(parsed, Err(FitError::HeaderNoMagic(_))));
 }
 
+#[test]
+fn parse_unmappable_ptr() {
+    let mut data = vec![0x00; 0x100];
+    // Neither within the image nor within the BIOS region mapped below 4GB
+    data[0xc0..0xc4].copy_from_slice(&0xfff0_0000_u32.to_le_bytes());
+    let parsed = Fit::new(&data);
+    assert!(matches!(parsed, Err(FitError::UnmappablePointer(_))));
+    // An explicit mapping never takes the pointer as a plain offset.
+    let parsed = Fit::with_mapping(DATA, Mapping::whole(DATA.len()));
+    assert!(matches!(parsed, Err(FitError::UnmappablePointer(_))));
+}
+
 #[test]
 fn parse_fit_ok() {
     let parsed = Fit::new(&DATA);
     assert!(parsed.is_ok());
 }
+
+#[cfg(test)]
+static MAPPED_DATA: &[u8] = include_bytes!("../tests/me11_fit_mapped.bin");
+
+#[test]
+fn parse_fit_mapped() {
+    // The same FIT, with the pointer given as a physical address
+    let fit = Fit::with_mapping(MAPPED_DATA, Mapping::whole(MAPPED_DATA.len())).unwrap();
+    assert_eq!(fit.offset, 0);
+    assert_eq!(fit.address_for(fit.offset), 0xffff_ff00);
+    assert_eq!(fit.entries.len(), Fit::new(DATA).unwrap().entries.len());
+}
+
+#[test]
+fn to_vec_roundtrip() {
+    let fit = Fit::new(DATA).unwrap();
+    let v = fit.to_vec();
+    assert_eq!(v, DATA[..v.len()]);
+}
+
+#[test]
+fn recompute_checksums() {
+    let mut data = DATA.to_vec();
+    let mut fit = Fit::new(&data).unwrap();
+    // The fixture has no C_V bits set, so let the header and the first BIOS
+    // Startup Module entry, which is within the fixture, claim checksums.
+    fit.header.checksum_valid_and_type |= CHECKSUM_VALID;
+    fit.entries[6].checksum_valid_and_type |= CHECKSUM_VALID;
+    fit.entries[6].addr = 0xffff_ff80;
+    fit.entries[6].size = [0x01, 0x00, 0x00];
+    assert!(matches!(
+        fit.verify(&data)[0],
+        ChecksumStatus::Invalid { .. }
+    ));
+    fit.recompute_checksums(&data);
+    assert!(
+        fit.verify(&data)
+            .iter()
+            .all(|s| matches!(s, ChecksumStatus::Valid | ChecksumStatus::NotApplicable))
+    );
+    // Changing the component invalidates its checksum.
+    data[0x80] ^= 0xff;
+    assert!(matches!(
+        fit.verify(&data)[7],
+        ChecksumStatus::Invalid { .. }
+    ));
+}
+
+#[test]
+fn mapping_region_only() {
+    // A 12MB BIOS region is mapped @ 0xff40_0000.
+    let m = Mapping::whole(0x00c0_0000);
+    assert_eq!(m.offset_for(0xff40_0000), Ok(0));
+    assert_eq!(m.offset_for(0xffff_ffc0), Ok(0x00bf_ffc0));
+    assert!(m.offset_for(0xff00_0000).is_err());
+    assert!(m.offset_for(0x1_0000_0000).is_err());
+    assert_eq!(m.address_for(0x1000), 0xff40_1000);
+}
+
+#[test]
+fn mapping_below_window() {
+    // Addresses below the mapped region must not wrap into it.
+    let m = Mapping::whole(0x00c0_0000);
+    assert!(m.offset_for(0xff3f_ffff).is_err());
+    assert!(m.offset_for(0xfe40_0000).is_err());
+    assert!(m.offset_for(0x0040_0000).is_err());
+    let m = Mapping::from_bios_range(0x0200_0000..0x0400_0000);
+    assert!(m.offset_for(0xfdff_ffff).is_err());
+    assert!(m.offset_for(0x7e00_0000).is_err());
+}
+
+#[test]
+fn mapping_full_image() {
+    // A 64MB image with the BIOS region in the upper 32MB
+    let m = Mapping::from_bios_range(0x0200_0000..0x0400_0000);
+    assert_eq!(m.offset_for(0xfe00_0000), Ok(0x0200_0000));
+    assert_eq!(m.address_for(0x03ff_ffc0), 0xffff_ffc0);
+}
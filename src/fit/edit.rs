@@ -0,0 +1,431 @@
+//! Editing the FIT
+//!
+//! The FIT requires its entries to be in ascending order of their types, so
+//! adding entries inserts them at the right position and reordering is only
+//! possible among entries of the same type. After editing, the table is
+//! written back with [`Fit::write`], which also updates the checksums and,
+//! if the table does not fit its current slot anymore, relocates it and
+//! updates the FIT pointer.
+//!
+//! Erased bytes within a BIOS region are not necessarily unused, e.g., they
+//! may be the free space of an NVRAM store or a firmware volume. Anything
+//! written outside of existing updates and the table is thus only placed in
+//! free space given explicitly.
+
+use core::fmt::{self, Display};
+use core::ops::Range;
+use serde::{Deserialize, Serialize};
+use zerocopy::FromBytes;
+
+use crate::EMPTY;
+use crate::fit::{
+    EntryType, FIT_HEADER_SIZE, FIT_MAGIC_BYTES, FIT_POINTER_BOTTOM_OFFSET, Fit, FitEntry,
+    FitHeader, SIZE_UNIT,
+};
+use crate::microcode::Microcode;
+
+/// FIT entries and microcode updates must be aligned to 16 bytes.
+pub const ALIGNMENT: usize = 16;
+
+// The entry count in the header is a 24 bit field.
+const MAX_ENTRIES: usize = 0x00ff_ffff;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum FitEditError {
+    InvalidIndex(usize),
+    InvalidOrder(String),
+    InvalidMicrocode(String),
+    NoSpace(String),
+    TooManyEntries(usize),
+    Unmappable(String),
+}
+
+impl Display for FitEditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidIndex(i) => write!(f, "no FIT entry at index {i}"),
+            Self::InvalidOrder(e) => write!(f, "invalid entry order: {e}"),
+            Self::InvalidMicrocode(e) => write!(f, "invalid microcode update: {e}"),
+            Self::NoSpace(e) => write!(f, "not enough free space: {e}"),
+            Self::TooManyEntries(n) => write!(f, "too many FIT entries: {n}"),
+            Self::Unmappable(e) => write!(f, "cannot be mapped: {e}"),
+        }
+    }
+}
+
+impl FitEntry {
+    pub fn new(t: EntryType, addr: u64, size: usize, version: u16) -> Self {
+        let s = (size / SIZE_UNIT) as u32;
+        let [s0, s1, s2, _] = s.to_le_bytes();
+        Self {
+            addr,
+            size: [s0, s1, s2],
+            _11: 0,
+            version,
+            checksum_valid_and_type: t as u8,
+            checksum: 0,
+        }
+    }
+}
+
+/// Find a range of erased bytes of the given size within the given range,
+/// starting at an aligned offset. The lowest such offset is returned.
+pub fn find_free_space(
+    data: &[u8],
+    range: Range<usize>,
+    size: usize,
+    align: usize,
+) -> Option<usize> {
+    let end = range.end.min(data.len());
+    let mut o = range.start.next_multiple_of(align);
+    while o + size <= end {
+        match data[o..o + size].iter().rposition(|b| *b != EMPTY) {
+            // Continue after the last non-empty byte found.
+            Some(p) => o = (o + p + 1).next_multiple_of(align),
+            None => return Some(o),
+        }
+    }
+    None
+}
+
+impl Fit {
+    /// Size of the table in bytes, including the header
+    pub fn table_size(&self) -> usize {
+        (self.entries.len() + 1) * FIT_HEADER_SIZE
+    }
+
+    fn update_header(&mut self) -> Result<(), FitEditError> {
+        let n = self.entries.len() + 1;
+        if n > MAX_ENTRIES {
+            return Err(FitEditError::TooManyEntries(n));
+        }
+        // The upper byte is reserved.
+        self.header.entries = n as u32;
+        Ok(())
+    }
+
+    /// Add an entry after all entries of the same or lower type, and return
+    /// its index.
+    pub fn add_entry(&mut self, e: FitEntry) -> Result<usize, FitEditError> {
+        let t = e.type_id();
+        let i = self
+            .entries
+            .iter()
+            .rposition(|x| x.type_id() <= t)
+            .map_or(0, |p| p + 1);
+        self.entries.insert(i, e);
+        if let Err(e) = self.update_header() {
+            self.entries.remove(i);
+            return Err(e);
+        }
+        Ok(i)
+    }
+
+    /// Remove the entry at the given index.
+    pub fn remove_entry(&mut self, index: usize) -> Result<FitEntry, FitEditError> {
+        if index >= self.entries.len() {
+            return Err(FitEditError::InvalidIndex(index));
+        }
+        let e = self.entries.remove(index);
+        self.update_header()?;
+        Ok(e)
+    }
+
+    /// Move an entry to another position among the entries of the same type,
+    /// e.g., to change the order in which microcode updates are tried.
+    pub fn move_entry(&mut self, from: usize, to: usize) -> Result<(), FitEditError> {
+        let l = self.entries.len();
+        for i in [from, to] {
+            if i >= l {
+                return Err(FitEditError::InvalidIndex(i));
+            }
+        }
+        let (a, b) = (self.entries[from].type_id(), self.entries[to].type_id());
+        if a != b {
+            return Err(FitEditError::InvalidOrder(format!(
+                "cannot move entry of type {a:02x} to position of type {b:02x}"
+            )));
+        }
+        let e = self.entries.remove(from);
+        self.entries.insert(to, e);
+        Ok(())
+    }
+
+    /// Sort the entries by type, keeping the order among the same type.
+    pub fn sort_entries(&mut self) {
+        self.entries.sort_by_key(|e| e.type_id());
+    }
+
+    /// Tell whether the entries are in ascending order of their types.
+    pub fn is_sorted(&self) -> bool {
+        self.entries.is_sorted_by_key(|e| e.type_id())
+    }
+
+    // Check that a range is within the part of the image that is mapped, so
+    // that anything placed there can be pointed to.
+    fn mapped(&self, r: &Range<usize>) -> Result<Range<usize>, FitEditError> {
+        let m = self.mapping.range();
+        if r.start < m.start || r.end > m.end {
+            return Err(FitEditError::Unmappable(format!(
+                "free space {r:08x?} outside of BIOS region {m:08x?}"
+            )));
+        }
+        Ok(r.clone())
+    }
+
+    // Find the microcode entry that a new update with the given signature and
+    // platform IDs supersedes.
+    fn find_microcode_entry(&self, data: &[u8], new: &Microcode) -> Option<(usize, usize)> {
+        let (sig, flags) = (new.header.signature, new.header.processor_flags);
+        self.entries.iter().enumerate().find_map(|(i, e)| {
+            if !matches!(e.get_type(), Ok(EntryType::MicrocodeUpdate)) {
+                return None;
+            }
+            let o = self.offset_for(e.addr).ok()?;
+            let m = Microcode::parse(data.get(o..)?).ok()?;
+            let h = m.header;
+            if h.signature == sig && h.processor_flags & flags != 0 {
+                Some((i, h.total_size()))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Insert a microcode update into the image and return the index of its
+    /// entry.
+    ///
+    /// An existing update for the same CPUID signature and platform IDs is
+    /// replaced, in place if the new one fits, otherwise it is erased and the
+    /// new one is written to erased space within `free`, which must be known
+    /// to be unused and within the mapped BIOS region. Updates for other
+    /// processors are appended as new entries.
+    /// Note that for UEFI images, firmware volumes that contain the updates
+    /// are not adjusted.
+    pub fn insert_microcode(
+        &mut self,
+        data: &mut [u8],
+        blob: &[u8],
+        free: Range<usize>,
+    ) -> Result<usize, FitEditError> {
+        let free = self.mapped(&free)?;
+        let m = Microcode::parse(blob).map_err(FitEditError::InvalidMicrocode)?;
+        if !m.checksum_valid {
+            let e = "checksum is invalid".to_string();
+            return Err(FitEditError::InvalidMicrocode(e));
+        }
+        let blob = &blob[..m.header.total_size()];
+        let size = blob.len();
+        let existing = self.find_microcode_entry(data, &m);
+        let mut old = None;
+        if let Some((i, old_size)) = existing {
+            let o = self
+                .offset_for(self.entries[i].addr)
+                .map_err(FitEditError::Unmappable)?;
+            if size <= old_size {
+                data[o..o + size].copy_from_slice(blob);
+                data[o + size..o + old_size].fill(EMPTY);
+                return Ok(i);
+            }
+            // The old space may be reused if it is followed by free space.
+            old = Some((o, data[o..o + old_size].to_vec()));
+            data[o..o + old_size].fill(EMPTY);
+        }
+        let Some(o) = find_free_space(data, free.clone(), size, ALIGNMENT) else {
+            if let Some((o, d)) = old {
+                data[o..o + d.len()].copy_from_slice(&d);
+            }
+            return Err(FitEditError::NoSpace(format!(
+                "{size:08x} bytes for microcode in {free:08x?}"
+            )));
+        };
+        data[o..o + size].copy_from_slice(blob);
+        let addr = self.address_for(o);
+        match existing {
+            Some((i, _)) => {
+                self.entries[i].addr = addr;
+                Ok(i)
+            }
+            // Microcode entries carry no size and version 0x0100.
+            None => self.add_entry(FitEntry::new(EntryType::MicrocodeUpdate, addr, 0, 0x0100)),
+        }
+    }
+
+    // Get the size of the current slot of the table, i.e., the existing table
+    // in the image and the given padding following it.
+    fn slot_size(&self, data: &[u8], padding: usize) -> usize {
+        let o = self.offset;
+        let old = match FitHeader::read_from_prefix(&data[o.min(data.len())..]) {
+            Ok((h, _)) if h.magic == FIT_MAGIC_BYTES => (h.entries & MAX_ENTRIES as u32) as usize,
+            _ => 0,
+        };
+        let end = (o + old * FIT_HEADER_SIZE).saturating_add(padding);
+        end.min(data.len()).saturating_sub(o)
+    }
+
+    /// Recompute the checksums and write the table back to the image.
+    ///
+    /// The table may grow into `padding` bytes after the existing table. If it
+    /// outgrows that slot, it is relocated to erased space within `free`,
+    /// which must be known to be unused, the old table is erased and the FIT
+    /// pointer at 4GB - 0x40 is updated. Without `free`, that is an error.
+    pub fn write(
+        &mut self,
+        data: &mut [u8],
+        padding: usize,
+        free: Option<Range<usize>>,
+    ) -> Result<(), FitEditError> {
+        self.update_header()?;
+        self.recompute_checksums(data);
+        let size = self.table_size();
+        let slot = self.slot_size(data, padding);
+        if size > slot {
+            let Some(free) = free else {
+                return Err(FitEditError::NoSpace(format!(
+                    "{size:08x} bytes for FIT in slot of {slot:08x} bytes"
+                )));
+            };
+            let free = self.mapped(&free)?;
+            // Erase the old table first, its space may be part of the new slot.
+            let o = self.offset;
+            let e = (o + slot).min(data.len());
+            let old = data[o..e].to_vec();
+            data[o..e].fill(EMPTY);
+            let Some(n) = find_free_space(data, free.clone(), size, ALIGNMENT) else {
+                data[o..e].copy_from_slice(&old);
+                return Err(FitEditError::NoSpace(format!(
+                    "{size:08x} bytes for FIT in {free:08x?}"
+                )));
+            };
+            self.offset = n;
+            let p = self.mapping.range().end - FIT_POINTER_BOTTOM_OFFSET;
+            let addr = self.address_for(n) as u32;
+            data[p..p + 4].copy_from_slice(&addr.to_le_bytes());
+        }
+        let o = self.offset;
+        data[o..o + size].copy_from_slice(&self.to_vec());
+        // Erase what is left of the old table.
+        data[o + size..o + size.max(slot)].fill(EMPTY);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    fit::Mapping,
+    microcode::{from_fit, raw_update},
+};
+
+#[cfg(test)]
+const SIZE: usize = 0x1_0000;
+#[cfg(test)]
+const FIT_OFFSET: usize = 0xf000;
+
+#[cfg(test)]
+// An image with a FIT of two entries in a slot that fits three
+fn image() -> (Fit, Vec<u8>) {
+    let mut data = vec![EMPTY; SIZE];
+    // Used data below and after the FIT slot
+    data[..0x8000].fill(0);
+    data[FIT_OFFSET + 0x30..FIT_OFFSET + 0x100].fill(0);
+    let mut fit = Fit {
+        header: FitHeader {
+            magic: *b"_FIT_   ",
+            entries: 1,
+            version: 0x0100,
+            checksum_valid_and_type: 0x80,
+            checksum: 0,
+        },
+        entries: vec![],
+        mapping: Mapping::whole(SIZE),
+        offset: FIT_OFFSET,
+    };
+    let p = SIZE - FIT_POINTER_BOTTOM_OFFSET;
+    let addr = fit.address_for(FIT_OFFSET) as u32;
+    data[p..p + 4].copy_from_slice(&addr.to_le_bytes());
+    let e = FitEntry::new(EntryType::BIOSStartupModule, 0xff00_c000, 0x100, 0x0100);
+    fit.add_entry(e).unwrap();
+    let e = FitEntry::new(EntryType::StartupACM, 0xff00_d000, 0, 0x0100);
+    fit.add_entry(e).unwrap();
+    fit.write(&mut data, 0x30, None).unwrap();
+    (fit, data)
+}
+
+#[test]
+fn add_keeps_order() {
+    let (fit, data) = image();
+    assert!(fit.is_sorted());
+    let parsed = Fit::new(&data).unwrap();
+    assert_eq!(parsed.offset, FIT_OFFSET);
+    assert_eq!(parsed.entries.len(), 2);
+    assert!(matches!(
+        parsed.entries[0].get_type(),
+        Ok(EntryType::StartupACM)
+    ));
+}
+
+#[test]
+fn move_requires_same_type() {
+    let (mut fit, _) = image();
+    assert!(fit.move_entry(0, 1).is_err());
+    assert!(fit.move_entry(0, 5).is_err());
+}
+
+#[test]
+fn insert_and_replace_microcode() {
+    let (mut fit, mut data) = image();
+    let free = 0x8000..FIT_OFFSET;
+    let u = raw_update(0x000906ea, &[]);
+    let i = fit.insert_microcode(&mut data, &u, free.clone()).unwrap();
+    assert_eq!(i, 0);
+    let u = raw_update(0x000906eb, &[]);
+    fit.insert_microcode(&mut data, &u, free.clone()).unwrap();
+    // Same signature and platforms, so it replaces the first one.
+    let u = raw_update(0x000906ea, &[(0x000906ec, 0x22)]);
+    let i = fit.insert_microcode(&mut data, &u, free.clone()).unwrap();
+    assert_eq!(i, 0);
+    // The slot of the table does not grow by itself.
+    let mut f = fit.clone();
+    assert!(matches!(
+        f.write(&mut data.clone(), 0, None),
+        Err(FitEditError::NoSpace(_))
+    ));
+    fit.write(&mut data, 0, Some(free)).unwrap();
+
+    // With 4 entries, the table had to be relocated.
+    let parsed = Fit::new(&data).unwrap();
+    assert_ne!(parsed.offset, FIT_OFFSET);
+    assert_eq!(parsed.entries.len(), 4);
+    assert!(parsed.is_sorted());
+    assert_eq!(parsed.checksum(), parsed.header.checksum);
+    let ucodes = from_fit(&parsed, &data);
+    assert_eq!(ucodes.len(), 2);
+    let m = ucodes[0].1.as_ref().unwrap();
+    assert_eq!(m.signatures().len(), 2);
+    assert!(m.checksum_valid);
+}
+
+#[test]
+fn remove_entry() {
+    let (mut fit, mut data) = image();
+    fit.remove_entry(0).unwrap();
+    assert!(fit.remove_entry(1).is_err());
+    fit.write(&mut data, 0, None).unwrap();
+    let parsed = Fit::new(&data).unwrap();
+    assert_eq!(parsed.offset, FIT_OFFSET);
+    assert_eq!(parsed.entries.len(), 1);
+}
+
+#[test]
+fn insert_microcode_needs_free_space() {
+    let (mut fit, mut data) = image();
+    let orig = data.clone();
+    let u = raw_update(0x000906ea, &[]);
+    // Erased space that is not given is not used, e.g., after the table.
+    let r = fit.insert_microcode(&mut data, &u, 0x7000..0x8040);
+    assert!(matches!(r, Err(FitEditError::NoSpace(_))));
+    let r = fit.insert_microcode(&mut data, &u, 0x8000..SIZE + 0x1000);
+    assert!(matches!(r, Err(FitEditError::Unmappable(_))));
+    assert_eq!(fit.entries.len(), 2);
+    assert_eq!(data, orig);
+}
@@ -0,0 +1,221 @@
+//! Policy records in the FIT
+//!
+//! The TXT, TPM and JMP $ debug policy records do not point to a component,
+//! but to a single bit that holds the respective setting. The bit is either
+//! in CMOS or another register accessed through an index and a data I/O
+//! port, or at a flat memory address, which may be within the image. The
+//! BIOS policy record points to a data structure owned by the platform, and
+//! the Feature Policy Delivery record carries policy bits directly.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+use crate::fit::{EntryType, Fit, FitEntry};
+
+/// Entry version for records that use the index/IO address format
+pub const VERSION_INDEX_IO: u16 = 0x0000;
+
+/// Location of a policy bit
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PolicyLocation {
+    /// Register accessed by writing its index to one I/O port and accessing
+    /// the data through another, e.g., CMOS via ports 0x70 and 0x71
+    IndexIo {
+        index_port: u16,
+        data_port: u16,
+        /// Access width in bytes
+        width: u8,
+        bit: u8,
+        index: u16,
+    },
+    /// Bit 0 of the byte at a physical address
+    Flat { addr: u64 },
+}
+
+impl PolicyLocation {
+    pub fn from_entry(e: &FitEntry) -> Self {
+        let a = e.addr;
+        match e.version {
+            VERSION_INDEX_IO => Self::IndexIo {
+                index_port: a as u16,
+                data_port: (a >> 16) as u16,
+                width: (a >> 32) as u8,
+                bit: (a >> 40) as u8,
+                index: (a >> 48) as u16,
+            },
+            _ => Self::Flat { addr: a },
+        }
+    }
+}
+
+impl Display for PolicyLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IndexIo {
+                index_port,
+                data_port,
+                width,
+                bit,
+                index,
+            } => write!(
+                f,
+                "bit {bit} of index {index:04x} via I/O ports {index_port:04x}/{data_port:04x}, {width} byte(s) wide"
+            ),
+            Self::Flat { addr } => write!(f, "bit 0 @ {addr:08x}"),
+        }
+    }
+}
+
+/// A policy bit and its value, if it can be read from the image
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PolicySetting {
+    pub location: PolicyLocation,
+    pub enabled: Option<bool>,
+}
+
+impl PolicySetting {
+    fn from_entry(fit: &Fit, e: &FitEntry, data: &[u8]) -> Self {
+        let location = PolicyLocation::from_entry(e);
+        let enabled = match location {
+            PolicyLocation::Flat { addr } => fit
+                .offset_for(addr)
+                .ok()
+                .and_then(|o| data.get(o))
+                .map(|b| b & 1 != 0),
+            // This is runtime state, not part of the image.
+            PolicyLocation::IndexIo { .. } => None,
+        };
+        Self { location, enabled }
+    }
+}
+
+impl Display for PolicySetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self.enabled {
+            Some(true) => "enabled",
+            Some(false) => "disabled",
+            None => "set at runtime",
+        };
+        write!(f, "{s} ({})", self.location)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PolicyRecord {
+    Txt(PolicySetting),
+    Tpm(PolicySetting),
+    Bios { addr: u64, size: usize },
+    JmpDebug(PolicySetting),
+    FeaturePolicyDelivery { policy: u64 },
+}
+
+impl PolicyRecord {
+    /// Decode a FIT entry if it is a policy record.
+    pub fn from_entry(fit: &Fit, e: &FitEntry, data: &[u8]) -> Option<Self> {
+        let s = || PolicySetting::from_entry(fit, e, data);
+        match e.get_type() {
+            Ok(EntryType::TXTPolicyRecord) => Some(Self::Txt(s())),
+            Ok(EntryType::TPMPolicyRecord) => Some(Self::Tpm(s())),
+            Ok(EntryType::JMPDebugPolicy) => Some(Self::JmpDebug(s())),
+            Ok(EntryType::BIOSPolicyRecord) => Some(Self::Bios {
+                addr: e.addr,
+                size: e.component_size(),
+            }),
+            Ok(EntryType::FeaturePolicyDeliveryRecord) => {
+                Some(Self::FeaturePolicyDelivery { policy: e.addr })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for PolicyRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Txt(s) => write!(f, "TXT: {s}"),
+            Self::Tpm(s) => write!(f, "TPM: {s}"),
+            Self::JmpDebug(s) => write!(f, "JMP $ debug: {s}"),
+            Self::Bios { addr, size } => write!(f, "BIOS policy: {size:08x} @ {addr:08x}"),
+            Self::FeaturePolicyDelivery { policy } => {
+                let bits = (0..64)
+                    .filter(|b| policy & (1 << b) != 0)
+                    .map(|b| b.to_string())
+                    .collect::<Vec<String>>();
+                let b = match bits.is_empty() {
+                    true => "none".to_string(),
+                    false => bits.join(", "),
+                };
+                write!(f, "feature policy: {policy:016x}, bits set: {b}")
+            }
+        }
+    }
+}
+
+impl Fit {
+    /// Decode all policy records.
+    pub fn policy_records(&self, data: &[u8]) -> Vec<PolicyRecord> {
+        self.entries
+            .iter()
+            .filter_map(|e| PolicyRecord::from_entry(self, e, data))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+use crate::fit::Mapping;
+
+#[cfg(test)]
+fn fit_with(entries: Vec<FitEntry>) -> Fit {
+    use crate::fit::FitHeader;
+    Fit {
+        header: FitHeader {
+            magic: *b"_FIT_   ",
+            entries: entries.len() as u32 + 1,
+            version: 0x0100,
+            checksum_valid_and_type: 0,
+            checksum: 0,
+        },
+        entries,
+        mapping: Mapping::whole(0x1000),
+        offset: 0,
+    }
+}
+
+#[test]
+fn decode_index_io() {
+    // CMOS index 0x2a, bit 3, via ports 0x70/0x71
+    let mut e = FitEntry::new(EntryType::TXTPolicyRecord, 0x002a_0301_0071_0070, 0, 0);
+    e.version = VERSION_INDEX_IO;
+    let fit = fit_with(vec![e]);
+    let r = fit.policy_records(&[]);
+    let PolicyRecord::Txt(s) = r[0] else {
+        panic!("not a TXT policy: {r:?}");
+    };
+    assert_eq!(s.enabled, None);
+    assert_eq!(
+        s.location,
+        PolicyLocation::IndexIo {
+            index_port: 0x70,
+            data_port: 0x71,
+            width: 1,
+            bit: 3,
+            index: 0x2a,
+        }
+    );
+}
+
+#[test]
+fn decode_flat() {
+    let mut data = vec![0xff; 0x1000];
+    data[0x800] = 0xfe;
+    let e = FitEntry::new(EntryType::TPMPolicyRecord, 0xffff_f800, 0, 0x0100);
+    let fit = fit_with(vec![e]);
+    let r = fit.policy_records(&data);
+    assert!(matches!(
+        r[0],
+        PolicyRecord::Tpm(PolicySetting {
+            enabled: Some(false),
+            ..
+        })
+    ));
+}
@@ -0,0 +1,297 @@
+//! Gigabit Ethernet (GbE) region
+//!
+//! The GbE region holds the NVM of the integrated Intel Ethernet controller,
+//! e.g., 82566, 82579 or I219. It is a sequence of 16 bit little endian words,
+//! the first 64 of which are loaded into the shadow RAM of the controller and
+//! protected by a checksum: their sum must be 0xBABA, which the checksum word
+//! at 0x3F makes up for. They hold, among others, the MAC address, the PCI IDs
+//! and the PXE/boot agent configuration.
+//!
+//! The region is commonly split into two banks, of which the controller uses
+//! the one with a valid signature in word 0x13, so that the NVM can be updated
+//! safely. For the word offsets, see Linux `drivers/net/ethernet/intel/e1000e/`.
+
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// The checksummed words sum up to this value.
+pub const NVM_SUM: u16 = 0xbaba;
+/// Number of words loaded into the shadow RAM and covered by the checksum
+pub const SHADOW_WORDS: usize = 0x40;
+/// Size of a bank in dual-bank layouts
+pub const BANK_SIZE: usize = 0x1000;
+
+const MAC_WORD: usize = 0x00;
+const SUBSYSTEM_ID_WORD: usize = 0x0b;
+const SUBSYSTEM_VENDOR_WORD: usize = 0x0c;
+const DEVICE_ID_WORD: usize = 0x0d;
+// Bits 15:14 are 0b10 for a valid bank.
+const SIGNATURE_WORD: usize = 0x13;
+const SIGNATURE_MASK: u16 = 0xc000;
+const SIGNATURE_VALUE: u16 = 0x8000;
+const PXE_WORDS: core::ops::Range<usize> = 0x30..0x3f;
+const CHECKSUM_WORD: usize = 0x3f;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self
+            .0
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<String>>();
+        write!(f, "{}", s.join(":"))
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = String;
+
+    /// Parse a MAC address given as 6 hex bytes separated by `:` or `-`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s
+            .split([':', '-'])
+            .map(|b| match b.len() {
+                1 | 2 => u8::from_str_radix(b, 16).map_err(|e| e.to_string()),
+                _ => Err(format!("invalid byte {b}")),
+            })
+            .collect::<Result<Vec<u8>, String>>()
+            .map_err(|e| format!("invalid MAC address {s}: {e}"))?;
+        match <[u8; 6]>::try_from(bytes) {
+            Ok(m) => Ok(Self(m)),
+            Err(_) => Err(format!("invalid MAC address {s}: expected 6 bytes")),
+        }
+    }
+}
+
+/// One copy of the NVM
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GbeBank {
+    /// Offset of the bank in the image
+    pub offset: usize,
+    /// The words loaded into the shadow RAM
+    pub words: Vec<u16>,
+}
+
+impl GbeBank {
+    pub fn parse(data: &[u8], offset: usize) -> Result<Self, String> {
+        let size = SHADOW_WORDS * 2;
+        let Some(d) = data.get(..size) else {
+            return Err(format!("GbE bank @ {offset:08x} smaller than {size:#x}"));
+        };
+        let words = d
+            .chunks_exact(2)
+            .map(|w| u16::from_le_bytes([w[0], w[1]]))
+            .collect();
+        Ok(Self { offset, words })
+    }
+
+    /// Whether the bank has a valid signature, i.e., is used.
+    pub fn is_valid(&self) -> bool {
+        self.words[SIGNATURE_WORD] & SIGNATURE_MASK == SIGNATURE_VALUE
+    }
+
+    pub fn mac(&self) -> MacAddress {
+        let mut m = [0u8; 6];
+        for (i, w) in self.words[MAC_WORD..MAC_WORD + 3].iter().enumerate() {
+            m[i * 2..i * 2 + 2].copy_from_slice(&w.to_le_bytes());
+        }
+        MacAddress(m)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.words[DEVICE_ID_WORD]
+    }
+
+    pub fn subsystem_id(&self) -> u16 {
+        self.words[SUBSYSTEM_ID_WORD]
+    }
+
+    pub fn subsystem_vendor_id(&self) -> u16 {
+        self.words[SUBSYSTEM_VENDOR_WORD]
+    }
+
+    /// PXE/boot agent configuration words
+    pub fn pxe_words(&self) -> &[u16] {
+        &self.words[PXE_WORDS]
+    }
+
+    /// Compute the checksum word that makes the words sum up to 0xBABA.
+    pub fn compute_checksum(&self) -> u16 {
+        let sum = self.words[..CHECKSUM_WORD]
+            .iter()
+            .fold(0u16, |s, w| s.wrapping_add(*w));
+        NVM_SUM.wrapping_sub(sum)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.words[CHECKSUM_WORD]
+    }
+
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum() == self.compute_checksum()
+    }
+
+    /// Set the MAC address and recompute the checksum.
+    pub fn set_mac(&mut self, mac: MacAddress) {
+        for (i, b) in mac.0.chunks_exact(2).enumerate() {
+            self.words[MAC_WORD + i] = u16::from_le_bytes([b[0], b[1]]);
+        }
+        self.words[CHECKSUM_WORD] = self.compute_checksum();
+    }
+
+    /// Write the words back to the image.
+    pub fn write(&self, data: &mut [u8]) -> Result<(), String> {
+        let o = self.offset;
+        let Some(d) = data.get_mut(o..o + SHADOW_WORDS * 2) else {
+            return Err(format!("GbE bank @ {o:08x} out of bounds"));
+        };
+        for (c, w) in d.chunks_exact_mut(2).zip(&self.words) {
+            c.copy_from_slice(&w.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl Display for GbeBank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let o = self.offset;
+        let v = if self.is_valid() { "valid" } else { "invalid" };
+        let c = self.checksum();
+        let cs = if self.is_checksum_valid() {
+            format!("checksum {c:04x} valid")
+        } else {
+            let e = self.compute_checksum();
+            format!("checksum {c:04x} INVALID, should be {e:04x}")
+        };
+        writeln!(f, "Bank @ {o:08x}, signature {v}, {cs}")?;
+        writeln!(f, "  MAC address:         {}", self.mac())?;
+        writeln!(f, "  Device ID:           {:04x}", self.device_id())?;
+        writeln!(f, "  Subsystem ID:        {:04x}", self.subsystem_id())?;
+        writeln!(
+            f,
+            "  Subsystem vendor ID: {:04x}",
+            self.subsystem_vendor_id()
+        )?;
+        write!(f, "  PXE words:           {:04x?}", self.pxe_words())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Gbe {
+    /// Offset of the region in the image
+    pub offset: usize,
+    pub size: usize,
+    pub banks: Vec<GbeBank>,
+}
+
+impl Gbe {
+    /// Parse a GbE region, given its data and offset. Regions that can hold
+    /// two banks are taken as dual-bank.
+    pub fn parse(data: &[u8], offset: usize) -> Result<Self, String> {
+        let size = data.len();
+        let banks = if size >= 2 * BANK_SIZE {
+            vec![
+                GbeBank::parse(data, offset)?,
+                GbeBank::parse(&data[BANK_SIZE..], offset + BANK_SIZE)?,
+            ]
+        } else {
+            vec![GbeBank::parse(data, offset)?]
+        };
+        Ok(Self {
+            offset,
+            size,
+            banks,
+        })
+    }
+
+    /// The bank the controller uses, i.e., the first with a valid signature.
+    pub fn active_bank(&self) -> Option<&GbeBank> {
+        self.banks.iter().find(|b| b.is_valid())
+    }
+
+    pub fn mac(&self) -> Option<MacAddress> {
+        self.active_bank().map(|b| b.mac())
+    }
+
+    /// Set the MAC address in all valid banks, recomputing their checksums.
+    pub fn set_mac(&mut self, mac: MacAddress) -> Result<(), String> {
+        let mut found = false;
+        for b in self.banks.iter_mut().filter(|b| b.is_valid()) {
+            b.set_mac(mac);
+            found = true;
+        }
+        match found {
+            true => Ok(()),
+            false => Err("no valid GbE bank".into()),
+        }
+    }
+
+    /// Write all banks back to the image.
+    pub fn write(&self, data: &mut [u8]) -> Result<(), String> {
+        self.banks.iter().try_for_each(|b| b.write(data))
+    }
+}
+
+impl Display for Gbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let o = self.offset;
+        let s = self.size;
+        write!(f, "GbE region @ {o:08x}, size {s:08x}")?;
+        for b in &self.banks {
+            write!(f, "\n{b}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_region() -> Vec<u8> {
+    let mut words = [0u16; SHADOW_WORDS];
+    words[0..3].copy_from_slice(&[0x1100, 0x3322, 0x5544]);
+    words[DEVICE_ID_WORD] = 0x1502;
+    words[SIGNATURE_WORD] = 0x8000;
+    let bank = GbeBank {
+        offset: 0,
+        words: words.to_vec(),
+    };
+    words[CHECKSUM_WORD] = bank.compute_checksum();
+    let mut data = vec![0xff; 2 * BANK_SIZE];
+    for (i, w) in words.iter().enumerate() {
+        data[i * 2..i * 2 + 2].copy_from_slice(&w.to_le_bytes());
+    }
+    data
+}
+
+#[test]
+fn parse_gbe() {
+    let gbe = Gbe::parse(&test_region(), 0x1000).unwrap();
+    assert_eq!(gbe.banks.len(), 2);
+    let b = gbe.active_bank().unwrap();
+    assert_eq!(b.offset, 0x1000);
+    assert!(b.is_checksum_valid());
+    assert_eq!(b.device_id(), 0x1502);
+    assert_eq!(b.mac().to_string(), "00:11:22:33:44:55");
+    // The erased second bank has no valid signature.
+    assert!(!gbe.banks[1].is_valid());
+    assert!(Gbe::parse(&[0; 0x20], 0).is_err());
+}
+
+#[test]
+fn set_mac() {
+    let mut data = test_region();
+    let mut gbe = Gbe::parse(&data, 0).unwrap();
+    let mac = "aa-bb-cc-dd-ee-0f".parse::<MacAddress>().unwrap();
+    gbe.set_mac(mac).unwrap();
+    gbe.write(&mut data).unwrap();
+    let gbe = Gbe::parse(&data, 0).unwrap();
+    assert_eq!(gbe.mac(), Some(mac));
+    assert!(gbe.banks[0].is_checksum_valid());
+    // The second bank is left alone.
+    assert!(gbe.banks[1].words.iter().all(|w| *w == 0xffff));
+    assert!("aa:bb:cc".parse::<MacAddress>().is_err());
+    assert!("aa:bb:cc:dd:ee:fg".parse::<MacAddress>().is_err());
+}
@@ -44,23 +44,37 @@
 // Lowercase helpers are provided through implementations.
 #![allow(non_snake_case)]
 
+pub mod access;
+pub mod builder;
+pub mod components;
+pub mod image;
+pub mod layout;
+pub mod straps;
+
 use std::{
     fmt::{Debug, Display},
     ops::Range,
 };
 
 use bitfield_struct::bitfield;
+use log::warn;
 use serde::{Deserialize, Serialize};
 use zerocopy::{FromBytes, IntoBytes};
 use zerocopy_derive::{FromBytes, Immutable, IntoBytes};
 
-use crate::{EMPTY, me::Generation, ver::Version};
+use crate::{EMPTY, me::Generation, meta::Variant, ver::Version};
 
 // NOTE: This is the LE representation.
 const MAGIC: u32 = 0x0ff0_a55a;
 // This is based on examples, excluding the VSCC table, upper descriptor map and
 // OEM section.
 const SIZE: usize = 0x800;
+// The full descriptor, including the upper map and OEM section
+const FULL_SIZE: usize = 0x1000;
+// Descriptor Upper Map, right below the OEM section
+const UPPER_MAP_OFFSET: usize = 0xefc;
+const OEM_OFFSET: usize = 0xf00;
+const OEM_SIZE: usize = 0x100;
 
 #[bitfield(u32)]
 #[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize)]
@@ -173,6 +187,21 @@ impl Display for FLMAP2 {
     }
 }
 
+// The fields are not publicly documented; coreboot ifdtool only prints the
+// raw value, so we do the same.
+#[bitfield(u32)]
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize)]
+pub struct FLMAP3 {
+    raw: u32,
+}
+
+// Only for 500 series chipset PCHs and later, per coreboot util/ifdtool
+impl Display for FLMAP3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "            FLMAP3: 0x{:08x}", self.raw())
+    }
+}
+
 #[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
 #[repr(C)]
 pub struct Header {
@@ -180,7 +209,163 @@ pub struct Header {
     flmap0: FLMAP0,
     flmap1: FLMAP1,
     flmap2: FLMAP2, // 100x series
-    flmap3: u32,    // TODO: 500, 600, 800 and 900 series
+    flmap3: FLMAP3, // 500, 600, 800 and 900 series
+}
+
+/// Descriptor Upper Map
+#[bitfield(u32)]
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize)]
+pub struct FLUMAP1 {
+    VTBA: u8,
+    VTL: u8,
+    _0: u16,
+}
+
+impl FLUMAP1 {
+    fn vtba(self) -> usize {
+        (self.VTBA() as usize) << 4
+    }
+    /// Number of VSCC table entries; the length is given in dwords.
+    fn vtl(self) -> usize {
+        self.VTL() as usize / 2
+    }
+}
+
+impl Display for FLUMAP1 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let vtba = self.vtba();
+        let vtl = self.vtl();
+        write!(f, "        VSCC table:  {vtl}, base: 0x{vtba:08x}")
+    }
+}
+
+/// JEDEC ID of a flash part, as returned by the RDID (0x9f) command
+#[bitfield(u32)]
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize)]
+pub struct JedecId {
+    pub vendor: u8,
+    pub device0: u8,
+    pub device1: u8,
+    _0: u8,
+}
+
+impl Display for JedecId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let v = self.vendor();
+        let d0 = self.device0();
+        let d1 = self.device1();
+        write!(f, "{v:02x} {d0:02x} {d1:02x}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum EraseSize {
+    B256,
+    K4,
+    K8,
+    K64,
+}
+
+impl EraseSize {
+    const fn from_bits(val: u8) -> Self {
+        match val {
+            0b00 => Self::B256,
+            0b01 => Self::K4,
+            0b10 => Self::K8,
+            _ => Self::K64,
+        }
+    }
+
+    const fn into_bits(self) -> u8 {
+        self as u8
+    }
+}
+
+impl Display for EraseSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::B256 => "256B",
+            Self::K4 => "4K",
+            Self::K8 => "8K",
+            Self::K64 => "64K",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Vendor Specific Component Capabilities, for the lower and upper parts of
+/// the flash as split by the FPBA
+#[bitfield(u32)]
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize)]
+pub struct Vscc {
+    #[bits(2)]
+    pub upper_erase_size: EraseSize,
+    /// 64 bytes if set, otherwise 1 byte
+    pub upper_write_granularity_64: bool,
+    pub upper_write_status_required: bool,
+    /// Write enable opcode 0x06 if set, otherwise 0x50
+    pub upper_write_enable_on_status_06: bool,
+    #[bits(3)]
+    _0: u8,
+    pub upper_erase_opcode: u8,
+    #[bits(2)]
+    pub lower_erase_size: EraseSize,
+    pub lower_write_granularity_64: bool,
+    pub lower_write_status_required: bool,
+    pub lower_write_enable_on_status_06: bool,
+    #[bits(3)]
+    _1: u8,
+    pub lower_erase_opcode: u8,
+}
+
+fn vscc_part(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    (size, granularity, status, wren, opcode): (EraseSize, bool, bool, bool, u8),
+) -> std::fmt::Result {
+    let g = if granularity { 64 } else { 1 };
+    let s = if status { "yes" } else { "no" };
+    let w = if wren { 0x06 } else { 0x50 };
+    write!(
+        f,
+        "    {name}: erase {size} with opcode 0x{opcode:02x}, write granularity {g}B, write status required: {s}, write enable on write status 0x{w:02x}"
+    )
+}
+
+impl Display for Vscc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let l = (
+            self.lower_erase_size(),
+            self.lower_write_granularity_64(),
+            self.lower_write_status_required(),
+            self.lower_write_enable_on_status_06(),
+            self.lower_erase_opcode(),
+        );
+        let u = (
+            self.upper_erase_size(),
+            self.upper_write_granularity_64(),
+            self.upper_write_status_required(),
+            self.upper_write_enable_on_status_06(),
+            self.upper_erase_opcode(),
+        );
+        vscc_part(f, "lower", l)?;
+        writeln!(f)?;
+        vscc_part(f, "upper", u)
+    }
+}
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct VsccEntry {
+    pub jid: JedecId,
+    pub vscc: Vscc,
+}
+
+impl Display for VsccEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "  JEDEC ID {}", self.jid)?;
+        write!(f, "{}", self.vscc)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
@@ -289,19 +474,24 @@ pub struct Components {
     FLILL1: FlashInvalidInstructions,
 }
 
+// IFD v1 only uses 13 bits for base and limit, the upper 2 bits being
+// reserved and 0, while IFD v2 uses all 15 bits to support larger flashes.
 #[bitfield(u32)]
 #[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize)]
 pub struct FlashRegion {
-    #[bits(13)]
+    #[bits(15)]
     base: u32,
-    #[bits(3)]
+    #[bits(1)]
     _0: u8,
-    #[bits(13)]
+    #[bits(15)]
     limit: u32,
-    #[bits(3)]
+    #[bits(1)]
     _1: u8,
 }
 
+// An erased dword, e.g., a region entry beyond the end of the table
+const ERASED: u32 = 0xffff_ffff;
+
 impl FlashRegion {
     fn ba(self) -> usize {
         self.base() as usize * 4096
@@ -313,54 +503,284 @@ impl FlashRegion {
     pub fn range(self) -> Range<usize> {
         self.ba()..self.la() + 1
     }
+
+    /// Unused regions have a base above their limit.
+    pub fn is_used(self) -> bool {
+        self.into_bits() != ERASED && self.ba() <= self.la()
+    }
 }
 
 impl Display for FlashRegion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let b = self.ba();
         let l = self.la();
-        let u = if b > l { " (unused)" } else { "" };
+        let u = if self.is_used() { "" } else { " (unused)" };
         write!(f, "{b:08x} - {l:08x}{u}")
     }
 }
 
-#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
-#[repr(C)]
+/// What a flash region is used for, identified by its index
+///
+/// The names follow coreboot `util/ifdtool/`. Most platforms only use the
+/// first few; server PCHs, e.g., C620, add the Innovation Engine and 10GbE
+/// regions.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RegionKind {
+    Descriptor,
+    Bios,
+    Me,
+    Gbe,
+    PlatformData,
+    DeviceExpansion,
+    SecondaryBios,
+    Reserved(usize),
+    Ec,
+    DeviceExpansion2,
+    /// Innovation Engine
+    Ie,
+    TenGbeA,
+    TenGbeB,
+    /// Platform Trust Technology
+    Ptt,
+}
+
+/// Maximum number of regions as of IFD v2
+pub const MAX_REGIONS: usize = 16;
+
+impl RegionKind {
+    pub fn from_index(i: usize) -> Option<Self> {
+        let k = match i {
+            0 => Self::Descriptor,
+            1 => Self::Bios,
+            2 => Self::Me,
+            3 => Self::Gbe,
+            4 => Self::PlatformData,
+            5 => Self::DeviceExpansion,
+            6 => Self::SecondaryBios,
+            7 | 13 | 14 => Self::Reserved(i),
+            8 => Self::Ec,
+            9 => Self::DeviceExpansion2,
+            10 => Self::Ie,
+            11 => Self::TenGbeA,
+            12 => Self::TenGbeB,
+            15 => Self::Ptt,
+            _ => return None,
+        };
+        Some(k)
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            Self::Descriptor => 0,
+            Self::Bios => 1,
+            Self::Me => 2,
+            Self::Gbe => 3,
+            Self::PlatformData => 4,
+            Self::DeviceExpansion => 5,
+            Self::SecondaryBios => 6,
+            Self::Reserved(i) => i,
+            Self::Ec => 8,
+            Self::DeviceExpansion2 => 9,
+            Self::Ie => 10,
+            Self::TenGbeA => 11,
+            Self::TenGbeB => 12,
+            Self::Ptt => 15,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Descriptor => "Flash descriptor (IFD)",
+            Self::Bios => "BIOS (host) firmware",
+            Self::Me => "(CS)ME firmware",
+            Self::Gbe => "Gigabit ethernet data",
+            Self::PlatformData => "Platform data",
+            Self::DeviceExpansion => "Device expansion",
+            Self::SecondaryBios => "Secondary BIOS",
+            Self::Reserved(_) => "Reserved",
+            Self::Ec => "Embedded controller (EC)",
+            Self::DeviceExpansion2 => "Device expansion 2",
+            Self::Ie => "Innovation Engine (IE)",
+            Self::TenGbeA => "10 Gigabit ethernet A",
+            Self::TenGbeB => "10 Gigabit ethernet B",
+            Self::Ptt => "Platform Trust Tech. (PTT)",
+        }
+    }
+}
+
+impl RegionKind {
+    /// Short name as used by coreboot `ifdtool` and flashprog, e.g., "bios"
+    pub fn short_name(self) -> &'static str {
+        match self {
+            Self::Descriptor => "fd",
+            Self::Bios => "bios",
+            Self::Me => "me",
+            Self::Gbe => "gbe",
+            Self::PlatformData => "pd",
+            Self::DeviceExpansion => "devexp",
+            Self::SecondaryBios => "bios2",
+            Self::Reserved(13) => "res13",
+            Self::Reserved(14) => "res14",
+            Self::Reserved(_) => "res7",
+            Self::Ec => "ec",
+            Self::DeviceExpansion2 => "devexp2",
+            Self::Ie => "ie",
+            Self::TenGbeA => "10gbe_0",
+            Self::TenGbeB => "10gbe_1",
+            Self::Ptt => "ptt",
+        }
+    }
+
+    /// Name of the region in coreboot flashmaps, e.g., "SI_BIOS"
+    pub fn fmap_name(self) -> Option<&'static str> {
+        let n = match self {
+            Self::Descriptor => "SI_DESC",
+            Self::Bios => "SI_BIOS",
+            Self::Me => "SI_ME",
+            Self::Gbe => "SI_GBE",
+            Self::PlatformData => "SI_PDR",
+            Self::DeviceExpansion => "SI_DEVICEEXT",
+            Self::SecondaryBios => "SI_BIOS2",
+            Self::Reserved(_) => return None,
+            Self::Ec => "SI_EC",
+            Self::DeviceExpansion2 => "SI_DEVICEEXT2",
+            Self::Ie => "SI_IE",
+            Self::TenGbeA => "SI_10GBE0",
+            Self::TenGbeB => "SI_10GBE1",
+            Self::Ptt => "SI_PTT",
+        };
+        Some(n)
+    }
+
+    /// Name of the file `ifdtool -x` extracts the region to
+    pub fn file_name(self) -> String {
+        let n = match self {
+            Self::Descriptor => "flashdescriptor",
+            Self::Bios => "bios",
+            Self::Me => "intel_me",
+            Self::Gbe => "gbe",
+            Self::PlatformData => "platform_data",
+            Self::DeviceExpansion => "device_exp",
+            Self::SecondaryBios => "bios2",
+            Self::Reserved(_) => "reserved",
+            Self::Ec => "ec",
+            Self::DeviceExpansion2 => "device_exp2",
+            Self::Ie => "ie",
+            Self::TenGbeA => "10gbe0",
+            Self::TenGbeB => "10gbe1",
+            Self::Ptt => "ptt",
+        };
+        format!("flashregion_{}_{n}.bin", self.index())
+    }
+
+    /// Look up a region by its short or flashmap name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..MAX_REGIONS).filter_map(Self::from_index).find(|k| {
+            k.short_name().eq_ignore_ascii_case(name)
+                || k.fmap_name().is_some_and(|n| n.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+impl Display for RegionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A flash region along with what it is used for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub region: FlashRegion,
+}
+
+impl Region {
+    pub fn range(&self) -> Range<usize> {
+        self.region.range()
+    }
+
+    pub fn is_used(&self) -> bool {
+        self.region.is_used()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Regions {
-    pub flreg0: FlashRegion,
-    pub flreg1: FlashRegion,
-    pub flreg2: FlashRegion,
-    pub flreg3: FlashRegion,
-    pub flreg4: FlashRegion,
-    pub flreg5: FlashRegion,
-    pub flreg6: FlashRegion,
-    pub flreg7: FlashRegion,
-    pub flreg8: FlashRegion,
-    pub flreg9: FlashRegion,
+    /// All entries of the region table, indexed as per [`RegionKind`]
+    pub entries: Vec<FlashRegion>,
 }
 
 impl Regions {
+    /// Parse the region table, given the maximum number of entries there is
+    /// space for.
+    ///
+    /// On IFD v1, NR tells the number of regions. On IFD v2, it is reserved
+    /// and 0, and the table simply ends with erased entries, so we read the
+    /// table up to the first erased entry, honoring NR if it claims more.
+    fn parse(data: &[u8], nr: usize, max: usize) -> Self {
+        let max = max.min(MAX_REGIONS);
+        let all = u8_slice_to_u32(&data[..(max * 4).min(data.len() / 4 * 4)]);
+        let n = all
+            .iter()
+            .position(|r| *r == ERASED)
+            .unwrap_or(all.len())
+            .max(nr.min(all.len()));
+        let entries = all[..n]
+            .iter()
+            .map(|r| FlashRegion::from_bits(*r))
+            .collect();
+        Self { entries }
+    }
+
+    /// Iterate over all regions present in the table, used or not.
+    pub fn iter(&self) -> impl Iterator<Item = Region> + '_ {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| RegionKind::from_index(i).map(|kind| Region { kind, region: *r }))
+    }
+
+    pub fn get(&self, kind: RegionKind) -> Option<Region> {
+        self.entries
+            .get(kind.index())
+            .map(|r| Region { kind, region: *r })
+    }
+
+    /// Get the range of a region, empty if it is not present.
+    pub fn range(&self, kind: RegionKind) -> Range<usize> {
+        match self.get(kind) {
+            Some(r) => r.range(),
+            None => 0..0,
+        }
+    }
+
     pub fn ifd_range(&self) -> Range<usize> {
-        self.flreg0.range()
+        self.range(RegionKind::Descriptor)
     }
     pub fn bios_range(&self) -> Range<usize> {
-        self.flreg1.range()
+        self.range(RegionKind::Bios)
     }
     pub fn me_range(&self) -> Range<usize> {
-        self.flreg2.range()
+        self.range(RegionKind::Me)
     }
 }
 
 // NOTE: Regions have changed over processors generations.
 impl Display for Regions {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let r0 = format!("   Flash descriptor (IFD):   {}", self.flreg0);
-        let r1 = format!("   BIOS (host) firmware:     {}", self.flreg1);
-        let r2 = format!("   (CS)ME firmware:          {}", self.flreg2);
-        let r3 = format!("   Gigabit ethernet data:    {}", self.flreg3);
-        let r4 = format!("   Platform data:            {}", self.flreg4);
-        let r5 = format!("   Embedded controller (EC): {}", self.flreg5);
-        write!(f, "{r0}\n{r1}\n{r2}\n{r3}\n{r4}\n{r5}")
+        let l = self
+            .iter()
+            .map(|r| {
+                format!(
+                    "  {:2} {:27} {}",
+                    r.kind.index(),
+                    format!("{}:", r.kind),
+                    r.region
+                )
+            })
+            .collect::<Vec<String>>();
+        write!(f, "{}", l.join("\n"))
     }
 }
 
@@ -485,6 +905,18 @@ impl Display for FlashMasterV2 {
     }
 }
 
+/// Descriptor version, which determines the layout of the masters section
+///
+/// IFD v2 came with Skylake and the 100 series PCH.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IfdVersion {
+    V1,
+    V2,
+}
+
+// The (CS)ME is the second master.
+const ME_MASTER: usize = 1;
+
 #[derive(Serialize, Deserialize, Clone)]
 #[repr(C)]
 pub struct IFD {
@@ -494,6 +926,14 @@ pub struct IFD {
     pub masters: Vec<u32>,
     pub pch_straps: Vec<u32>,
     pub mch_straps: Vec<u32>,
+    /// Only present in full 4K descriptors
+    pub upper_map: Option<FLUMAP1>,
+    pub vscc: Vec<VsccEntry>,
+    #[serde(with = "serde_bytes")]
+    pub oem: Option<Vec<u8>>,
+    /// The descriptor as read, so that bytes not modelled above survive
+    #[serde(skip)]
+    raw: Vec<u8>,
 }
 
 impl Display for IFD {
@@ -502,10 +942,39 @@ impl Display for IFD {
         writeln!(f, "{}", self.header.flmap0)?;
         writeln!(f, "{}", self.header.flmap1)?;
         writeln!(f, "{}", self.header.flmap2)?;
+        writeln!(f, "{}", self.header.flmap3)?;
+        if let Some(m) = self.upper_map {
+            writeln!(f, "{m}")?;
+        }
         writeln!(f, "== Components ==")?;
+        match self.component_sizes() {
+            Ok(sizes) => {
+                for (i, s) in sizes.iter().enumerate() {
+                    writeln!(f, "  component {}: {}", i + 1, components::format_size(*s))?;
+                }
+            }
+            Err(e) => writeln!(f, "  {e}")?,
+        }
         writeln!(f, "{:#02x?}", self.components)?;
         writeln!(f, "== Regions ==")?;
-        write!(f, "{}", self.regions)
+        write!(f, "{}", self.regions)?;
+        if !self.vscc.is_empty() {
+            write!(f, "\n== ME VSCC table ==")?;
+            for e in &self.vscc {
+                write!(f, "\n{e}")?;
+            }
+        }
+        if let Some(oem) = &self.oem {
+            write!(f, "\n== OEM section ==")?;
+            if oem.iter().all(|b| *b == EMPTY) {
+                write!(f, "\n  empty")?;
+            } else {
+                for (i, c) in oem.chunks(16).enumerate() {
+                    write!(f, "\n  {:04x}: {c:02x?}", OEM_OFFSET + i * 16)?;
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -524,17 +993,6 @@ impl Debug for IFD {
     }
 }
 
-/// Extract a bit from a given byte, as bool.
-fn extract_bit(straps: &[u32], byte: usize, bit: u32) -> bool {
-    straps[byte] >> bit & 1 == 1
-}
-
-/// Set or clear a bit in a given strap, identified by its number.
-fn set_bit(straps: &mut [u32], byte: usize, bit: u32, s: bool) {
-    let b = straps[byte];
-    straps[byte] = b & !(1 << bit) | (u32::from(s) << bit);
-}
-
 // TODO: The straps changed over the generations of processors.
 // Specifically the HAP bit on Skylake and later has moved, so we should not
 // blindly assume it.
@@ -546,11 +1004,11 @@ impl IFD {
     // from <https://review.coreboot.org/c/coreboot/+/82272>
     // <https://edc.intel.com/content/www/us/en/design/products-and-solutions/processors-and-chipsets/700-series-chipset-family-platform-controller-hub-datasheet-volume-1-of/004/intel-direct-connect-interface-dci/>
     pub fn dci(&self) -> bool {
-        extract_bit(&self.pch_straps, 0, 17)
+        self.strap_flag(&straps::DCI)
     }
     /// Direct Connect Interface
     pub fn set_dci(&mut self, s: bool) {
-        set_bit(&mut self.pch_straps, 0, 17, s);
+        self.set_strap_flag(&straps::DCI, s);
     }
 
     // TODO: there is a _different_ soft-disable feature
@@ -558,65 +1016,77 @@ impl IFD {
     // TODO: make ME version a parameter to infer platform
     /// High-Assurance Platform (ME soft-disable), ME Gen 3
     pub fn hap(&self) -> bool {
-        extract_bit(&self.pch_straps, 0, 16)
+        self.strap_flag(&straps::HAP)
     }
     /// High-Assurance Platform (ME soft-disable), ME Gen 3
     pub fn set_hap(&mut self, s: bool) {
-        set_bit(&mut self.pch_straps, 0, 16, s);
+        self.set_strap_flag(&straps::HAP, s);
     }
 
     /// I/O Controller Hub, ME Gen 1
     pub fn ich_me_disabled(&self) -> bool {
-        extract_bit(&self.pch_straps, 0, 0)
+        self.strap_flag(&straps::ME_DISABLE)
     }
     /// I/O Controller Hub, ME Gen 1
     pub fn set_ich_me_disabled(&mut self, s: bool) {
-        set_bit(&mut self.pch_straps, 0, 0, s);
+        self.set_strap_flag(&straps::ME_DISABLE, s);
     }
 
     /// Memory Controller Hub, ME Gen 1
     pub fn mch_me_disabled(&self) -> bool {
-        extract_bit(&self.mch_straps, 0, 0)
+        self.strap_flag(&straps::MCH_ME_DISABLE)
     }
     /// Memory Controller Hub, ME Gen 1
     pub fn set_mch_me_disabled(&mut self, s: bool) {
-        set_bit(&mut self.mch_straps, 0, 0, s);
+        self.set_strap_flag(&straps::MCH_ME_DISABLE, s);
     }
 
     /// Memory Controller Hub (alternative), ME Gen 1
     pub fn mch_alt_me_disabled(&self) -> bool {
-        extract_bit(&self.mch_straps, 0, 7)
+        self.strap_flag(&straps::MCH_ALT_ME_DISABLE)
     }
     /// Memory Controller Hub (alternative), ME Gen 1
     pub fn set_mch_alt_me_disabled(&mut self, s: bool) {
-        set_bit(&mut self.mch_straps, 0, 7, s);
+        self.set_strap_flag(&straps::MCH_ALT_ME_DISABLE, s);
     }
 
     /// Disable ME (alternative), ME Gen 2
     pub fn alt_me_disabled(&self) -> bool {
-        extract_bit(&self.pch_straps, 10, 7)
+        self.strap_flag(&straps::ALT_ME_DISABLE)
     }
     /// Disable ME (alternative), ME Gen 2
     pub fn set_alt_me_disabled(&mut self, s: bool) {
-        set_bit(&mut self.pch_straps, 10, 7, s);
+        self.set_strap_flag(&straps::ALT_ME_DISABLE, s);
     }
 
-    /// Disable ME for ME generation 3.
-    /// Requires passing the ME version in order to infer the platform.
-    pub fn disable_me_gen3(&mut self, me_ver: &Option<Version>) -> Result<(), String> {
-        if let Some(ver) = me_ver {
-            match ver.major {
-                11 => self.set_hap(true),
-                _ => return Err("ME > 11 not yet supported".into()),
+    /// Disable ME for ME generation 3 by setting the HAP bit.
+    /// Requires passing the ME variant and version in order to infer the
+    /// platform.
+    pub fn disable_me_gen3(
+        &mut self,
+        variant: Variant,
+        me_ver: &Option<Version>,
+    ) -> Result<(), String> {
+        let Some(ver) = me_ver else {
+            return Err("ME version unknown, cannot locate HAP bit".into());
+        };
+        match straps::hap_field(variant, ver) {
+            Ok(f) => self.set_strap_value(&f, 1).map_err(|e| e.to_string()),
+            Err(straps::NoHapField::Unverified) => Err(format!(
+                "HAP bit location for {variant:?} {ver} is unverified, deliberately unsupported"
+            )),
+            Err(straps::NoHapField::Unknown) => {
+                Err(format!("HAP bit location unknown for {variant:?} {ver}"))
             }
         }
-        Ok(())
     }
 
-    /// Disable the ME using any mechanism given the ME generation and version.
+    /// Disable the ME using any mechanism given the ME generation, variant
+    /// and version.
     pub fn disable_me(
         &mut self,
         me_gen: &Generation,
+        variant: Variant,
         me_ver: &Option<Version>,
     ) -> Result<(), String> {
         match me_gen {
@@ -631,15 +1101,59 @@ impl IFD {
                 self.set_alt_me_disabled(true);
                 Ok(())
             }
-            Generation::Gen3 => self.disable_me_gen3(me_ver),
+            Generation::Gen3 => self.disable_me_gen3(variant, me_ver),
             _ => Err("Unknown ME generation/version".into()),
         }
     }
 }
 
+impl IFD {
+    /// Detect the descriptor version from the SPI read clock frequency, as
+    /// coreboot `ifdtool` does: IFD v1 platforms use 20MHz, IFD v2 platforms
+    /// 17MHz or 50/30MHz.
+    pub fn version(&self) -> Option<IfdVersion> {
+        let c = self.components.FLCOMP;
+        match c.read_clock_frequency() {
+            Frequency::M20 => Some(IfdVersion::V1),
+            Frequency::M17 | Frequency::M50_30 => Some(IfdVersion::V2),
+            _ => None,
+        }
+    }
+
+    /// Remove (CS)ME/TXE read and write access to all other regions, leaving
+    /// it access to its own region and, on IFD v2, read access to the
+    /// descriptor, as `me_cleaner -d` does.
+    pub fn restrict_me_access(&mut self) -> Result<(), String> {
+        let v = self.version();
+        let Some(m) = self.masters.get_mut(ME_MASTER) else {
+            return Err("no (CS)ME master in descriptor".into());
+        };
+        *m = match v {
+            Some(IfdVersion::V1) => {
+                // The lower 16 bits hold the requester ID.
+                let r = FlashMasterV1::from_bits(*m & 0xffff);
+                r.with_read_me(true).with_write_me(true).into_bits()
+            }
+            Some(IfdVersion::V2) => FlashMasterV2::new()
+                .with_read_fd(true)
+                .with_read_me(true)
+                .with_write_me(true)
+                .into_bits(),
+            None => return Err("unknown descriptor version".into()),
+        };
+        Ok(())
+    }
+}
+
 impl IFD {
     pub fn to_vec(self) -> Vec<u8> {
-        let mut res = vec![EMPTY; SIZE];
+        let size = match (&self.upper_map, &self.oem) {
+            (None, None) => SIZE,
+            _ => FULL_SIZE,
+        };
+        // Start from the original bytes and patch what we know about.
+        let mut res = self.raw;
+        res.resize(size, EMPTY);
         let components_offset = self.header.flmap0.fcba();
         let regions_offset = self.header.flmap0.frba();
         let masters_offset = self.header.flmap1.fmba();
@@ -653,7 +1167,7 @@ impl IFD {
         for (o, b) in self.components.as_bytes().iter().enumerate() {
             res[components_offset + o] = *b;
         }
-        for (o, b) in self.regions.as_bytes().iter().enumerate() {
+        for (o, b) in self.regions.entries.as_bytes().iter().enumerate() {
             res[regions_offset + o] = *b;
         }
         for (o, b) in self.masters.as_bytes().iter().enumerate() {
@@ -667,6 +1181,18 @@ impl IFD {
             res[mch_straps_offset + o] = *b;
         }
 
+        if let Some(m) = self.upper_map {
+            let o = UPPER_MAP_OFFSET;
+            res[o..o + 4].copy_from_slice(m.as_bytes());
+            let vtba = m.vtba();
+            for (o, b) in self.vscc.as_bytes().iter().enumerate() {
+                res[vtba + o] = *b;
+            }
+        }
+        if let Some(oem) = &self.oem {
+            res[OEM_OFFSET..OEM_OFFSET + OEM_SIZE].copy_from_slice(oem);
+        }
+
         res
     }
 }
@@ -736,10 +1262,12 @@ impl IFD {
                 "Regions @ {regions_offset:08x}"
             )));
         }
-        let regions = match Regions::read_from_prefix(&data[regions_offset..]) {
-            Ok((r, _)) => r,
-            Err(e) => return Err(IfdError::ParserError(format!("{e:?}"))),
+        // The region table ends where the next section starts.
+        let max_regions = match masters_offset > regions_offset {
+            true => (masters_offset - regions_offset) / 4,
+            false => MAX_REGIONS,
         };
+        let regions = Regions::parse(&data[regions_offset..], header.flmap0.nr(), max_regions);
 
         let masters_end = masters_offset + REGION_COUNT * 4;
         if masters_offset > data.len() || masters_end > data.len() {
@@ -768,6 +1296,33 @@ impl IFD {
         let slice = &data[mch_straps_offset..mch_straps_end];
         let mch_straps = u8_slice_to_u32(slice);
 
+        // The upper map and OEM section are only in full 4K descriptors.
+        let (upper_map, vscc, oem) = if data.len() >= FULL_SIZE {
+            let (m, _) = match FLUMAP1::read_from_prefix(&data[UPPER_MAP_OFFSET..]) {
+                Ok(r) => r,
+                Err(e) => return Err(IfdError::ParserError(format!("FLUMAP1: {e:?}"))),
+            };
+            let vtba = m.vtba();
+            let vtl = m.vtl();
+            let vscc_end = vtba + vtl * core::mem::size_of::<VsccEntry>();
+            // The upper map is commonly left erased, meaning there is no table.
+            let vscc = if m.into_bits() == ERASED {
+                vec![]
+            } else if vscc_end > UPPER_MAP_OFFSET {
+                warn!("Ignoring VSCC table @ {vtba:08x}..{vscc_end:08x}, out of bounds");
+                vec![]
+            } else {
+                match <[VsccEntry]>::ref_from_bytes(&data[vtba..vscc_end]) {
+                    Ok(r) => r.to_vec(),
+                    Err(e) => return Err(IfdError::ParserError(format!("VSCC table: {e:?}"))),
+                }
+            };
+            let oem = data[OEM_OFFSET..OEM_OFFSET + OEM_SIZE].to_vec();
+            (Some(m), vscc, Some(oem))
+        } else {
+            (None, vec![], None)
+        };
+
         Ok(Self {
             header,
             components,
@@ -775,6 +1330,10 @@ impl IFD {
             masters,
             pch_straps,
             mch_straps,
+            upper_map,
+            vscc,
+            oem,
+            raw: data[..data.len().min(FULL_SIZE)].to_vec(),
         })
     }
 }
@@ -797,3 +1356,108 @@ fn to_vec_gen3() {
     let ifd = IFD::parse(IFD_DATA_GEN3).unwrap();
     assert_eq!(ifd.to_vec(), IFD_DATA_GEN3);
 }
+
+#[cfg(test)]
+// Extend a descriptor to 4K with an upper map, one VSCC entry and OEM data.
+fn full_ifd(data: &[u8]) -> Vec<u8> {
+    let mut full = data.to_vec();
+    full.resize(FULL_SIZE, EMPTY);
+    // VSCC table @ 0xdf0, 2 dwords
+    full[UPPER_MAP_OFFSET..UPPER_MAP_OFFSET + 4].copy_from_slice(&[0xdf, 0x02, 0x00, 0x00]);
+    // Winbond W25Q128
+    full[0xdf0..0xdf8].copy_from_slice(&[0xef, 0x40, 0x18, 0x00, 0x15, 0x20, 0x15, 0x20]);
+    full[OEM_OFFSET..OEM_OFFSET + 4].copy_from_slice(b"OEM!");
+    full
+}
+
+#[test]
+/// We should be able to write back the original data 1:1.
+fn to_vec_full() {
+    let full = full_ifd(IFD_DATA_GEN3);
+    let ifd = IFD::parse(&full).unwrap();
+    assert_eq!(ifd.vscc.len(), 1);
+    let e = ifd.vscc[0];
+    assert_eq!(e.jid.vendor(), 0xef);
+    assert_eq!(e.vscc.lower_erase_opcode(), 0x20);
+    assert!(matches!(e.vscc.upper_erase_size(), EraseSize::K4));
+    assert_eq!(ifd.to_vec(), full);
+}
+
+#[test]
+fn to_vec_keeps_unknown() {
+    let mut full = full_ifd(IFD_DATA_GEN3);
+    full[0xa00..0xa04].copy_from_slice(b"ABCD");
+    full[0xc00..0xc04].copy_from_slice(b"EFGH");
+    let mut ifd = IFD::parse(&full).unwrap();
+    ifd.set_hap(true);
+    let data = ifd.to_vec();
+    assert_eq!(&data[0xa00..0xa04], b"ABCD");
+    assert_eq!(&data[0xc00..0xc04], b"EFGH");
+    assert!(IFD::parse(&data).unwrap().hap());
+}
+
+#[test]
+fn erased_upper_map() {
+    let mut full = full_ifd(IFD_DATA_GEN3);
+    full[UPPER_MAP_OFFSET..UPPER_MAP_OFFSET + 4].fill(EMPTY);
+    let ifd = IFD::parse(&full).unwrap();
+    assert!(ifd.vscc.is_empty());
+}
+
+#[test]
+fn regions_gen2() {
+    let ifd = IFD::parse(IFD_DATA_GEN2).unwrap();
+    let r = ifd.regions.iter().collect::<Vec<Region>>();
+    assert_eq!(r.len(), 5);
+    assert!(r[2].is_used());
+    assert!(!r[4].is_used());
+    assert_eq!(ifd.regions.range(RegionKind::Me), 0x3000..0x500000);
+}
+
+#[test]
+fn regions_gen3() {
+    let ifd = IFD::parse(IFD_DATA_GEN3).unwrap();
+    assert_eq!(ifd.regions.iter().count(), 10);
+    let ec = ifd.regions.get(RegionKind::Ec).unwrap();
+    assert!(!ec.is_used());
+    assert!(ifd.regions.get(RegionKind::Ptt).is_none());
+}
+
+#[test]
+fn restrict_me_access() {
+    let mut ifd = IFD::parse(IFD_DATA_GEN2).unwrap();
+    assert_eq!(ifd.version(), Some(IfdVersion::V1));
+    ifd.restrict_me_access().unwrap();
+    assert_eq!(ifd.masters[ME_MASTER], 0x0404_0000);
+
+    let mut ifd = IFD::parse(IFD_DATA_GEN3).unwrap();
+    assert_eq!(ifd.version(), Some(IfdVersion::V2));
+    ifd.restrict_me_access().unwrap();
+    assert_eq!(ifd.masters[ME_MASTER], 0x0040_0500);
+}
+
+#[test]
+fn disable_me_gen3() {
+    let mut ifd = IFD::parse(IFD_DATA_GEN3).unwrap();
+    let v = |major| {
+        Some(Version {
+            major,
+            minor: 0,
+            patch: 0,
+            build: 0,
+        })
+    };
+    ifd.set_hap(false);
+    ifd.disable_me_gen3(Variant::ME, &v(11)).unwrap();
+    assert!(ifd.hap());
+    ifd.set_hap(false);
+    ifd.disable_me_gen3(Variant::TXE, &v(3)).unwrap();
+    assert!(ifd.hap());
+    ifd.set_hap(false);
+    assert!(ifd.disable_me_gen3(Variant::TXE, &v(11)).is_err());
+    let e = ifd.disable_me_gen3(Variant::SPS, &v(5)).unwrap_err();
+    assert!(e.contains("unverified"));
+    assert!(ifd.disable_me_gen3(Variant::ME, &v(42)).is_err());
+    assert!(ifd.disable_me_gen3(Variant::ME, &None).is_err());
+    assert!(!ifd.hap());
+}
@@ -0,0 +1,329 @@
+//! Flash master access permissions
+//!
+//! Each master, i.e., a component accessing the flash, has a register in the
+//! masters section granting it read and write access per region. On IFD v1,
+//! the lower 16 bits hold a requester ID, followed by read and write bits for
+//! 8 regions. IFD v2 has read and write bits for 12 regions in the upper 24
+//! bits, and those for regions 12 to 15 in the lowest byte.
+//!
+//! The presets follow coreboot `util/ifdtool/`.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+use crate::ifd::{IFD, IfdVersion, MAX_REGIONS, RegionKind};
+
+/// A component accessing the flash, identified by its master register
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Master {
+    /// Host CPU/BIOS
+    Host,
+    /// (CS)ME/TXE
+    Me,
+    Gbe,
+    /// Embedded controller, IFD v2 only
+    Ec,
+}
+
+impl Master {
+    /// Index of the FLMSTRx register, starting at 0
+    pub fn index(self) -> usize {
+        match self {
+            Self::Host => 0,
+            Self::Me => 1,
+            Self::Gbe => 2,
+            // FLMSTR4 is reserved.
+            Self::Ec => 4,
+        }
+    }
+
+    /// Masters defined for the given descriptor version
+    pub fn all(v: IfdVersion) -> &'static [Self] {
+        match v {
+            IfdVersion::V1 => &[Self::Host, Self::Me, Self::Gbe],
+            IfdVersion::V2 => &[Self::Host, Self::Me, Self::Gbe, Self::Ec],
+        }
+    }
+}
+
+impl Display for Master {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Host => "Host CPU/BIOS",
+            Self::Me => "(CS)ME",
+            Self::Gbe => "GbE",
+            Self::Ec => "EC",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Access {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = if self.read { "r" } else { "-" };
+        let w = if self.write { "w" } else { "-" };
+        write!(f, "{r}{w}")
+    }
+}
+
+/// Permissions of all masters for all regions in the region table
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccessMatrix {
+    pub regions: Vec<RegionKind>,
+    /// One row per master, one column per region
+    pub access: Vec<(Master, Vec<Access>)>,
+}
+
+impl Display for AccessMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let h = self
+            .regions
+            .iter()
+            .map(|r| format!("{:>3}", r.index()))
+            .collect::<String>();
+        write!(f, "  {:14}{h}", "")?;
+        for (m, a) in &self.access {
+            let l = a.iter().map(|a| format!(" {a}")).collect::<String>();
+            write!(f, "\n  {:14}{l}", m.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+// Bits for read and write access to a region, if the version supports it.
+fn access_bits(v: IfdVersion, region: usize) -> Option<(u32, u32)> {
+    let r = region as u32;
+    match v {
+        IfdVersion::V1 if r < 8 => Some((16 + r, 24 + r)),
+        IfdVersion::V2 if r < 12 => Some((8 + r, 20 + r)),
+        IfdVersion::V2 if r < MAX_REGIONS as u32 => Some((r - 12, r - 8)),
+        _ => None,
+    }
+}
+
+impl IFD {
+    fn access_params(
+        &self,
+        master: Master,
+        region: RegionKind,
+    ) -> Result<(IfdVersion, (u32, u32)), String> {
+        let Some(v) = self.version() else {
+            return Err("unknown descriptor version".into());
+        };
+        if !Master::all(v).contains(&master) || master.index() >= self.masters.len() {
+            return Err(format!("no {master} master in descriptor"));
+        }
+        match access_bits(v, region.index()) {
+            Some(b) => Ok((v, b)),
+            None => Err(format!("no access control for {region} region")),
+        }
+    }
+
+    /// Get the access permissions of a master for a region.
+    pub fn access(&self, master: Master, region: RegionKind) -> Result<Access, String> {
+        let (_, (r, w)) = self.access_params(master, region)?;
+        let m = self.masters[master.index()];
+        Ok(Access {
+            read: m >> r & 1 == 1,
+            write: m >> w & 1 == 1,
+        })
+    }
+
+    /// Set the access permissions of a master for a region.
+    pub fn set_access(
+        &mut self,
+        master: Master,
+        region: RegionKind,
+        access: Access,
+    ) -> Result<(), String> {
+        let (_, (r, w)) = self.access_params(master, region)?;
+        let m = &mut self.masters[master.index()];
+        *m = *m & !(1 << r) & !(1 << w);
+        *m |= u32::from(access.read) << r | u32::from(access.write) << w;
+        Ok(())
+    }
+
+    /// Get the permissions of all masters for all regions in the table.
+    pub fn access_matrix(&self) -> Result<AccessMatrix, String> {
+        let Some(v) = self.version() else {
+            return Err("unknown descriptor version".into());
+        };
+        let regions = self
+            .regions
+            .iter()
+            .map(|r| r.kind)
+            .filter(|k| access_bits(v, k.index()).is_some())
+            .collect::<Vec<RegionKind>>();
+        let access = Master::all(v)
+            .iter()
+            .filter(|m| m.index() < self.masters.len())
+            .map(|m| {
+                let a = regions.iter().map(|r| self.access(*m, *r));
+                a.collect::<Result<Vec<Access>, String>>().map(|a| (*m, a))
+            })
+            .collect::<Result<Vec<(Master, Vec<Access>)>, String>>()?;
+        Ok(AccessMatrix { regions, access })
+    }
+
+    fn master_mut(&mut self, master: Master) -> Result<&mut u32, String> {
+        match self.masters.get_mut(master.index()) {
+            Some(m) => Ok(m),
+            None => Err(format!("no {master} master in descriptor")),
+        }
+    }
+
+    /// Grant all masters full access to all regions, like `ifdtool -u`.
+    pub fn unlock(&mut self) -> Result<(), String> {
+        match self.version() {
+            Some(IfdVersion::V1) => {
+                *self.master_mut(Master::Host)? = 0xffff_0000;
+                *self.master_mut(Master::Me)? = 0xffff_0000;
+                // Keep the chipset specific requester ID.
+                let m = self.master_mut(Master::Gbe)?;
+                *m = 0x0808_0000 | (*m & 0xffff);
+            }
+            Some(IfdVersion::V2) => {
+                for master in Master::all(IfdVersion::V2) {
+                    let m = self.master_mut(*master)?;
+                    *m = 0xffff_ff00 | (*m & 0xff);
+                }
+            }
+            None => return Err("unknown descriptor version".into()),
+        }
+        Ok(())
+    }
+
+    /// Restrict each master to the regions it needs, like `ifdtool -l`.
+    ///
+    /// The host CPU/BIOS may read the descriptor and read and write the BIOS,
+    /// GbE and platform data regions, as well as read the EC region. All other
+    /// masters may read the descriptor and read and write their own region,
+    /// the ME also reading the GbE region.
+    pub fn lock(&mut self) -> Result<(), String> {
+        let Some(v) = self.version() else {
+            return Err("unknown descriptor version".into());
+        };
+        match v {
+            IfdVersion::V1 => {
+                *self.master_mut(Master::Host)? = 0;
+                *self.master_mut(Master::Me)? = 0;
+                // Requester ID
+                *self.master_mut(Master::Gbe)? = 0x118;
+            }
+            IfdVersion::V2 => {
+                for master in Master::all(v) {
+                    *self.master_mut(*master)? &= 0xff;
+                }
+            }
+        }
+        let used = |k: RegionKind| self.regions.get(k).is_some_and(|r| r.is_used());
+        let (gbe, pd, ec) = (
+            used(RegionKind::Gbe),
+            used(RegionKind::PlatformData),
+            used(RegionKind::Ec),
+        );
+        let (r, rw) = (
+            Access {
+                read: true,
+                write: false,
+            },
+            Access {
+                read: true,
+                write: true,
+            },
+        );
+        let mut grants = vec![
+            (Master::Host, RegionKind::Descriptor, r),
+            (Master::Host, RegionKind::Bios, rw),
+            (Master::Me, RegionKind::Descriptor, r),
+            (Master::Me, RegionKind::Me, rw),
+        ];
+        if gbe {
+            grants.extend([
+                (Master::Host, RegionKind::Gbe, rw),
+                (Master::Me, RegionKind::Gbe, r),
+                (Master::Gbe, RegionKind::Descriptor, r),
+                (Master::Gbe, RegionKind::Gbe, rw),
+            ]);
+        }
+        if pd {
+            grants.push((Master::Host, RegionKind::PlatformData, rw));
+        }
+        if ec && v == IfdVersion::V2 {
+            grants.extend([
+                (Master::Host, RegionKind::Ec, r),
+                (Master::Ec, RegionKind::Descriptor, r),
+                (Master::Ec, RegionKind::Ec, rw),
+            ]);
+        }
+        for (m, k, a) in grants {
+            self.set_access(m, k, a)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+static IFD_DATA_V1: &[u8] = include_bytes!("../../tests/me8.ifd");
+#[cfg(test)]
+static IFD_DATA_V2: &[u8] = include_bytes!("../../tests/me11.ifd");
+
+#[test]
+fn access_matrix() {
+    let ifd = IFD::parse(IFD_DATA_V2).unwrap();
+    let m = ifd.access_matrix().unwrap();
+    assert_eq!(m.regions.len(), 10);
+    assert_eq!(m.access.len(), 4);
+    let (master, a) = &m.access[1];
+    assert_eq!(*master, Master::Me);
+    // ME may read and write the ME and GbE regions.
+    assert_eq!(
+        a[2],
+        Access {
+            read: true,
+            write: true
+        }
+    );
+    assert_eq!(
+        a[3],
+        Access {
+            read: true,
+            write: true
+        }
+    );
+    assert_eq!(a[1], Access::default());
+}
+
+#[test]
+fn set_access() {
+    let mut ifd = IFD::parse(IFD_DATA_V1).unwrap();
+    let a = Access {
+        read: true,
+        write: false,
+    };
+    ifd.set_access(Master::Gbe, RegionKind::Bios, a).unwrap();
+    assert_eq!(ifd.access(Master::Gbe, RegionKind::Bios).unwrap(), a);
+    assert!(ifd.access(Master::Ec, RegionKind::Bios).is_err());
+    assert!(ifd.access(Master::Host, RegionKind::Ptt).is_err());
+}
+
+#[test]
+fn unlock_lock() {
+    let mut ifd = IFD::parse(IFD_DATA_V1).unwrap();
+    ifd.unlock().unwrap();
+    assert_eq!(ifd.masters[..3], [0xffff_0000, 0xffff_0000, 0x0808_0118]);
+    ifd.lock().unwrap();
+    assert_eq!(ifd.masters[..3], [0x0a0b_0000, 0x040d_0000, 0x0809_0118]);
+
+    let mut ifd = IFD::parse(IFD_DATA_V2).unwrap();
+    ifd.unlock().unwrap();
+    assert_eq!(ifd.masters[0], 0xffff_ff00);
+    ifd.lock().unwrap();
+    assert_eq!(ifd.masters[..3], [0x00a0_0b00, 0x0040_0d00, 0x0080_0900]);
+}
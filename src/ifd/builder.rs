@@ -0,0 +1,435 @@
+//! Building a descriptor from scratch
+//!
+//! For board bring-up, a descriptor can be built from a specification rather
+//! than copied from a vendor image, e.g., in TOML:
+//!
+//! ```toml
+//! version = "V2"
+//! components = ["16M"]
+//! family = "Series100"
+//!
+//! [[region]]
+//! name = "me"
+//! start = 0x3000
+//! size = 0x6fd000
+//!
+//! [[region]]
+//! name = "bios"
+//! start = "0x700000"
+//! size = "9M"
+//!
+//! [[master]]
+//! name = "Host"
+//! read = ["fd", "bios"]
+//! write = ["bios"]
+//!
+//! [straps]
+//! PCHSTRP9 = 0x00000c80
+//! HAP = 1
+//! ```
+//!
+//! Numbers may be given as integers or as strings in hex with `0x` prefix or
+//! decimal, with an optional K or M suffix. JSON specifications have the same
+//! structure.
+//!
+//! The descriptor region always covers the first 4K. Masters not listed get
+//! the permissions `ifdtool -l` sets. Straps are given either as raw values,
+//! named `PCHSTRPn` or `MCHSTRPn`, or as fields by name, which requires the
+//! chipset `family`; fields are applied after raw values. The numbers of
+//! straps default to those found on 7 and 100 series boards for IFD v1 and v2,
+//! respectively, and all straps not given are 0.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::EMPTY;
+use crate::ifd::{
+    Components, ERASED, FLMAP0, FLMAP1, FLMAP2, FLMAP3, FLUMAP1, FULL_SIZE, FlashComponentConfig,
+    FlashInvalidInstructions, FlashRegion, Frequency, Header, IFD, IfdVersion, MAGIC, OEM_SIZE,
+    RegionKind, Regions,
+    access::{Access, Master},
+    layout::parse_num,
+    straps::{ChipsetFamily, StrapField, StrapSection},
+};
+
+/// An integer, or a string with a number as in flashmaps, e.g., "0x1000" or "16M"
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Number {
+    Int(usize),
+    Str(String),
+}
+
+impl Number {
+    pub fn value(&self) -> Result<usize, String> {
+        match self {
+            Self::Int(n) => Ok(*n),
+            Self::Str(s) => parse_num(s),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RegionSpec {
+    /// Short or flashmap name, e.g., "bios" or "SI_BIOS"
+    pub name: String,
+    pub start: Number,
+    pub size: Number,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MasterSpec {
+    pub name: Master,
+    /// Regions the master may read
+    #[serde(default)]
+    pub read: Vec<String>,
+    /// Regions the master may write
+    #[serde(default)]
+    pub write: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Spec {
+    pub version: IfdVersion,
+    /// Sizes of the flash components
+    pub components: Vec<Number>,
+    #[serde(default, rename = "region")]
+    pub regions: Vec<RegionSpec>,
+    #[serde(default, rename = "master")]
+    pub masters: Vec<MasterSpec>,
+    /// Number of PCH straps
+    pub pch_straps: Option<usize>,
+    /// Number of MCH straps
+    pub mch_straps: Option<usize>,
+    /// Chipset family, needed to set strap fields by name
+    pub family: Option<ChipsetFamily>,
+    #[serde(default)]
+    pub straps: BTreeMap<String, Number>,
+}
+
+impl Spec {
+    pub fn from_toml(s: &str) -> Result<Self, String> {
+        toml::from_str(s).map_err(|e| format!("TOML error: {e}"))
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        serde_json::from_str(s).map_err(|e| format!("JSON error: {e}"))
+    }
+}
+
+// Section offsets in 16 byte units and default sizes, following the samples
+struct Defaults {
+    fmba: u8,
+    fisba: u8,
+    fmsba: u8,
+    regions: usize,
+    masters: usize,
+    pch_straps: usize,
+    mch_straps: usize,
+}
+
+fn defaults(v: IfdVersion) -> Defaults {
+    match v {
+        IfdVersion::V1 => Defaults {
+            fmba: 0x06,
+            fisba: 0x10,
+            fmsba: 0x20,
+            regions: 5,
+            masters: 3,
+            pch_straps: 18,
+            mch_straps: 1,
+        },
+        IfdVersion::V2 => Defaults {
+            fmba: 0x08,
+            fisba: 0x10,
+            fmsba: 0x30,
+            regions: 10,
+            masters: 5,
+            pch_straps: 66,
+            mch_straps: 3,
+        },
+    }
+}
+
+const FCBA: u8 = 0x03;
+const FRBA: u8 = 0x04;
+// The MCH straps are followed by at most this many bytes before the VSCC table.
+const MCH_STRAPS_SPACE: usize = 0x100;
+
+fn flash_config(v: IfdVersion) -> FlashComponentConfig {
+    let c = FlashComponentConfig::new();
+    match v {
+        IfdVersion::V1 => c
+            .with_read_clock_frequency(Frequency::M20)
+            .with_fast_read_clock_frequency(Frequency::M20)
+            .with_write_erase_clock_frequency(Frequency::M20)
+            .with_read_id_status_clock_frequency(Frequency::M20),
+        IfdVersion::V2 => c
+            .with_read_clock_frequency(Frequency::M17)
+            .with_fast_read_support(true)
+            .with_fast_read_clock_frequency(Frequency::M48)
+            .with_write_erase_clock_frequency(Frequency::M48)
+            .with_read_id_status_clock_frequency(Frequency::M17),
+    }
+}
+
+fn region_kind(name: &str) -> Result<RegionKind, String> {
+    RegionKind::from_name(name).ok_or(format!("unknown region {name}"))
+}
+
+// Get a field covering a whole strap given its name, e.g., PCHSTRP10.
+fn raw_strap(name: &str) -> Option<StrapField> {
+    let u = name.to_uppercase();
+    let (section, n) = match u.strip_prefix("PCHSTRP") {
+        Some(n) => (StrapSection::Pch, n),
+        None => (StrapSection::Mch, u.strip_prefix("MCHSTRP")?),
+    };
+    Some(StrapField {
+        name: name.to_string(),
+        section,
+        strap: n.parse().ok()?,
+        bit: 0,
+        width: 32,
+        description: String::new(),
+    })
+}
+
+impl IFD {
+    /// Build a descriptor from a specification, see above.
+    pub fn from_spec(spec: &Spec) -> Result<Self, String> {
+        let v = spec.version;
+        let d = defaults(v);
+        let pch = spec.pch_straps.unwrap_or(d.pch_straps);
+        let mch = spec.mch_straps.unwrap_or(d.mch_straps);
+        let pch_space = (d.fmsba - d.fisba) as usize * 16 / 4;
+        if pch > pch_space {
+            return Err(format!("{pch} PCH straps given, space for {pch_space}"));
+        }
+        if mch > MCH_STRAPS_SPACE / 4 {
+            let max = MCH_STRAPS_SPACE / 4;
+            return Err(format!("{mch} MCH straps given, space for {max}"));
+        }
+
+        let mut kinds = vec![];
+        for r in &spec.regions {
+            let start = r.start.value()?;
+            let Some(end) = start.checked_add(r.size.value()?) else {
+                return Err(format!("region {} out of range", r.name));
+            };
+            kinds.push((region_kind(&r.name)?, start..end));
+        }
+        let n = kinds
+            .iter()
+            .map(|(k, _)| k.index() + 1)
+            .max()
+            .unwrap_or(0)
+            .max(d.regions);
+        let max_regions = (d.fmba - FRBA) as usize * 16 / 4;
+        if n > max_regions {
+            return Err(format!("IFD {v:?} supports up to {max_regions} regions"));
+        }
+        let mut entries = vec![FlashRegion::unused(); n];
+        entries[RegionKind::Descriptor.index()] = FlashRegion::from_range(0..FULL_SIZE);
+
+        // IFD v2 has NR reserved and 0.
+        let nr = match v {
+            IfdVersion::V1 => n - 1,
+            IfdVersion::V2 => 0,
+        };
+        let header = Header {
+            magic: MAGIC,
+            flmap0: FLMAP0::new()
+                .with_FCBA(FCBA)
+                .with_FRBA(FRBA)
+                .with_NR(nr as u8),
+            flmap1: FLMAP1::new()
+                .with_FMBA(d.fmba)
+                .with_NM(2)
+                .with_FISBA(d.fisba)
+                .with_ISL(pch as u8),
+            flmap2: FLMAP2::new().with_FMSBA(d.fmsba).with_MSL(mch as u8),
+            flmap3: FLMAP3::from_bits(ERASED),
+        };
+        let no_instructions = FlashInvalidInstructions {
+            inst1: 0,
+            inst2: 0,
+            inst3: 0,
+            inst4: 0,
+        };
+        let mut ifd = Self {
+            header,
+            components: Components {
+                FLCOMP: flash_config(v),
+                FLILL0: no_instructions,
+                FLILL1: no_instructions,
+            },
+            regions: Regions { entries },
+            masters: vec![0; d.masters],
+            pch_straps: vec![0; pch],
+            mch_straps: vec![0; mch],
+            upper_map: Some(FLUMAP1::from_bits(ERASED)),
+            vscc: vec![],
+            oem: Some(vec![EMPTY; OEM_SIZE]),
+            raw: vec![],
+        };
+
+        let sizes = spec
+            .components
+            .iter()
+            .map(|c| c.value())
+            .collect::<Result<Vec<usize>, String>>()?;
+        ifd.set_component_sizes(&sizes).map_err(|e| e.to_string())?;
+
+        for (kind, range) in kinds {
+            if kind == RegionKind::Descriptor {
+                if range != (0..FULL_SIZE) {
+                    return Err(format!("descriptor region must be {:08x?}", 0..FULL_SIZE));
+                }
+                continue;
+            }
+            ifd.set_region(kind, range).map_err(|e| e.to_string())?;
+        }
+
+        ifd.lock()?;
+        for m in &spec.masters {
+            let read = m
+                .read
+                .iter()
+                .map(|r| region_kind(r))
+                .collect::<Result<Vec<_>, String>>()?;
+            let write = m
+                .write
+                .iter()
+                .map(|r| region_kind(r))
+                .collect::<Result<Vec<_>, String>>()?;
+            for r in read.iter().chain(&write) {
+                if r.index() >= n {
+                    return Err(format!("{r} region not in region table"));
+                }
+            }
+            for kind in (0..n).filter_map(RegionKind::from_index) {
+                let a = Access {
+                    read: read.contains(&kind),
+                    write: write.contains(&kind),
+                };
+                ifd.set_access(m.name, kind, a)?;
+            }
+        }
+
+        let fields = spec.family.map(|f| f.fields()).unwrap_or_default();
+        let mut named = vec![];
+        for (name, value) in &spec.straps {
+            let value = value.value()?;
+            let value =
+                u32::try_from(value).map_err(|_| format!("{name}: {value:#x} too large"))?;
+            match raw_strap(name) {
+                Some(f) => ifd.set_strap_value(&f, value).map_err(|e| e.to_string())?,
+                None => named.push((name, value)),
+            }
+        }
+        for (name, value) in named {
+            if spec.family.is_none() {
+                return Err(format!("strap field {name} requires a chipset family"));
+            }
+            ifd.set_strap(&fields, name, value)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(ifd)
+    }
+}
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../../tests/me11.ifd");
+
+#[cfg(test)]
+const SPEC: &str = r#"
+version = "V2"
+components = ["16M"]
+family = "Series100"
+
+[[region]]
+name = "gbe"
+start = 0x1000
+size = "8K"
+
+[[region]]
+name = "me"
+start = 0x3000
+size = 0x6fd000
+
+[[region]]
+name = "SI_BIOS"
+start = "0x700000"
+size = "9M"
+
+[straps]
+PCHSTRP0 = 0x1
+HAP = 1
+"#;
+
+#[test]
+fn build_from_toml() {
+    let spec = Spec::from_toml(SPEC).unwrap();
+    let ifd = IFD::from_spec(&spec).unwrap();
+    let data = ifd.clone().to_vec();
+    assert_eq!(data.len(), FULL_SIZE);
+    let parsed = IFD::parse(&data).unwrap();
+    assert_eq!(parsed.version(), Some(IfdVersion::V2));
+    assert_eq!(parsed.component_sizes(), Ok(vec![0x100_0000]));
+    assert_eq!(parsed.pch_straps.len(), 66);
+    assert_eq!(parsed.pch_straps[0], 0x1_0001);
+    assert!(parsed.hap());
+    // The layout and default permissions match those of a vendor image.
+    let vendor = IFD::parse(IFD_DATA).unwrap();
+    let ranges = |i: &IFD| i.regions.iter().map(|r| r.range()).collect::<Vec<_>>();
+    assert_eq!(ranges(&parsed)[..4], ranges(&vendor)[..4]);
+    assert_eq!(parsed.masters[..3], [0x00a0_0b00, 0x0040_0d00, 0x0080_0900]);
+}
+
+#[test]
+fn build_from_json() {
+    let json = r#"{
+        "version": "V1",
+        "components": ["8M", 4194304],
+        "region": [
+            {"name": "me", "start": "0x1000", "size": "5M"},
+            {"name": "bios", "start": "0x501000", "size": "0x6ff000"}
+        ],
+        "master": [
+            {"name": "Host", "read": ["fd", "bios", "me"], "write": ["bios", "me"]}
+        ],
+        "straps": {"MCHSTRP0": 5}
+    }"#;
+    let spec = Spec::from_json(json).unwrap();
+    let ifd = IFD::from_spec(&spec).unwrap();
+    let parsed = IFD::parse(&ifd.to_vec()).unwrap();
+    assert_eq!(parsed.version(), Some(IfdVersion::V1));
+    assert_eq!(parsed.component_sizes(), Ok(vec![0x80_0000, 0x40_0000]));
+    assert_eq!(parsed.regions.entries.len(), 5);
+    assert_eq!(parsed.regions.bios_range(), 0x50_1000..0xc0_0000);
+    let host = parsed.access(Master::Host, RegionKind::Me).unwrap();
+    assert!(host.read && host.write);
+    assert!(!parsed.access(Master::Me, RegionKind::Bios).unwrap().read);
+    assert_eq!(parsed.mch_straps, [5]);
+}
+
+#[test]
+fn build_errors() {
+    let spec = Spec::from_toml(SPEC).unwrap();
+    let mut s = spec.clone();
+    s.family = None;
+    assert!(IFD::from_spec(&s).unwrap_err().contains("chipset family"));
+    let mut s = spec.clone();
+    s.regions[1].size = Number::Int(0x7fe000);
+    assert!(IFD::from_spec(&s).unwrap_err().contains("overlaps"));
+    let mut s = spec.clone();
+    s.regions[0].name = "foo".into();
+    assert!(IFD::from_spec(&s).is_err());
+    let mut s = spec.clone();
+    s.regions[1].size = Number::Int(usize::MAX);
+    assert!(IFD::from_spec(&s).unwrap_err().contains("out of range"));
+    let mut s = spec;
+    s.components = vec![Number::Str("3M".into())];
+    assert!(IFD::from_spec(&s).is_err());
+}
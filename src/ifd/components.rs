@@ -0,0 +1,233 @@
+//! Flash components
+//!
+//! A board may have one or two SPI flash chips, called components, which the
+//! chipset maps one after the other. The descriptor holds the number of
+//! components in FLMAP0 and their densities in FLCOMP. IFD v1 uses 3 bits per
+//! density, allowing for up to 16MB, while IFD v2 uses 4 bits, allowing for
+//! up to 64MB, as coreboot `util/ifdtool/` decodes them.
+//!
+//! Chips are commonly dumped separately with an external programmer, so an
+//! image can be split into one file per chip and merged back.
+
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+use crate::ifd::{FlashComponentConfig, IFD, IfdVersion};
+
+/// The chipset supports up to 2 components.
+pub const MAX_COMPONENTS: usize = 2;
+
+// The smallest density is 512K, each step doubling it.
+const MIN_DENSITY: usize = 512 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum ComponentError {
+    /// The descriptor version, and thus the density encoding, is unknown.
+    UnknownVersion,
+    /// The density bits of a component do not encode a size.
+    UnknownDensity { component: usize, bits: u32 },
+    /// The size cannot be encoded as a density.
+    UnsupportedSize(usize),
+    /// There are no or too many components.
+    InvalidCount(usize),
+    /// The data does not match the sizes of the components.
+    SizeMismatch { expected: usize, actual: usize },
+}
+
+impl Display for ComponentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownVersion => write!(f, "unknown descriptor version"),
+            Self::UnknownDensity { component, bits } => {
+                write!(f, "component {component} has unknown density {bits:#x}")
+            }
+            Self::UnsupportedSize(s) => write!(f, "size {s:#x} not supported for a component"),
+            Self::InvalidCount(n) => {
+                write!(f, "{n} components given, expected 1 to {MAX_COMPONENTS}")
+            }
+            Self::SizeMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "size {actual:#x} does not match components ({expected:#x})"
+                )
+            }
+        }
+    }
+}
+
+// Number of density bits per component and the largest density code
+fn density_format(v: IfdVersion) -> (u32, u32) {
+    match v {
+        IfdVersion::V1 => (3, 0b101),
+        IfdVersion::V2 => (4, 0b111),
+    }
+}
+
+/// Format a size in bytes as a human readable string, e.g., "16MB".
+pub fn format_size(s: usize) -> String {
+    match s {
+        s if s >= 1024 * 1024 && s.is_multiple_of(1024 * 1024) => format!("{}MB", s >> 20),
+        s if s >= 1024 && s.is_multiple_of(1024) => format!("{}K", s >> 10),
+        s => format!("{s}B"),
+    }
+}
+
+impl IFD {
+    /// Number of flash components
+    pub fn component_count(&self) -> usize {
+        self.header.flmap0.nc()
+    }
+
+    fn density_params(&self) -> Result<(u32, u32), ComponentError> {
+        self.version()
+            .map(density_format)
+            .ok_or(ComponentError::UnknownVersion)
+    }
+
+    /// Get the sizes of the flash components in bytes.
+    pub fn component_sizes(&self) -> Result<Vec<usize>, ComponentError> {
+        let (width, max) = self.density_params()?;
+        let bits = self.components.FLCOMP.into_bits();
+        let mask = (1 << width) - 1;
+        (0..self.component_count())
+            .map(|i| {
+                let d = bits >> (i as u32 * width) & mask;
+                match d {
+                    d if d <= max => Ok(MIN_DENSITY << d),
+                    _ => Err(ComponentError::UnknownDensity {
+                        component: i + 1,
+                        bits: d,
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Set the number and sizes of the flash components. The sizes must be
+    /// powers of 2 supported by the descriptor version.
+    pub fn set_component_sizes(&mut self, sizes: &[usize]) -> Result<(), ComponentError> {
+        let n = sizes.len();
+        if n == 0 || n > MAX_COMPONENTS {
+            return Err(ComponentError::InvalidCount(n));
+        }
+        let (width, max) = self.density_params()?;
+        let mut bits = self.components.FLCOMP.into_bits();
+        for (i, s) in sizes.iter().enumerate() {
+            let code = (0..=max)
+                .find(|d| MIN_DENSITY << d == *s)
+                .ok_or(ComponentError::UnsupportedSize(*s))?;
+            let shift = i as u32 * width;
+            bits = bits & !(((1 << width) - 1) << shift) | code << shift;
+        }
+        self.components.FLCOMP = FlashComponentConfig::from_bits(bits);
+        self.header.flmap0 = self.header.flmap0.with_NC((n - 1) as u8);
+        Ok(())
+    }
+
+    /// Check that the components add up to the size of the image.
+    pub fn check_components(&self, size: usize) -> Result<(), ComponentError> {
+        let expected = self.component_sizes()?.iter().sum();
+        if expected != size {
+            return Err(ComponentError::SizeMismatch {
+                expected,
+                actual: size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Split an image into one image per flash component.
+    pub fn split<'a>(&self, data: &'a [u8]) -> Result<Vec<&'a [u8]>, ComponentError> {
+        self.check_components(data.len())?;
+        let mut res = vec![];
+        let mut rest = data;
+        for s in self.component_sizes()? {
+            let (c, r) = rest.split_at(s);
+            res.push(c);
+            rest = r;
+        }
+        Ok(res)
+    }
+}
+
+/// Merge per-component images back into one image. The descriptor in the
+/// first component must describe the components given.
+pub fn merge(chips: &[&[u8]]) -> Result<Vec<u8>, String> {
+    let Some(first) = chips.first() else {
+        return Err(ComponentError::InvalidCount(0).to_string());
+    };
+    let ifd = IFD::parse(first).map_err(|e| format!("no IFD in first component: {e:?}"))?;
+    let sizes = ifd.component_sizes().map_err(|e| e.to_string())?;
+    if sizes.len() != chips.len() {
+        let (n, c) = (sizes.len(), chips.len());
+        return Err(format!("descriptor has {n} component(s), {c} given"));
+    }
+    for (i, (s, c)) in sizes.iter().zip(chips).enumerate() {
+        if *s != c.len() {
+            let e = ComponentError::SizeMismatch {
+                expected: *s,
+                actual: c.len(),
+            };
+            return Err(format!("component {}: {e}", i + 1));
+        }
+    }
+    Ok(chips.concat())
+}
+
+#[cfg(test)]
+static IFD_DATA_V1: &[u8] = include_bytes!("../../tests/me8.ifd");
+#[cfg(test)]
+static IFD_DATA_V2: &[u8] = include_bytes!("../../tests/me11.ifd");
+
+#[test]
+fn component_sizes() {
+    let ifd = IFD::parse(IFD_DATA_V2).unwrap();
+    assert_eq!(ifd.component_sizes(), Ok(vec![0x100_0000]));
+    assert!(ifd.check_components(0x100_0000).is_ok());
+    assert_eq!(
+        ifd.check_components(0x80_0000),
+        Err(ComponentError::SizeMismatch {
+            expected: 0x100_0000,
+            actual: 0x80_0000
+        })
+    );
+    assert_eq!(format_size(0x100_0000), "16MB");
+    assert_eq!(format_size(0x8_0000), "512K");
+}
+
+#[test]
+fn set_component_sizes() {
+    let mut ifd = IFD::parse(IFD_DATA_V2).unwrap();
+    // IFD v2 supports 32MB components.
+    ifd.set_component_sizes(&[0x200_0000, 0x80_0000]).unwrap();
+    let parsed = IFD::parse(&ifd.to_vec()).unwrap();
+    assert_eq!(parsed.component_count(), 2);
+    assert_eq!(parsed.component_sizes(), Ok(vec![0x200_0000, 0x80_0000]));
+    assert_eq!(parsed.flash_size(), Some(0x280_0000));
+
+    let mut ifd = IFD::parse(IFD_DATA_V1).unwrap();
+    assert_eq!(
+        ifd.set_component_sizes(&[0x200_0000]),
+        Err(ComponentError::UnsupportedSize(0x200_0000))
+    );
+    assert_eq!(
+        ifd.set_component_sizes(&[0x1000; 3]),
+        Err(ComponentError::InvalidCount(3))
+    );
+}
+
+#[test]
+fn split_merge() {
+    let mut ifd = IFD::parse(IFD_DATA_V2).unwrap();
+    ifd.set_component_sizes(&[0x10_0000, 0x8_0000]).unwrap();
+    let mut data = vec![0x5a; 0x18_0000];
+    let d = ifd.clone().to_vec();
+    data[..d.len()].copy_from_slice(&d);
+    let chips = ifd.split(&data).unwrap();
+    assert_eq!(chips.len(), 2);
+    assert_eq!(chips[1].len(), 0x8_0000);
+    assert_eq!(merge(&chips).unwrap(), data);
+    assert!(merge(&chips[..1]).is_err());
+    assert!(merge(&[chips[0], &chips[1][..0x1000]]).is_err());
+    assert!(ifd.split(&data[..0x10_0000]).is_err());
+}
@@ -0,0 +1,233 @@
+//! Region contents
+//!
+//! Extracting the regions of a full image, injecting region contents and
+//! moving them along with a new layout, as coreboot `ifdtool -x`, `-i` and
+//! `-n` do. The BIOS region is mapped to end at 4GB, so its contents are kept
+//! at the end of the region when they are smaller than it or when the region
+//! is resized. The contents of all other regions start at the region start.
+
+use core::ops::Range;
+use log::warn;
+
+use crate::EMPTY;
+use crate::ifd::{
+    IFD, RegionKind,
+    layout::{LayoutEntry, RegionError},
+};
+
+// Align contents of the given length within a region.
+fn align(kind: RegionKind, r: &Range<usize>, len: usize) -> Range<usize> {
+    match kind {
+        RegionKind::Bios => r.end - len..r.end,
+        _ => r.start..r.start + len,
+    }
+}
+
+impl IFD {
+    /// Get the contents of all regions in use.
+    pub fn extract_regions<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> Result<Vec<(RegionKind, &'a [u8])>, String> {
+        self.regions
+            .iter()
+            .filter(|r| r.is_used())
+            .map(|r| {
+                let range = r.range();
+                match data.get(range.clone()) {
+                    Some(d) => Ok((r.kind, d)),
+                    None => {
+                        let s = data.len();
+                        let k = r.kind;
+                        Err(format!(
+                            "{k} region {range:08x?} exceeds image size {s:08x}"
+                        ))
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Write the contents of a region into an image. Contents smaller than
+    /// the region only overwrite part of it, see above. Injecting the
+    /// descriptor replaces `self` with the new one.
+    pub fn inject_region(
+        &mut self,
+        data: &mut [u8],
+        kind: RegionKind,
+        contents: &[u8],
+    ) -> Result<(), String> {
+        let Some(r) = self.regions.get(kind).filter(|r| r.is_used()) else {
+            return Err(format!("{kind} region not in descriptor"));
+        };
+        let range = r.range();
+        let (size, len) = (range.len(), contents.len());
+        if len > size {
+            return Err(format!(
+                "{len:#x} bytes do not fit into {kind} region of {size:#x} bytes"
+            ));
+        }
+        let s = data.len();
+        let Some(d) = data.get_mut(align(kind, &range, len)) else {
+            return Err(format!(
+                "{kind} region {range:08x?} exceeds image size {s:08x}"
+            ));
+        };
+        d.copy_from_slice(contents);
+        if kind == RegionKind::Descriptor {
+            *self = IFD::parse(&data[range]).map_err(|e| format!("injected descriptor: {e:?}"))?;
+        }
+        Ok(())
+    }
+
+    fn set_regions(&mut self, changes: &[(RegionKind, Range<usize>)]) -> Result<(), RegionError> {
+        // Clear all regions first, so that they may trade places.
+        for (k, _) in changes {
+            self.clear_region(*k)?;
+        }
+        for (k, r) in changes {
+            self.set_region(*k, r.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Apply a new layout to an image, moving the region contents along, and
+    /// return the new image. Regions that shrink are truncated, and regions
+    /// not in the layout stay where they are. The descriptor is updated in
+    /// both the image and `self`.
+    pub fn apply_layout(
+        &mut self,
+        data: &[u8],
+        entries: &[LayoutEntry],
+    ) -> Result<Vec<u8>, String> {
+        let old = self.clone();
+        let mut changes = vec![];
+        for e in entries {
+            let Some(kind) = RegionKind::from_name(&e.name) else {
+                return Err(format!("unknown region {}", e.name));
+            };
+            // ifdtool layouts list the descriptor, which cannot move.
+            if kind == RegionKind::Descriptor && old.regions.range(kind) == e.range {
+                continue;
+            }
+            if e.range.end > data.len() {
+                let (r, s) = (&e.range, data.len());
+                return Err(format!("{kind} region {r:08x?} exceeds image size {s:08x}"));
+            }
+            changes.push((kind, e.range.clone()));
+        }
+        if let Err(e) = self.set_regions(&changes) {
+            *self = old;
+            return Err(e.to_string());
+        }
+
+        let mut res = vec![EMPTY; data.len()];
+        for (kind, contents) in old.extract_regions(data)? {
+            let Some(r) = self.regions.get(kind) else {
+                continue;
+            };
+            let r = r.range();
+            let len = contents.len().min(r.len());
+            if len < contents.len() {
+                let n = contents.len();
+                warn!("{kind} region shrinks from {n:#x} to {len:#x} bytes, truncating");
+            }
+            let src = align(kind, &(0..contents.len()), len);
+            res[align(kind, &r, len)].copy_from_slice(&contents[src]);
+        }
+        let ifd = self.clone().to_vec();
+        res[..ifd.len()].copy_from_slice(&ifd);
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../../tests/me11.ifd");
+
+#[cfg(test)]
+fn test_image() -> Vec<u8> {
+    let mut data = vec![EMPTY; 0x100_0000];
+    data[..IFD_DATA.len()].copy_from_slice(IFD_DATA);
+    // ME at 0x3000..0x700000, BIOS at 0x700000..0x1000000
+    data[0x3000] = 0x11;
+    data[0x6f_ffff] = 0x12;
+    data[0x70_0000] = 0x21;
+    data[0xff_ffff] = 0x22;
+    data
+}
+
+#[test]
+fn inject_region() {
+    let mut data = test_image();
+    let mut ifd = IFD::parse(&data).unwrap();
+    ifd.inject_region(&mut data, RegionKind::Bios, &[0x5a; 0x1000])
+        .unwrap();
+    assert!(data[0xff_f000..].iter().all(|b| *b == 0x5a));
+    assert_eq!(data[0x70_0000], 0x21);
+    ifd.inject_region(&mut data, RegionKind::Me, &[0xa5; 0x10])
+        .unwrap();
+    assert_eq!(
+        data[0x3000..0x3011],
+        [[0xa5; 0x10].as_slice(), &[EMPTY]].concat()
+    );
+    assert!(
+        ifd.inject_region(&mut data, RegionKind::Gbe, &[0; 0x2001])
+            .is_err()
+    );
+    assert!(ifd.inject_region(&mut data, RegionKind::Ec, &[0]).is_err());
+}
+
+#[test]
+fn inject_descriptor() {
+    let mut data = test_image();
+    let mut ifd = IFD::parse(&data).unwrap();
+    let mut other = IFD::parse(IFD_DATA).unwrap();
+    other.set_hap(true);
+    let contents = other.to_vec();
+    ifd.inject_region(&mut data, RegionKind::Descriptor, &contents)
+        .unwrap();
+    assert!(ifd.hap());
+    // Writing back the descriptor keeps the injected one.
+    let new = ifd.to_vec();
+    assert_eq!(new[..contents.len()], contents);
+    assert_eq!(data[..new.len()], new);
+}
+
+#[test]
+fn apply_layout() {
+    let data = test_image();
+    let mut ifd = IFD::parse(&data).unwrap();
+    let entries = [
+        LayoutEntry {
+            name: "fd".into(),
+            range: 0..0x1000,
+        },
+        LayoutEntry {
+            name: "me".into(),
+            range: 0x3000..0x50_0000,
+        },
+        LayoutEntry {
+            name: "bios".into(),
+            range: 0x50_0000..0x100_0000,
+        },
+    ];
+    let new = ifd.apply_layout(&data, &entries).unwrap();
+    let parsed = IFD::parse(&new).unwrap();
+    assert_eq!(parsed.regions.me_range(), 0x3000..0x50_0000);
+    assert_eq!(parsed.regions.bios_range(), 0x50_0000..0x100_0000);
+    assert_eq!(ifd.regions.bios_range(), 0x50_0000..0x100_0000);
+    // The ME is truncated at its end, the BIOS kept at its end.
+    assert_eq!(new[0x3000], 0x11);
+    assert_eq!(new[0x6f_ffff], EMPTY);
+    assert_eq!(new[0x70_0000], 0x21);
+    assert_eq!(new[0xff_ffff], 0x22);
+    // The GbE region is not in the layout and stays.
+    assert_eq!(parsed.regions.range(RegionKind::Gbe), 0x1000..0x3000);
+
+    let bad = [LayoutEntry {
+        name: "me".into(),
+        range: 0x3000..0x80_0000,
+    }];
+    assert!(ifd.apply_layout(&data, &bad).is_err());
+    assert_eq!(ifd.regions.me_range(), 0x3000..0x50_0000);
+}
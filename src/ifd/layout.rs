@@ -0,0 +1,336 @@
+//! Editing the flash layout
+//!
+//! Regions are given as base and limit in units of 4K. When moving or
+//! resizing a region, it must stay within the flash components described by
+//! the descriptor and must not overlap any other region. The descriptor region
+//! itself is fixed at the start of the flash, so it can neither be moved nor
+//! resized. Note that only the layout in the descriptor is changed; moving the
+//! contents of the regions is up to the caller.
+//!
+//! The layout can be exported to and imported from the layout files of other
+//! tools, see [`flashprog`] and [`fmd`].
+
+use core::fmt::{self, Display};
+use core::ops::Range;
+use serde::{Deserialize, Serialize};
+
+use crate::check::Finding;
+use crate::ifd::{FlashRegion, IFD, RegionKind};
+
+pub mod flashprog;
+pub mod fmd;
+
+/// Regions start and end at multiples of 4K.
+pub const REGION_ALIGNMENT: usize = 4096;
+
+// Base and limit are 15 bit fields of 4K units.
+const MAX_FLASH_SIZE: usize = 0x8000 * REGION_ALIGNMENT;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum RegionError {
+    /// The region has no entry in the region table.
+    NotPresent(RegionKind),
+    /// The region cannot be changed.
+    Fixed(RegionKind),
+    /// The range is empty or does not start or end at a 4K boundary.
+    Misaligned(RegionKind, Range<usize>),
+    /// The range exceeds the flash size given by the component densities.
+    OutOfBounds {
+        kind: RegionKind,
+        range: Range<usize>,
+        size: usize,
+    },
+    /// The range overlaps another region.
+    Overlap {
+        kind: RegionKind,
+        range: Range<usize>,
+        other: RegionKind,
+        other_range: Range<usize>,
+    },
+}
+
+impl Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPresent(k) => write!(f, "{k} region not in region table"),
+            Self::Fixed(k) => write!(f, "{k} region cannot be changed"),
+            Self::Misaligned(k, r) => {
+                write!(f, "{k} region {r:08x?} not a non-empty range of 4K blocks")
+            }
+            Self::OutOfBounds { kind, range, size } => {
+                write!(
+                    f,
+                    "{kind} region {range:08x?} exceeds flash size {size:08x}"
+                )
+            }
+            Self::Overlap {
+                kind,
+                range,
+                other,
+                other_range,
+            } => write!(
+                f,
+                "{kind} region {range:08x?} overlaps {other} region {other_range:08x?}"
+            ),
+        }
+    }
+}
+
+impl FlashRegion {
+    /// Create a region entry for a range of 4K blocks.
+    pub fn from_range(r: Range<usize>) -> Self {
+        let base = (r.start / REGION_ALIGNMENT) as u32;
+        let limit = (r.end.saturating_sub(1) / REGION_ALIGNMENT) as u32;
+        Self::new().with_base(base).with_limit(limit)
+    }
+
+    /// Create an entry for an unused region, as ifdtool does.
+    pub fn unused() -> Self {
+        Self::new().with_base(0x7fff).with_limit(0)
+    }
+}
+
+impl IFD {
+    /// Total size of the flash components, if their densities are known.
+    pub fn flash_size(&self) -> Option<usize> {
+        self.component_sizes().ok().map(|s| s.iter().sum())
+    }
+
+    fn check_region(&self, kind: RegionKind, r: &Range<usize>) -> Result<(), RegionError> {
+        if kind == RegionKind::Descriptor {
+            return Err(RegionError::Fixed(kind));
+        }
+        if kind.index() >= self.regions.entries.len() {
+            return Err(RegionError::NotPresent(kind));
+        }
+        if r.is_empty()
+            || !r.start.is_multiple_of(REGION_ALIGNMENT)
+            || !r.end.is_multiple_of(REGION_ALIGNMENT)
+        {
+            return Err(RegionError::Misaligned(kind, r.clone()));
+        }
+        let size = self.flash_size().unwrap_or(MAX_FLASH_SIZE);
+        if r.end > size.min(MAX_FLASH_SIZE) {
+            return Err(RegionError::OutOfBounds {
+                kind,
+                range: r.clone(),
+                size,
+            });
+        }
+        let other = self
+            .regions
+            .iter()
+            .filter(|o| o.kind != kind && o.is_used())
+            .find(|o| {
+                let o = o.range();
+                o.start < r.end && r.start < o.end
+            });
+        if let Some(o) = other {
+            return Err(RegionError::Overlap {
+                kind,
+                range: r.clone(),
+                other: o.kind,
+                other_range: o.range(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Move and/or resize a region. The range must be 4K aligned, within the
+    /// flash and must not overlap other regions.
+    pub fn set_region(&mut self, kind: RegionKind, r: Range<usize>) -> Result<(), RegionError> {
+        self.check_region(kind, &r)?;
+        self.regions.entries[kind.index()] = FlashRegion::from_range(r);
+        Ok(())
+    }
+
+    /// Mark a region as unused.
+    pub fn clear_region(&mut self, kind: RegionKind) -> Result<(), RegionError> {
+        if kind == RegionKind::Descriptor {
+            return Err(RegionError::Fixed(kind));
+        }
+        match self.regions.entries.get_mut(kind.index()) {
+            Some(e) => *e = FlashRegion::unused(),
+            None => return Err(RegionError::NotPresent(kind)),
+        }
+        Ok(())
+    }
+
+    /// Shrink the ME region to the given size, rounded up to 4K, e.g., after
+    /// truncating the ME firmware, and return the new range.
+    ///
+    /// With `extend_bios`, the BIOS region is extended downwards to cover the
+    /// space freed up if it directly follows the ME region. The BIOS is mapped
+    /// to end at 4GB, so its data stays where it is, at the end of the region.
+    pub fn shrink_me(
+        &mut self,
+        size: usize,
+        extend_bios: bool,
+    ) -> Result<Range<usize>, RegionError> {
+        let me = RegionKind::Me;
+        let Some(r) = self.regions.get(me).filter(|r| r.is_used()) else {
+            return Err(RegionError::NotPresent(me));
+        };
+        let old = r.range();
+        let end = old.start + size.next_multiple_of(REGION_ALIGNMENT);
+        if end > old.end {
+            return Err(RegionError::OutOfBounds {
+                kind: me,
+                range: old.start..end,
+                size: old.end,
+            });
+        }
+        let new = old.start..end;
+        self.set_region(me, new.clone())?;
+        let bios = self.regions.get(RegionKind::Bios).filter(|b| b.is_used());
+        if extend_bios && let Some(b) = bios {
+            let b = b.range();
+            if b.start == old.end {
+                self.set_region(RegionKind::Bios, end..b.end)?;
+            }
+        }
+        Ok(new)
+    }
+}
+
+/// A named range in a layout file
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct LayoutEntry {
+    pub name: String,
+    pub range: Range<usize>,
+}
+
+impl Display for LayoutEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:08x?}", self.name, self.range)
+    }
+}
+
+/// Validate layout entries against a descriptor and the size of an image.
+///
+/// Entries must be within the image and must not partially overlap. Those
+/// named after a region must match it exactly; others, e.g., subdivisions of
+/// the BIOS region in a flashmap, are only reported.
+pub fn validate_layout(ifd: &IFD, entries: &[LayoutEntry], size: usize) -> Vec<Finding> {
+    let mut res = vec![];
+    for (i, e) in entries.iter().enumerate() {
+        let n = e.name.as_str();
+        let r = &e.range;
+        if r.is_empty() {
+            res.push(Finding::error(n, format!("empty range {r:08x?}")));
+            continue;
+        }
+        if r.end > size {
+            let m = format!("range {r:08x?} exceeds image size {size:08x}");
+            res.push(Finding::error(n, m));
+        }
+        // Nesting is fine, e.g., for flashmap sections.
+        let contains = |a: &Range<usize>, b: &Range<usize>| a.start <= b.start && b.end <= a.end;
+        let overlapping = entries[i + 1..].iter().filter(|o| {
+            let o = &o.range;
+            o.start < r.end && r.start < o.end && !contains(r, o) && !contains(o, r)
+        });
+        for o in overlapping {
+            let m = format!("range {r:08x?} overlaps {o}");
+            res.push(Finding::error(n, m));
+        }
+        let Some(kind) = RegionKind::from_name(n) else {
+            res.push(Finding::info(n, format!("not a region, {r:08x?}")));
+            continue;
+        };
+        match ifd.regions.get(kind).filter(|r| r.is_used()) {
+            Some(reg) if reg.range() == *r => {}
+            Some(reg) => {
+                let m = format!(
+                    "range {r:08x?} differs from {kind} region {:08x?}",
+                    reg.range()
+                );
+                res.push(Finding::error(n, m));
+            }
+            None => {
+                let m = format!("{kind} region not in descriptor");
+                res.push(Finding::error(n, m));
+            }
+        }
+    }
+    res
+}
+
+// Parse a number in hex with 0x prefix or in decimal, with an optional K or M
+// suffix as used in flashmaps.
+pub(crate) fn parse_num(s: &str) -> Result<usize, String> {
+    let (n, m) = match s.strip_suffix(['K', 'k']) {
+        Some(n) => (n, 1024),
+        None => match s.strip_suffix(['M', 'm']) {
+            Some(n) => (n, 1024 * 1024),
+            None => (s, 1),
+        },
+    };
+    let r = match n.strip_prefix("0x").or(n.strip_prefix("0X")) {
+        Some(h) => usize::from_str_radix(h, 16),
+        None => n.parse::<usize>(),
+    };
+    let n = r.map_err(|e| format!("invalid number {s}: {e}"))?;
+    n.checked_mul(m).ok_or(format!("number {s} out of range"))
+}
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../../tests/me11.ifd");
+
+#[test]
+fn grow_bios_into_me() {
+    let mut ifd = IFD::parse(IFD_DATA).unwrap();
+    assert_eq!(ifd.flash_size(), Some(0x100_0000));
+    // BIOS @ 0x700000, ME @ 0x3000..0x700000
+    let e = ifd.set_region(RegionKind::Bios, 0x0060_0000..0x0100_0000);
+    assert!(matches!(
+        e,
+        Err(RegionError::Overlap {
+            other: RegionKind::Me,
+            ..
+        })
+    ));
+    ifd.set_region(RegionKind::Me, 0x3000..0x0010_0000).unwrap();
+    ifd.set_region(RegionKind::Bios, 0x0010_0000..0x0100_0000)
+        .unwrap();
+    let parsed = IFD::parse(&ifd.to_vec()).unwrap();
+    assert_eq!(parsed.regions.me_range(), 0x3000..0x0010_0000);
+    assert_eq!(parsed.regions.bios_range(), 0x0010_0000..0x0100_0000);
+}
+
+#[test]
+fn invalid_layout() {
+    let mut ifd = IFD::parse(IFD_DATA).unwrap();
+    let d = RegionKind::Descriptor;
+    assert!(matches!(
+        ifd.set_region(d, 0..0x2000),
+        Err(RegionError::Fixed(_))
+    ));
+    let b = RegionKind::Bios;
+    assert!(matches!(
+        ifd.set_region(b, 0x0070_0800..0x0100_0000),
+        Err(RegionError::Misaligned(..))
+    ));
+    assert!(matches!(
+        ifd.set_region(b, 0x0070_0000..0x0200_0000),
+        Err(RegionError::OutOfBounds { .. })
+    ));
+    assert!(matches!(
+        ifd.set_region(RegionKind::Ptt, 0x1000..0x2000),
+        Err(RegionError::NotPresent(_))
+    ));
+    ifd.clear_region(RegionKind::Gbe).unwrap();
+    assert!(!ifd.regions.get(RegionKind::Gbe).unwrap().is_used());
+}
+
+#[test]
+fn shrink_me() {
+    let mut ifd = IFD::parse(IFD_DATA).unwrap();
+    let r = ifd.shrink_me(0x0004_2345, true).unwrap();
+    assert_eq!(r, 0x3000..0x0004_6000);
+    assert_eq!(ifd.regions.bios_range(), 0x0004_6000..0x0100_0000);
+    assert!(matches!(
+        ifd.shrink_me(0x0100_0000, true),
+        Err(RegionError::OutOfBounds { .. })
+    ));
+}
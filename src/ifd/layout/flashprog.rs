@@ -0,0 +1,91 @@
+//! flashprog/flashrom layout files
+//!
+//! A layout file lists one region per line, given as inclusive start and end
+//! address in hex and a name, e.g.:
+//!
+//! ```text
+//! 00000000:00000fff fd
+//! 00700000:00ffffff bios
+//! ```
+//!
+//! With such a file, `flashprog -l layout.txt -i bios` only writes the BIOS
+//! region. The names are those used by coreboot `ifdtool -f`.
+
+use crate::ifd::{
+    Regions,
+    layout::{LayoutEntry, parse_num},
+};
+
+/// Create a layout file from the regions in use.
+pub fn export(regions: &Regions) -> String {
+    regions
+        .iter()
+        .filter(|r| r.is_used())
+        .map(|r| {
+            let range = r.range();
+            let (s, e) = (range.start, range.end - 1);
+            format!("{s:08x}:{e:08x} {}\n", r.kind.short_name())
+        })
+        .collect()
+}
+
+/// Parse a layout file. Empty lines and lines starting with `#` are skipped.
+pub fn import(s: &str) -> Result<Vec<LayoutEntry>, String> {
+    let mut res = vec![];
+    for (i, l) in s.lines().enumerate() {
+        let l = l.trim();
+        if l.is_empty() || l.starts_with('#') {
+            continue;
+        }
+        let err = |e: String| format!("line {}: {e}", i + 1);
+        let Some((range, name)) = l.split_once(char::is_whitespace) else {
+            return Err(err(format!("expected START:END NAME, got {l}")));
+        };
+        let Some((start, end)) = range.split_once(':') else {
+            return Err(err(format!("expected START:END, got {range}")));
+        };
+        let hex = |n: &str| match n.starts_with("0x") || n.starts_with("0X") {
+            true => parse_num(n),
+            false => parse_num(&format!("0x{n}")),
+        };
+        let (start, end) = (hex(start).map_err(err)?, hex(end).map_err(err)?);
+        if end < start {
+            return Err(err(format!("end {end:08x} before start {start:08x}")));
+        }
+        let Some(end) = end.checked_add(1) else {
+            return Err(err(format!("end {end:08x} out of range")));
+        };
+        res.push(LayoutEntry {
+            name: name.trim().to_string(),
+            range: start..end,
+        });
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+use crate::ifd::IFD;
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../../../tests/me11.ifd");
+
+#[test]
+fn export_import() {
+    use crate::ifd::layout::validate_layout;
+
+    let ifd = IFD::parse(IFD_DATA).unwrap();
+    let l = export(&ifd.regions);
+    assert!(l.starts_with("00000000:00000fff fd\n"));
+    assert!(l.contains("00700000:00ffffff bios\n"));
+    let entries = import(&l).unwrap();
+    assert_eq!(entries[1].name, "bios");
+    assert_eq!(entries[1].range, 0x0070_0000..0x0100_0000);
+    assert!(validate_layout(&ifd, &entries, 0x0100_0000).is_empty());
+
+    let bad = import("# comment\n\n0x0:0x7fffff bios\n").unwrap();
+    let f = validate_layout(&ifd, &bad, 0x0100_0000);
+    assert!(crate::check::has_errors(&f));
+    assert!(import("00000000-00000fff fd").is_err());
+    assert!(import("00001000:00000fff fd").is_err());
+    assert!(import("00000000:ffffffffffffffff fd").is_err());
+}
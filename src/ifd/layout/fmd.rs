@@ -0,0 +1,228 @@
+//! coreboot flashmap descriptors (FMD)
+//!
+//! An FMD file describes the flash as a tree of sections, each with a name,
+//! an optional offset relative to its parent and a size, e.g.:
+//!
+//! ```text
+//! FLASH 0x1000000 {
+//!     SI_DESC@0x0 0x1000
+//!     SI_ME@0x3000 0x6fd000
+//!     SI_BIOS@0x700000 0x900000 {
+//!         FMAP@0x0 0x800
+//!         COREBOOT(CBFS)
+//!     }
+//! }
+//! ```
+//!
+//! Sections without an offset follow their predecessor, and sections without
+//! a size extend up to the next one or the end of their parent. Regions are
+//! named as in coreboot `ifdtool`, e.g., `SI_BIOS`.
+
+use crate::ifd::{
+    IFD,
+    layout::{LayoutEntry, parse_num},
+};
+
+/// Create an FMD from the regions in use, in the order of their offsets.
+pub fn export(ifd: &IFD) -> String {
+    let mut regions = ifd
+        .regions
+        .iter()
+        .filter(|r| r.is_used())
+        .collect::<Vec<_>>();
+    regions.sort_by_key(|r| r.range().start);
+    let end = regions.iter().map(|r| r.range().end).max().unwrap_or(0);
+    let size = ifd.flash_size().unwrap_or(end);
+    let mut res = format!("FLASH {size:#x} {{\n");
+    for r in regions {
+        let range = r.range();
+        let n = match r.kind.fmap_name() {
+            Some(n) => n.to_string(),
+            None => format!("SI_{}", r.kind.short_name().to_uppercase()),
+        };
+        res.push_str(&format!("\t{n}@{:#x} {:#x}\n", range.start, range.len()));
+    }
+    res.push_str("}\n");
+    res
+}
+
+#[derive(Debug)]
+struct Section {
+    name: String,
+    offset: Option<usize>,
+    size: Option<usize>,
+    children: Vec<Section>,
+}
+
+// Split into tokens, braces being tokens of their own.
+fn tokenize(s: &str) -> Vec<String> {
+    let s = s
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    s.replace('{', " { ")
+        .replace('}', " } ")
+        .split_whitespace()
+        .map(|t| t.to_string())
+        .collect()
+}
+
+fn parse_section(tokens: &[String], pos: &mut usize) -> Result<Section, String> {
+    let Some(t) = tokens.get(*pos) else {
+        return Err("unexpected end of file".into());
+    };
+    *pos += 1;
+    let (name, offset) = match t.split_once('@') {
+        Some((n, o)) => (n, Some(parse_num(o)?)),
+        None => (t.as_str(), None),
+    };
+    // Drop annotations, e.g., (CBFS).
+    let name = name.split('(').next().unwrap_or_default().to_string();
+    if name.is_empty() || name == "{" || name == "}" {
+        return Err(format!("expected section name, got {t}"));
+    }
+    let mut size = None;
+    if let Some(t) = tokens.get(*pos)
+        && t != "{"
+        && t != "}"
+        && t.starts_with(|c: char| c.is_ascii_digit())
+    {
+        size = Some(parse_num(t)?);
+        *pos += 1;
+    }
+    let mut children = vec![];
+    if tokens.get(*pos).is_some_and(|t| t == "{") {
+        *pos += 1;
+        loop {
+            match tokens.get(*pos).map(|t| t.as_str()) {
+                Some("}") => {
+                    *pos += 1;
+                    break;
+                }
+                Some(_) => children.push(parse_section(tokens, pos)?),
+                None => return Err(format!("unclosed section {name}")),
+            }
+        }
+    }
+    Ok(Section {
+        name,
+        offset,
+        size,
+        children,
+    })
+}
+
+// Resolve offsets and sizes of the children of a section at base with size.
+fn flatten(
+    s: &Section,
+    base: usize,
+    parent_size: usize,
+    res: &mut Vec<LayoutEntry>,
+) -> Result<(), String> {
+    let mut next = 0;
+    let n = s.children.len();
+    for (i, c) in s.children.iter().enumerate() {
+        let start = c.offset.unwrap_or(next);
+        let size = match c.size {
+            Some(sz) => sz,
+            None => {
+                // Up to the next section with an offset, or the end.
+                let end = s.children[i + 1..n]
+                    .iter()
+                    .find_map(|c| c.offset)
+                    .unwrap_or(parent_size);
+                end.checked_sub(start)
+                    .ok_or(format!("cannot infer size of section {}", c.name))?
+            }
+        };
+        // Sections must fit into their parent.
+        let Some(end) = start.checked_add(size).filter(|e| *e <= parent_size) else {
+            return Err(format!(
+                "section {} @ {start:#x} with size {size:#x} exceeds {} of size {parent_size:#x}",
+                c.name, s.name
+            ));
+        };
+        let (Some(rs), Some(re)) = (base.checked_add(start), base.checked_add(end)) else {
+            return Err(format!("section {} out of range", c.name));
+        };
+        res.push(LayoutEntry {
+            name: c.name.clone(),
+            range: rs..re,
+        });
+        flatten(c, rs, size, res)?;
+        next = end;
+    }
+    Ok(())
+}
+
+/// Parse an FMD file into a flat list of all sections below the root, with
+/// offsets relative to the start of the flash.
+pub fn import(s: &str) -> Result<Vec<LayoutEntry>, String> {
+    let tokens = tokenize(s);
+    let mut pos = 0;
+    let root = parse_section(&tokens, &mut pos)?;
+    if let Some(t) = tokens.get(pos) {
+        return Err(format!("unexpected {t} after root section"));
+    }
+    let Some(size) = root.size else {
+        return Err(format!("root section {} without size", root.name));
+    };
+    let mut res = vec![];
+    flatten(&root, 0, size, &mut res)?;
+    Ok(res)
+}
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../../../tests/me11.ifd");
+
+#[test]
+fn import_nested() {
+    let fmd = "
+# A comment
+FLASH@0xff000000 16M {
+    SI_ALL@0x0 0x700000 {
+        SI_DESC@0x0 0x1000
+        SI_GBE 0x2000
+        SI_ME
+    }
+    SI_BIOS@0x700000 0x900000 {
+        FMAP 0x800
+        COREBOOT(CBFS)
+    }
+}
+";
+    let e = import(fmd).unwrap();
+    let find = |n: &str| e.iter().find(|e| e.name == n).unwrap().range.clone();
+    assert_eq!(find("SI_GBE"), 0x1000..0x3000);
+    assert_eq!(find("SI_ME"), 0x3000..0x0070_0000);
+    assert_eq!(find("FMAP"), 0x0070_0000..0x0070_0800);
+    assert_eq!(find("COREBOOT"), 0x0070_0800..0x0100_0000);
+    assert!(import("FLASH 16M { SI_BIOS 0x1000").is_err());
+    assert!(import("FLASH { SI_BIOS 0x1000 }").is_err());
+    assert!(import("FLASH 16M { SI_BIOS@0xf00000 0x200000 }").is_err());
+    assert!(import("FLASH 16M { SI_ALL 0x1000 { SI_DESC 0x2000 } }").is_err());
+    assert!(import("FLASH 16M { SI_BIOS@0xffffffffffffffff 0x10 }").is_err());
+    assert!(import("FLASH 0xffffffffffffffffM { SI_BIOS 0x10 }").is_err());
+}
+
+#[test]
+fn export_validate() {
+    use crate::check::{Severity, has_errors};
+    use crate::ifd::layout::validate_layout;
+
+    let ifd = IFD::parse(IFD_DATA).unwrap();
+    let fmd = export(&ifd);
+    assert!(fmd.starts_with("FLASH 0x1000000 {\n\tSI_DESC@0x0 0x1000\n"));
+    let e = import(&fmd).unwrap();
+    let f = validate_layout(&ifd, &e, 0x0100_0000);
+    assert!(f.is_empty());
+    let mut e = e;
+    e.push(LayoutEntry {
+        name: "RW_LEGACY".into(),
+        range: 0x0080_0000..0x0090_0000,
+    });
+    let f = validate_layout(&ifd, &e, 0x0100_0000);
+    assert!(!has_errors(&f));
+    assert_eq!(f[0].severity, Severity::Info);
+}
@@ -0,0 +1,467 @@
+//! Soft straps
+//!
+//! Soft straps configure the chipset before any firmware runs. Their meaning
+//! differs between chipset families, and most of them are only documented in
+//! Intel's confidential SPI programming guides. The definitions here are thus
+//! limited to fields known from public sources, i.e., `me_cleaner` and
+//! coreboot `util/ifdtool/`, and are looked up by name.
+//!
+//! This is not a strap database: the built-in definitions only cover the ME
+//! soft-disable bits and DCI, which stayed the same for whole generations of
+//! chipsets, so families of the same generation get the same fields. Other
+//! fields can be decoded by loading a [`schema`]; all straps are shown raw.
+
+use core::fmt::{self, Display};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::ifd::IFD;
+use crate::me::Generation;
+use crate::meta::Variant;
+use crate::ver::Version;
+
+pub mod schema;
+
+/// Chipset family, determining the meaning of the soft straps
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChipsetFamily {
+    Ich8,
+    Ich9,
+    Ich10,
+    /// Ibex Peak
+    Series5,
+    /// Cougar Point
+    Series6,
+    /// Panther Point
+    Series7,
+    /// Lynx Point
+    Series8,
+    /// Wildcat Point
+    Series9,
+    /// Sunrise Point and Union Point
+    Series100,
+    /// Cannon Point
+    Series300,
+    /// Comet Point and Ice Point
+    Series400,
+    /// Tiger Point
+    Series500,
+    /// Alder Point and Raptor Point
+    Series600,
+}
+
+impl ChipsetFamily {
+    /// Infer the chipset family from the ME generation and version, which
+    /// follow the chipsets; TXE and SPS versions are not taken into account.
+    pub fn from_me(me_gen: &Generation, me_ver: &Version) -> Option<Self> {
+        let f = match (me_gen, me_ver.major) {
+            (Generation::Gen1, 2) => Self::Ich8,
+            (Generation::Gen1, 3 | 4) => Self::Ich9,
+            (Generation::Gen1, 5) => Self::Ich10,
+            (Generation::Gen2, 6) => Self::Series5,
+            (Generation::Gen2, 7) => Self::Series6,
+            (Generation::Gen2, 8) => Self::Series7,
+            (Generation::Gen2, 9) => Self::Series8,
+            (Generation::Gen2, 10) => Self::Series9,
+            (Generation::Gen3, 11) => Self::Series100,
+            (Generation::Gen3, 12) => Self::Series300,
+            (Generation::Gen3, 13 | 14) => Self::Series400,
+            (Generation::Gen3, 15) => Self::Series500,
+            (Generation::Gen3, 16) => Self::Series600,
+            _ => return None,
+        };
+        Some(f)
+    }
+
+    /// Get the built-in strap fields for the family, see above: the ME
+    /// disable bits for the ICH era, AltMeDisable for the 5 to 9 series and
+    /// HAP and DCI from the 100 series on. A field may thus be listed for a
+    /// chipset that does not implement it.
+    pub fn fields(self) -> Vec<StrapField> {
+        let defs = match self {
+            Self::Ich8 | Self::Ich9 | Self::Ich10 => ICH_STRAPS,
+            Self::Series5 | Self::Series6 | Self::Series7 | Self::Series8 | Self::Series9 => {
+                PCH_GEN2_STRAPS
+            }
+            _ => PCH_GEN3_STRAPS,
+        };
+        defs.iter().map(StrapField::from_def).collect()
+    }
+}
+
+/// Why there is no HAP strap field for a firmware version
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NoHapField {
+    /// The platform has a HAP bit, but no public source confirms where.
+    /// Such versions are deliberately unsupported rather than guessed.
+    Unverified,
+    /// The version is not a known Gen 3 platform.
+    Unknown,
+}
+
+/// Get the High Assurance Platform (HAP) strap field for a Gen 3 (CS)ME, TXE
+/// or SPS version.
+pub fn hap_field(variant: Variant, me_ver: &Version) -> Result<StrapField, NoHapField> {
+    let hap = Ok(StrapField::from_def(&HAP));
+    match (variant, me_ver.major) {
+        // 100/200 series: Positive Technologies, "Disabling Intel ME 11 via
+        // undocumented mode" (2017), and `me_cleaner -S`
+        (Variant::ME, 11) => hap,
+        // 300 series: coreboot `ifdtool -p cnl --altmedisable`
+        (Variant::ME, 12) => hap,
+        // 400 series (Ice Lake): coreboot `ifdtool -p icl --altmedisable`
+        (Variant::ME, 13) => hap,
+        // 400 series (Comet Lake): coreboot `ifdtool -p cnl --altmedisable`
+        (Variant::ME, 14) => hap,
+        // 500 series: coreboot `ifdtool -p tgl --altmedisable`
+        (Variant::ME, 15) => hap,
+        // 600/700 series: coreboot `ifdtool -p adl --altmedisable`
+        (Variant::ME, 16) => hap,
+        // Apollo Lake: `me_cleaner -S`
+        (Variant::TXE, 3) => hap,
+        // Gemini Lake: not verified
+        (Variant::TXE, 4) => Err(NoHapField::Unverified),
+        // Purley (Lewisburg): `me_cleaner -S`
+        (Variant::SPS, 4) => hap,
+        // Whitley and Eagle Stream: not verified
+        (Variant::SPS, 5) => Err(NoHapField::Unverified),
+        (Variant::SPS, 6) => Err(NoHapField::Unverified),
+        _ => Err(NoHapField::Unknown),
+    }
+}
+
+impl Display for ChipsetFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Ich8 => "ICH8",
+            Self::Ich9 => "ICH9",
+            Self::Ich10 => "ICH10",
+            Self::Series5 => "5 series (Ibex Peak)",
+            Self::Series6 => "6 series (Cougar Point)",
+            Self::Series7 => "7 series (Panther Point)",
+            Self::Series8 => "8 series (Lynx Point)",
+            Self::Series9 => "9 series (Wildcat Point)",
+            Self::Series100 => "100/200 series (Sunrise/Union Point)",
+            Self::Series300 => "300 series (Cannon Point)",
+            Self::Series400 => "400 series (Comet/Ice Point)",
+            Self::Series500 => "500 series (Tiger Point)",
+            Self::Series600 => "600/700 series (Alder/Raptor Point)",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// The strap section a field is in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StrapSection {
+    /// ICH/PCH straps (ICHSTRPx/PCHSTRPx)
+    Pch,
+    /// MCH straps (MCHSTRPx), ICH era only
+    Mch,
+}
+
+/// A named field within a soft strap
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct StrapField {
+    pub name: String,
+    pub section: StrapSection,
+    /// Number of the strap within its section
+    pub strap: usize,
+    /// Lowest bit of the field
+    pub bit: u32,
+    /// Number of bits
+    pub width: u32,
+    pub description: String,
+}
+
+impl StrapField {
+    fn from_def(d: &StrapDef) -> Self {
+        let (name, section, strap, bit, width, description) = d;
+        Self {
+            name: name.to_string(),
+            section: *section,
+            strap: *strap,
+            bit: *bit,
+            width: *width,
+            description: description.to_string(),
+        }
+    }
+
+    /// The largest value the field can hold
+    pub fn max(&self) -> u32 {
+        u32::MAX >> (32 - self.width)
+    }
+}
+
+impl Display for StrapField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self.section {
+            StrapSection::Pch => "PCHSTRP",
+            StrapSection::Mch => "MCHSTRP",
+        };
+        let n = self.strap;
+        let l = self.bit;
+        let bits = match self.width {
+            1 => format!("{l}"),
+            w => format!("{}:{l}", l + w - 1),
+        };
+        write!(f, "{s}{n}[{bits}] {}", self.name)
+    }
+}
+
+// name, section, strap, lowest bit, width, description
+pub(crate) type StrapDef = (&'static str, StrapSection, usize, u32, u32, &'static str);
+
+pub(crate) const ME_DISABLE: StrapDef = (
+    "MeDisable",
+    StrapSection::Pch,
+    0,
+    0,
+    1,
+    "Disable the ME in the ICH",
+);
+
+pub(crate) const MCH_ME_DISABLE: StrapDef = (
+    "MchMeDisable",
+    StrapSection::Mch,
+    0,
+    0,
+    1,
+    "Disable the ME in the MCH",
+);
+
+pub(crate) const MCH_ALT_ME_DISABLE: StrapDef = (
+    "MchAltMeDisable",
+    StrapSection::Mch,
+    0,
+    7,
+    1,
+    "Disable the ME in the MCH (alternative)",
+);
+
+pub(crate) const ALT_ME_DISABLE: StrapDef = (
+    "AltMeDisable",
+    StrapSection::Pch,
+    10,
+    7,
+    1,
+    "Have the ME halt after platform bring-up",
+);
+
+pub(crate) const HAP: StrapDef = (
+    "HAP",
+    StrapSection::Pch,
+    0,
+    16,
+    1,
+    "High Assurance Platform: have the CSME halt after platform bring-up",
+);
+
+pub(crate) const DCI: StrapDef = (
+    "DCI",
+    StrapSection::Pch,
+    0,
+    17,
+    1,
+    "Enable the Direct Connect Interface for debugging",
+);
+
+const ICH_STRAPS: &[StrapDef] = &[ME_DISABLE, MCH_ME_DISABLE, MCH_ALT_ME_DISABLE];
+
+const PCH_GEN2_STRAPS: &[StrapDef] = &[ALT_ME_DISABLE];
+
+const PCH_GEN3_STRAPS: &[StrapDef] = &[HAP, DCI];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum StrapError {
+    /// No field of that name is defined.
+    UnknownField(String),
+    /// The strap the field is in is not in the descriptor.
+    MissingStrap(String),
+    /// The value does not fit into the field.
+    OutOfRange { name: String, value: u32, max: u32 },
+}
+
+impl Display for StrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownField(n) => write!(f, "unknown strap field {n}"),
+            Self::MissingStrap(n) => write!(f, "strap for field {n} not in descriptor"),
+            Self::OutOfRange { name, value, max } => {
+                write!(f, "value {value:#x} out of range for {name}, max. {max:#x}")
+            }
+        }
+    }
+}
+
+/// Find a field by name, ignoring case.
+pub fn find_field<'a>(fields: &'a [StrapField], name: &str) -> Result<&'a StrapField, StrapError> {
+    fields
+        .iter()
+        .find(|f| f.name.eq_ignore_ascii_case(name))
+        .ok_or(StrapError::UnknownField(name.to_string()))
+}
+
+impl IFD {
+    /// Get a single bit field from its definition, `false` if the strap is
+    /// missing.
+    pub(crate) fn strap_flag(&self, d: &StrapDef) -> bool {
+        self.strap_value(&StrapField::from_def(d))
+            .is_ok_and(|v| v == 1)
+    }
+
+    /// Set or clear a single bit field from its definition.
+    pub(crate) fn set_strap_flag(&mut self, d: &StrapDef, s: bool) {
+        if let Err(e) = self.set_strap_value(&StrapField::from_def(d), u32::from(s)) {
+            warn!("{e}");
+        }
+    }
+
+    fn straps_for(&self, s: StrapSection) -> &Vec<u32> {
+        match s {
+            StrapSection::Pch => &self.pch_straps,
+            StrapSection::Mch => &self.mch_straps,
+        }
+    }
+
+    /// Get the value of a strap field.
+    pub fn strap_value(&self, field: &StrapField) -> Result<u32, StrapError> {
+        let Some(s) = self.straps_for(field.section).get(field.strap) else {
+            return Err(StrapError::MissingStrap(field.name.clone()));
+        };
+        Ok(s >> field.bit & field.max())
+    }
+
+    /// Set the value of a strap field.
+    pub fn set_strap_value(&mut self, field: &StrapField, value: u32) -> Result<(), StrapError> {
+        let max = field.max();
+        if value > max {
+            return Err(StrapError::OutOfRange {
+                name: field.name.clone(),
+                value,
+                max,
+            });
+        }
+        let straps = match field.section {
+            StrapSection::Pch => &mut self.pch_straps,
+            StrapSection::Mch => &mut self.mch_straps,
+        };
+        let Some(s) = straps.get_mut(field.strap) else {
+            return Err(StrapError::MissingStrap(field.name.clone()));
+        };
+        *s = *s & !(max << field.bit) | (value << field.bit);
+        Ok(())
+    }
+
+    /// Get the value of a strap field by name.
+    pub fn strap(&self, fields: &[StrapField], name: &str) -> Result<u32, StrapError> {
+        self.strap_value(find_field(fields, name)?)
+    }
+
+    /// Set the value of a strap field by name.
+    pub fn set_strap(
+        &mut self,
+        fields: &[StrapField],
+        name: &str,
+        value: u32,
+    ) -> Result<(), StrapError> {
+        self.set_strap_value(find_field(fields, name)?, value)
+    }
+
+    /// Decode all given strap fields.
+    pub fn decode_straps<'a>(
+        &self,
+        fields: &'a [StrapField],
+    ) -> Vec<(&'a StrapField, Result<u32, StrapError>)> {
+        fields.iter().map(|f| (f, self.strap_value(f))).collect()
+    }
+}
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../../tests/me11.ifd");
+
+#[test]
+fn get_set_strap() {
+    let mut ifd = IFD::parse(IFD_DATA).unwrap();
+    let v = Version {
+        major: 11,
+        minor: 8,
+        patch: 50,
+        build: 3425,
+    };
+    let f = ChipsetFamily::from_me(&Generation::Gen3, &v).unwrap();
+    assert_eq!(f, ChipsetFamily::Series100);
+    let fields = f.fields();
+    assert_eq!(ifd.strap(&fields, "hap").unwrap(), u32::from(ifd.hap()));
+    ifd.set_strap(&fields, "HAP", 1).unwrap();
+    assert!(ifd.hap());
+    assert!(matches!(
+        ifd.set_strap(&fields, "HAP", 2),
+        Err(StrapError::OutOfRange { max: 1, .. })
+    ));
+    assert!(matches!(
+        ifd.strap(&fields, "AltMeDisable"),
+        Err(StrapError::UnknownField(_))
+    ));
+}
+
+#[test]
+fn flags_follow_fields() {
+    let mut ifd = IFD::parse(include_bytes!("../../tests/me8.ifd")).unwrap();
+    let fields = ChipsetFamily::Series7.fields();
+    ifd.set_alt_me_disabled(true);
+    assert_eq!(ifd.strap(&fields, "AltMeDisable").unwrap(), 1);
+    ifd.set_strap(&fields, "AltMeDisable", 0).unwrap();
+    assert!(!ifd.alt_me_disabled());
+    // Missing straps read as cleared and are not written.
+    ifd.pch_straps.clear();
+    assert!(!ifd.alt_me_disabled());
+    ifd.set_hap(true);
+    assert!(!ifd.hap());
+}
+
+#[test]
+fn hap_field_versions() {
+    let v = |major| Version {
+        major,
+        minor: 0,
+        patch: 0,
+        build: 0,
+    };
+    let pchstrp0_16 = |f: Result<StrapField, _>| f.is_ok_and(|f| f.strap == 0 && f.bit == 16);
+    for major in 11..=16 {
+        assert!(pchstrp0_16(hap_field(Variant::ME, &v(major))));
+    }
+    assert!(pchstrp0_16(hap_field(Variant::TXE, &v(3))));
+    assert!(pchstrp0_16(hap_field(Variant::SPS, &v(4))));
+    let unknown = Err(NoHapField::Unknown);
+    assert_eq!(hap_field(Variant::ME, &v(10)), unknown);
+    assert_eq!(hap_field(Variant::ME, &v(17)), unknown);
+    assert_eq!(hap_field(Variant::ME, &v(3)), unknown);
+    assert_eq!(hap_field(Variant::TXE, &v(11)), unknown);
+    let unverified = Err(NoHapField::Unverified);
+    assert_eq!(hap_field(Variant::TXE, &v(4)), unverified);
+    assert_eq!(hap_field(Variant::SPS, &v(5)), unverified);
+    assert_eq!(hap_field(Variant::SPS, &v(6)), unverified);
+}
+
+#[test]
+fn multi_bit_field() {
+    let mut ifd = IFD::parse(IFD_DATA).unwrap();
+    let f = StrapField {
+        name: "Test".into(),
+        section: StrapSection::Pch,
+        strap: 1,
+        bit: 4,
+        width: 3,
+        description: String::new(),
+    };
+    ifd.set_strap_value(&f, 5).unwrap();
+    assert_eq!(ifd.strap_value(&f).unwrap(), 5);
+    assert_eq!(f.to_string(), "PCHSTRP1[6:4] Test");
+    let f = StrapField { strap: 1000, ..f };
+    assert!(matches!(
+        ifd.strap_value(&f),
+        Err(StrapError::MissingStrap(_))
+    ));
+}
@@ -0,0 +1,247 @@
+//! Strap schemas loaded at runtime
+//!
+//! The Flash Image Tool (FIT) from Intel describes the soft straps in its
+//! resources as XML, e.g.:
+//!
+//! ```xml
+//! <LayoutEntry name="PCH_Strap_CSME_CSE_HAP_Mode" type="bitfield32"
+//!     value="0x0" offset="0x68" bitfield_high="16" bitfield_low="16" />
+//! ```
+//!
+//! Offsets are not relative to the PCH strap section, but to a base that
+//! depends on the FIT version, so it has to be given along with the XML. It
+//! is the offset of PCHSTRP0, e.g., [`FIT11_PCH_STRAP_BASE`] for CSME 11,
+//! where the HAP bit above is PCHSTRP0 bit 16. Entries of other integer types
+//! than `bitfield32` cover the whole integer.
+//!
+//! Alternatively, a TOML file can list the fields directly:
+//!
+//! ```toml
+//! [[field]]
+//! name = "HAP"
+//! section = "pch"
+//! strap = 0
+//! bit = 16
+//! width = 1
+//! description = "High Assurance Platform"
+//! ```
+//!
+//! `section` defaults to `pch` and `width` to 1.
+
+use quick_xml::{XmlVersion, events::Event, reader::Reader};
+use serde::Deserialize;
+
+use crate::ifd::straps::{StrapField, StrapSection};
+
+/// XML offset of PCHSTRP0 in the CSME 11 FIT, derived from the HAP entry
+/// above and `me_cleaner` setting HAP in PCHSTRP0 bit 16
+pub const FIT11_PCH_STRAP_BASE: usize = 0x68;
+
+// Check that a field fits into a 32 bit strap.
+fn checked(f: StrapField) -> Result<StrapField, String> {
+    if f.width == 0 || f.bit.checked_add(f.width).is_none_or(|e| e > 32) {
+        return Err(format!(
+            "field {} with bit {} and width {} does not fit into a strap",
+            f.name, f.bit, f.width
+        ));
+    }
+    Ok(f)
+}
+
+fn parse_num(s: &str) -> Result<u32, String> {
+    let r = match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(h) => u32::from_str_radix(h, 16),
+        None => s.parse::<u32>(),
+    };
+    r.map_err(|e| format!("invalid number {s}: {e}"))
+}
+
+// Convert a LayoutEntry given its attributes.
+fn layout_entry(attrs: &[(String, String)], base: usize) -> Result<Option<StrapField>, String> {
+    let get = |k: &str| attrs.iter().find(|(a, _)| a == k).map(|(_, v)| v.as_str());
+    let Some(name) = get("name") else {
+        return Err("LayoutEntry without name".into());
+    };
+    let Some(offset) = get("offset") else {
+        return Err(format!("LayoutEntry {name} without offset"));
+    };
+    let offset = parse_num(offset)? as usize;
+    let Some(offset) = offset.checked_sub(base) else {
+        return Err(format!(
+            "LayoutEntry {name} @ {offset:#x} below the strap base {base:#x}"
+        ));
+    };
+    let (bit, width) = match get("type") {
+        Some("bitfield32") => {
+            let (Some(h), Some(l)) = (get("bitfield_high"), get("bitfield_low")) else {
+                return Err(format!("bit field {name} without bits"));
+            };
+            let (h, l) = (parse_num(h)?, parse_num(l)?);
+            let Some(w) = h.checked_sub(l).and_then(|d| d.checked_add(1)) else {
+                return Err(format!("bit field {name} with invalid bits {h}:{l}"));
+            };
+            (l, w)
+        }
+        Some("uint8") => ((offset % 4) as u32 * 8, 8),
+        Some("uint16") => ((offset % 4) as u32 * 8, 16),
+        Some("uint32") => (0, 32),
+        // Strings, arrays etc. are not strap fields.
+        _ => return Ok(None),
+    };
+    let f = StrapField {
+        name: name.to_string(),
+        section: StrapSection::Pch,
+        strap: offset / 4,
+        bit,
+        width,
+        description: get("description").unwrap_or_default().to_string(),
+    };
+    checked(f).map(Some)
+}
+
+/// Load strap fields from the `LayoutEntry` elements of a FIT XML file,
+/// given the XML offset of PCHSTRP0.
+pub fn from_xml(xml: &str, base: usize) -> Result<Vec<StrapField>, String> {
+    let mut r = Reader::from_str(xml);
+    let mut res = vec![];
+    loop {
+        let e = match r.read_event() {
+            Ok(Event::Eof) => break,
+            Ok(Event::Start(e) | Event::Empty(e)) => e,
+            Ok(_) => continue,
+            Err(e) => {
+                let p = r.buffer_position();
+                return Err(format!("XML error at {p}: {e}"));
+            }
+        };
+        if e.local_name().as_ref() != "LayoutEntry" {
+            continue;
+        }
+        let mut attrs = vec![];
+        for a in e.attributes() {
+            let a = a.map_err(|e| format!("XML attribute error: {e}"))?;
+            let k = a.key.local_name().as_ref().to_string();
+            let v = a
+                .normalized_value(XmlVersion::Implicit1_0)
+                .map_err(|e| format!("XML attribute error: {e}"))?;
+            attrs.push((k, v.to_string()));
+        }
+        if let Some(f) = layout_entry(&attrs, base)? {
+            res.push(f);
+        }
+    }
+    Ok(res)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TomlSection {
+    Pch,
+    Mch,
+}
+
+#[derive(Deserialize)]
+struct TomlField {
+    name: String,
+    section: Option<TomlSection>,
+    strap: usize,
+    bit: u32,
+    width: Option<u32>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TomlSchema {
+    field: Vec<TomlField>,
+}
+
+/// Load strap fields from a TOML file.
+pub fn from_toml(s: &str) -> Result<Vec<StrapField>, String> {
+    let schema: TomlSchema = toml::from_str(s).map_err(|e| format!("TOML error: {e}"))?;
+    schema
+        .field
+        .into_iter()
+        .map(|f| {
+            let section = match f.section {
+                Some(TomlSection::Mch) => StrapSection::Mch,
+                _ => StrapSection::Pch,
+            };
+            checked(StrapField {
+                name: f.name,
+                section,
+                strap: f.strap,
+                bit: f.bit,
+                width: f.width.unwrap_or(1),
+                description: f.description.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn load_xml() {
+    let xml = r#"<?xml version="1.0"?>
+<Layout name="PCH Straps">
+  <LayoutEntry name="PCH_Strap_Bar" type="bitfield32"
+      value="0x0" offset="0x28" bitfield_high="17" bitfield_low="17" />
+  <LayoutEntry name="PCH_Strap_Foo" type="bitfield32"
+      value="0x0" offset="0x4" bitfield_high="7" bitfield_low="4" />
+  <LayoutEntry name="PCH_Strap_Byte" type="uint8" value="0x0" offset="0x9" />
+  <LayoutEntry name="Name" type="string" value="" offset="0x0" />
+</Layout>"#;
+    let f = from_xml(xml, 0).unwrap();
+    assert_eq!(f.len(), 3);
+    assert_eq!((f[0].strap, f[0].bit, f[0].width), (10, 17, 1));
+    assert_eq!((f[1].strap, f[1].bit, f[1].width), (1, 4, 4));
+    assert_eq!((f[2].strap, f[2].bit, f[2].width), (2, 8, 8));
+    let bad = r#"<LayoutEntry name="X" type="bitfield32" offset="0"
+        bitfield_high="1" bitfield_low="2" />"#;
+    assert!(from_xml(bad, 0).is_err());
+    let bad = r#"<LayoutEntry name="X" type="bitfield32" offset="0"
+        bitfield_high="4294967295" bitfield_low="0" />"#;
+    assert!(from_xml(bad, 0).is_err());
+    // Entries below the base cannot be straps.
+    assert!(from_xml(xml, 0x8).is_err());
+}
+
+#[test]
+fn load_xml_hap() {
+    use crate::ifd::straps::HAP;
+
+    // As found in the CSME 11 FIT, see docs/knowledge.md
+    let xml = r#"<LayoutEntry name="PCH_Strap_CSME_CSE_HAP_Mode" type="bitfield32"
+    value="0x0" offset="0x68" bitfield_high="16" bitfield_low="16" />"#;
+    let f = from_xml(xml, FIT11_PCH_STRAP_BASE).unwrap();
+    let hap = StrapField::from_def(&HAP);
+    assert_eq!(f[0].section, StrapSection::Pch);
+    assert_eq!((f[0].strap, f[0].bit, f[0].width), (hap.strap, hap.bit, 1));
+    assert_eq!((f[0].strap, f[0].bit), (0, 16));
+}
+
+#[test]
+fn load_toml() {
+    let s = r#"
+[[field]]
+name = "HAP"
+strap = 0
+bit = 16
+
+[[field]]
+name = "MchField"
+section = "mch"
+strap = 1
+bit = 28
+width = 4
+description = "Something"
+"#;
+    let f = from_toml(s).unwrap();
+    assert_eq!(f.len(), 2);
+    assert_eq!(f[0].section, StrapSection::Pch);
+    assert_eq!(f[0].width, 1);
+    assert_eq!(f[1].section, StrapSection::Mch);
+    assert_eq!(f[1].max(), 0xf);
+    let bad = "[[field]]\nname = \"X\"\nstrap = 0\nbit = 30\nwidth = 4\n";
+    assert!(from_toml(bad).is_err());
+    let bad = "[[field]]\nname = \"X\"\nstrap = 0\nbit = 1\nwidth = 4294967295\n";
+    assert!(from_toml(bad).is_err());
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::io;
+
+use intel_fw::{
+    Firmware,
+    ifd::{IFD, IfdVersion, RegionKind, layout::flashprog},
+    me::Generation,
+};
+use log::info;
+
+use crate::show;
+
+/// Operations, named after the coreboot `ifdtool` switches
+pub struct Options {
+    /// `-d`
+    pub dump: bool,
+    /// `-x`
+    pub extract: bool,
+    /// `-f`
+    pub layout: Option<String>,
+    /// `-n`
+    pub new_layout: Option<String>,
+    /// `-i`
+    pub inject: Option<String>,
+    /// `-u`
+    pub unlock: bool,
+    /// `-l`
+    pub lock: bool,
+    /// `-M`
+    pub altmedisable: Option<bool>,
+    /// `-p`
+    pub platform: Option<String>,
+}
+
+/// Platforms as given to `ifdtool -p`, with their descriptor versions
+const PLATFORMS: &[(&str, IfdVersion)] = &[
+    ("adl", IfdVersion::V2),
+    ("aplk", IfdVersion::V2),
+    ("cnl", IfdVersion::V2),
+    ("dnv", IfdVersion::V2),
+    ("ehl", IfdVersion::V2),
+    ("glk", IfdVersion::V2),
+    ("icl", IfdVersion::V2),
+    ("ifd2", IfdVersion::V2),
+    ("jsl", IfdVersion::V2),
+    ("lbg", IfdVersion::V2),
+    ("mtl", IfdVersion::V2),
+    ("ptl", IfdVersion::V2),
+    ("sklkbl", IfdVersion::V2),
+    ("tgl", IfdVersion::V2),
+    ("wbg", IfdVersion::V1),
+];
+
+fn platform_version(p: &str) -> Result<IfdVersion, String> {
+    match PLATFORMS.iter().find(|(n, _)| n.eq_ignore_ascii_case(p)) {
+        Some((_, v)) => Ok(*v),
+        None => {
+            let known = PLATFORMS.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+            Err(format!("unknown platform {p}, known: {}", known.join(", ")))
+        }
+    }
+}
+
+/// Get the ME generation, which determines the soft-disable bits. Without an
+/// ME firmware, infer it from the descriptor: IFD v2 came with CSME 11, and
+/// ICH descriptors have fewer straps than PCH descriptors.
+fn me_generation(fw: &Firmware, ifd: &IFD, ver: Option<IfdVersion>) -> Generation {
+    if let Some(Ok(me)) = &fw.me
+        && me.generation != Generation::Unknown
+    {
+        return me.generation.clone();
+    }
+    match ver {
+        Some(IfdVersion::V2) => Generation::Gen3,
+        Some(IfdVersion::V1) if ifd.pch_straps.len() > 4 => Generation::Gen2,
+        Some(IfdVersion::V1) => Generation::Gen1,
+        None => Generation::Unknown,
+    }
+}
+
+/// Set or clear the MeDisable/AltMeDisable bits or the HAP bit, as
+/// `ifdtool --altmedisable` does.
+fn set_altmedisable(ifd: &mut IFD, me_gen: &Generation, s: bool) -> Result<(), String> {
+    match me_gen {
+        Generation::Gen1 => {
+            ifd.set_ich_me_disabled(s);
+            ifd.set_mch_me_disabled(s);
+            ifd.set_mch_alt_me_disabled(s);
+        }
+        Generation::Gen2 => ifd.set_alt_me_disabled(s),
+        Generation::Gen3 => ifd.set_hap(s),
+        Generation::Unknown => return Err("platform unknown, please provide one via -p".into()),
+    }
+    Ok(())
+}
+
+fn invalid(e: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+pub fn ifdtool(
+    file_name: &str,
+    output: Option<String>,
+    options: Options,
+    debug: bool,
+) -> Result<(), io::Error> {
+    let mut data = fs::read(file_name)?;
+    let fw = Firmware::parse(&data, debug);
+    let mut ifd = fw
+        .ifd
+        .clone()
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("no IFD found: {e:?}")))?;
+    let ver = match &options.platform {
+        Some(p) => Some(platform_version(p).map_err(invalid)?),
+        None => ifd.version(),
+    };
+    if options.unlock && options.lock {
+        return Err(invalid("cannot lock and unlock at the same time".into()));
+    }
+
+    let modify = options.new_layout.is_some()
+        || options.inject.is_some()
+        || options.unlock
+        || options.lock
+        || options.altmedisable.is_some();
+    let read = options.extract || options.layout.is_some();
+    if options.dump || !(modify || read) {
+        show::print_ifd(&ifd, ver);
+        match ifd.access_matrix() {
+            Ok(m) => println!("== Region access ==\n{m}"),
+            Err(e) => println!("== Region access ==\n  {e}"),
+        }
+    }
+    if options.extract {
+        for (kind, d) in ifd.extract_regions(&data).map_err(invalid)? {
+            let f = kind.file_name();
+            info!("Writing {kind} region to {f}");
+            fs::write(f, d)?;
+        }
+    }
+    if let Some(f) = &options.layout {
+        info!("Writing layout to {f}");
+        fs::write(f, flashprog::export(&ifd.regions))?;
+    }
+    if !modify {
+        return Ok(());
+    }
+
+    if let Some(l) = &options.new_layout {
+        let entries = flashprog::import(&fs::read_to_string(l)?).map_err(invalid)?;
+        data = ifd.apply_layout(&data, &entries).map_err(invalid)?;
+    }
+    if let Some(i) = &options.inject {
+        let Some((r, f)) = i.split_once(':') else {
+            return Err(invalid(format!("expected REGION:FILE, got {i}")));
+        };
+        let Some(kind) = RegionKind::from_name(r) else {
+            return Err(invalid(format!("unknown region {r}")));
+        };
+        info!("Injecting {f} into {kind} region");
+        let contents = fs::read(f)?;
+        ifd.inject_region(&mut data, kind, &contents)
+            .map_err(invalid)?;
+    }
+    if options.unlock {
+        ifd.unlock().map_err(invalid)?;
+    }
+    if options.lock {
+        ifd.lock().map_err(invalid)?;
+    }
+    if let Some(s) = options.altmedisable {
+        let me_gen = me_generation(&fw, &ifd, ver);
+        set_altmedisable(&mut ifd, &me_gen, s).map_err(invalid)?;
+    }
+
+    let new_ifd = ifd.to_vec();
+    data[..new_ifd.len()].copy_from_slice(&new_ifd);
+    // Like ifdtool, write to FILE.new by default.
+    let output = output.unwrap_or(format!("{file_name}.new"));
+    info!("Writing output to {output}");
+    fs::write(output, data)?;
+    Ok(())
+}
@@ -3,16 +3,24 @@
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
+pub mod acm;
+pub mod audit;
+pub mod bootguard;
+pub mod check;
 pub mod dir;
 pub mod fit;
+pub mod gbe;
 pub mod ifd;
 pub mod me;
 pub mod meta;
+pub mod microcode;
 pub mod part;
+pub mod platform;
 pub mod ver;
 
 use fit::{Fit, FitError};
-use ifd::{IFD, IfdError};
+use gbe::Gbe;
+use ifd::{IFD, IfdError, RegionKind};
 use me::ME;
 
 // An empty byte in a NOR flash is all-1's.
@@ -30,6 +38,8 @@ pub struct Firmware {
     pub ifd: Result<IFD, IfdError>,
     pub me: Option<Result<ME, String>>,
     pub fit: Result<Fit, FitError>,
+    /// Only in full images with a GbE region
+    pub gbe: Option<Result<Gbe, String>>,
 }
 
 impl Firmware {
@@ -40,7 +50,15 @@ impl Firmware {
                 let me_region = ifd.regions.me_range();
                 let b = me_region.start;
                 info!("ME region start @ {b:08x}");
-                ME::parse(&data[me_region], b, debug)
+                let e = me_region.end;
+                let l = data.len();
+                if e > l {
+                    Some(Err(format!(
+                        "ME region end {e:08x} out of bounds ({l:08x})"
+                    )))
+                } else {
+                    ME::parse(&data[me_region], b, debug)
+                }
             }
             Err(e) => {
                 warn!("Not a full image: {e:?}");
@@ -48,14 +66,32 @@ impl Firmware {
             }
         };
         let fit = Fit::new(data);
-        Self { ifd, me, fit }
+        let gbe = parse_gbe(&ifd, data);
+        Self { ifd, me, fit, gbe }
     }
 
     pub fn scan(data: &[u8], debug: bool) -> Self {
         let ifd = IFD::parse(data);
         let me = ME::scan(data, debug);
         let fit = Fit::new(data);
-        Self { ifd, me, fit }
+        let gbe = parse_gbe(&ifd, data);
+        Self { ifd, me, fit, gbe }
+    }
+}
+
+fn parse_gbe(ifd: &Result<IFD, IfdError>, data: &[u8]) -> Option<Result<Gbe, String>> {
+    let r = ifd.as_ref().ok()?.regions.get(RegionKind::Gbe)?;
+    if !r.is_used() {
+        return None;
+    }
+    let r = r.range();
+    let (b, e, l) = (r.start, r.end, data.len());
+    info!("GbE region start @ {b:08x}");
+    match data.get(r) {
+        Some(d) => Some(Gbe::parse(d, b)),
+        None => Some(Err(format!(
+            "GbE region end {e:08x} out of bounds ({l:08x})"
+        ))),
     }
 }
 
@@ -15,9 +15,27 @@ use clap::{Parser, Subcommand};
 use log::{debug, error, info, warn};
 
 mod clean;
+mod ifdtool;
 mod show;
 
-use intel_fw::Firmware;
+use intel_fw::{
+    EMPTY, Firmware, acm,
+    bootguard::{Manifests, verify},
+    check::{check_fit, check_me, has_errors},
+    fit::Fit,
+    gbe::{Gbe, MacAddress},
+    ifd::{
+        IFD,
+        builder::Spec,
+        components::{self, format_size},
+        layout::{flashprog, fmd, validate_layout},
+        straps::{ChipsetFamily, StrapField, schema},
+    },
+    microcode,
+};
+
+/// Exit status of the checking commands if any check failed
+const EXIT_CHECK_FAILED: i32 = 2;
 
 #[derive(Subcommand, Debug)]
 enum MeCommand {
@@ -38,6 +56,9 @@ enum MeCommand {
         /// Truncuate empty part of the fimrware image
         #[clap(long, short)]
         truncate: bool,
+        /// Extend the BIOS region downwards into the space freed by truncating (requires a full image)
+        #[clap(long)]
+        extend_bios: bool,
         /// Retain FTPR modules even if they could be removed
         #[clap(long, short)]
         keep_modules: bool,
@@ -56,7 +77,7 @@ enum MeCommand {
         /// Extract ME region to a file if given a full image
         #[clap(long, short = 'M')]
         extract_me: Option<String>,
-        /// Perform basic integrity checks
+        /// Perform basic integrity checks, exit with status 2 if any failed
         #[clap(long, short)]
         check: bool,
         /// File to read
@@ -75,6 +96,8 @@ enum MeCommand {
         file_name: String,
     },
     /// Check for consistency (full image or ME region)
+    ///
+    /// Exit status: 0 if all checks passed, 2 if any check failed.
     #[clap(verbatim_doc_comment)]
     Check {
         /// File to read
@@ -93,8 +116,192 @@ enum MeCommand {
 
 #[derive(Subcommand)]
 enum BootGuardCommand {
+    /// Display the Key Manifest and Boot Policy Manifest referenced by the FIT
+    #[clap(verbatim_doc_comment)]
+    Manifests {
+        /// File to read
+        file_name: String,
+    },
+    /// Verify the Boot Guard chain of trust down to the IBB digest
+    ///
+    /// Exit status: 0 if all checks passed, 2 if any check failed.
+    #[clap(verbatim_doc_comment)]
+    Verify {
+        /// File to read
+        file_name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FitCommand {
+    /// Display the FIT and the microcode updates and ACMs it references
+    #[clap(verbatim_doc_comment)]
+    Show {
+        /// File to read
+        file_name: String,
+    },
+    /// Insert or replace a microcode update in the BIOS region and the FIT
+    #[clap(verbatim_doc_comment)]
+    InsertMicrocode {
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: String,
+        /// Microcode update file to insert
+        #[clap(long, short)]
+        microcode: String,
+        /// Unused space to write the update and, if needed, the relocated FIT
+        /// to, as START:END offsets within the image, END exclusive
+        #[clap(long, short, verbatim_doc_comment)]
+        range: String,
+        /// Number of unused bytes after the FIT that it may grow into
+        #[clap(long, default_value = "0")]
+        fit_padding: String,
+        /// File to read
+        file_name: String,
+    },
+    /// Check the FIT header and entry checksums
+    ///
+    /// Exit status: 0 if all checks passed, 2 if any check failed.
+    #[clap(verbatim_doc_comment)]
+    Verify {
+        /// File to read
+        file_name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum StrapsCommand {
+    /// Display the soft straps, decoding the fields known for the platform
+    #[clap(verbatim_doc_comment)]
+    Show {
+        /// Strap schema to use instead of the built-in definitions
+        /// (Flash Image Tool XML with LayoutEntry elements, or TOML)
+        #[clap(long, short, verbatim_doc_comment)]
+        schema: Option<String>,
+        /// Offset of PCHSTRP0 in the XML schema, e.g., 0x68 for CSME 11
+        #[clap(long)]
+        xml_base: Option<String>,
+        /// File to read
+        file_name: String,
+    },
+    /// Set soft strap fields by name
+    #[clap(verbatim_doc_comment)]
+    Set {
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: String,
+        /// Strap schema to use instead of the built-in definitions
+        /// (Flash Image Tool XML with LayoutEntry elements, or TOML)
+        #[clap(long, short, verbatim_doc_comment)]
+        schema: Option<String>,
+        /// Offset of PCHSTRP0 in the XML schema, e.g., 0x68 for CSME 11
+        #[clap(long)]
+        xml_base: Option<String>,
+        /// Comma separated list of NAME=VALUE assignments
+        #[clap(long = "set", short = 'S', value_delimiter = ',', required = true)]
+        assignments: Vec<String>,
+        /// File to read
+        file_name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FlashCommand {
+    /// Display the flash components and check them against the image size
+    #[clap(verbatim_doc_comment)]
+    Show {
+        /// File to read
+        file_name: String,
+    },
+    /// Set the number and sizes of the flash components
+    #[clap(verbatim_doc_comment)]
+    SetComponents {
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: String,
+        /// Comma separated list of component sizes, e.g., 16M,8M
+        #[clap(long, short, value_delimiter = ',', required = true)]
+        sizes: Vec<String>,
+        /// File to read
+        file_name: String,
+    },
+    /// Split an image into one file per flash component
+    #[clap(verbatim_doc_comment)]
+    Split {
+        /// Comma separated list of files to write, one per component
+        #[clap(long, short = 'O', value_delimiter = ',', required = true)]
+        output: Vec<String>,
+        /// File to read
+        file_name: String,
+    },
+    /// Merge per-component files into one image
+    #[clap(verbatim_doc_comment)]
+    Merge {
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: String,
+        /// Files to read, in the order of the components
+        #[clap(required = true)]
+        file_names: Vec<String>,
+    },
+    /// Export the regions as a flashprog layout, e.g., for
+    /// `flashprog -l layout -i bios`, or as a coreboot FMD
+    #[clap(verbatim_doc_comment)]
+    ExportLayout {
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: String,
+        /// Write a coreboot FMD instead of a flashprog layout
+        #[clap(long)]
+        fmd: bool,
+        /// File to read
+        file_name: String,
+    },
+    /// Build a new flash descriptor from a specification (TOML, or *.json)
+    #[clap(verbatim_doc_comment)]
+    BuildDescriptor {
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: String,
+        /// Write an empty image of the full flash size instead of only the descriptor
+        #[clap(long)]
+        full: bool,
+        /// Specification to read
+        spec: String,
+    },
+    /// Check a flashprog layout or coreboot FMD (*.fmd) against an image
+    ///
+    /// Exit status: 0 if all checks passed, 2 if any check failed.
+    #[clap(verbatim_doc_comment)]
+    CheckLayout {
+        /// Layout file to check
+        #[clap(long, short)]
+        layout: String,
+        /// File to read
+        file_name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum GbeCommand {
+    /// Display the GbE region, i.e., the Ethernet controller NVM
     #[clap(verbatim_doc_comment)]
-    Manifests,
+    Show {
+        /// File to read
+        file_name: String,
+    },
+    /// Set the MAC address, fixing up the checksum
+    #[clap(verbatim_doc_comment)]
+    SetMac {
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: String,
+        /// MAC address, e.g., 00:11:22:33:44:55
+        #[clap(long, short)]
+        mac: String,
+        /// File to read
+        file_name: String,
+    },
 }
 
 #[derive(Parser)]
@@ -105,6 +312,65 @@ enum Command {
     /// Anything related to BootGuard, such as manifests
     #[command(subcommand)]
     Bg(BootGuardCommand),
+    /// Anything related to the Firmware Interface Table (FIT)
+    #[command(subcommand)]
+    Fit(FitCommand),
+    /// Decode and edit the soft straps in the flash descriptor
+    #[command(subcommand)]
+    Straps(StrapsCommand),
+    /// Flash components, i.e., SPI chips, and per-chip images
+    #[command(subcommand)]
+    Flash(FlashCommand),
+    /// Gigabit Ethernet (GbE) region, e.g., the MAC address
+    #[command(subcommand)]
+    Gbe(GbeCommand),
+    /// Analyze and edit the flash descriptor, like coreboot ifdtool
+    ///
+    /// Without any operation, the descriptor is dumped. Modified images are
+    /// written to FILE.new unless an output file is given.
+    #[clap(verbatim_doc_comment)]
+    Ifd {
+        /// Dump the descriptor
+        #[clap(long, short)]
+        dump: bool,
+        /// Extract all regions to flashregion_<n>_<name>.bin files
+        #[clap(long, short = 'x')]
+        extract: bool,
+        /// Write the regions to a flashprog layout file
+        #[clap(long, short = 'f')]
+        layout: Option<String>,
+        /// Apply a new layout from a flashprog layout file, moving the regions
+        #[clap(long = "newlayout", short)]
+        new_layout: Option<String>,
+        /// Inject a file into a region, given as REGION:FILE, e.g., bios:bios.bin
+        #[clap(long, short)]
+        inject: Option<String>,
+        /// Grant all masters full access to all regions
+        #[clap(long, short)]
+        unlock: bool,
+        /// Restrict the masters to the regions they need
+        #[clap(long, short)]
+        lock: bool,
+        /// Set (1) or clear (0) the MeDisable/AltMeDisable or HAP bits
+        #[clap(long, short = 'M', value_parser = clap::value_parser!(u8).range(0..=1))]
+        altmedisable: Option<u8>,
+        /// Platform, e.g., sklkbl, cnl, tgl, adl, or ifd2 for any IFD v2 platform
+        #[clap(long, short)]
+        platform: Option<String>,
+        /// File to write output to
+        #[clap(long, short = 'O')]
+        output: Option<String>,
+        /// File to read
+        file_name: String,
+    },
+    /// Audit the security posture of a full image
+    ///
+    /// Exit status: 0 if no finding is an error, 2 otherwise.
+    #[clap(verbatim_doc_comment)]
+    Audit {
+        /// File to read
+        file_name: String,
+    },
 }
 
 /// Analyze and modify Intel firmware images
@@ -120,6 +386,89 @@ struct Cli {
     verbose: bool,
 }
 
+/// Load the strap fields from a schema file or the built-in definitions.
+fn strap_fields(
+    schema: &Option<String>,
+    xml_base: &Option<String>,
+    fw: &Firmware,
+) -> Result<Vec<StrapField>, io::Error> {
+    if let Some(s) = schema {
+        let contents = fs::read_to_string(s)?;
+        let res = if s.to_lowercase().ends_with(".xml") {
+            let Some(b) = xml_base else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "XML schemas need the offset of PCHSTRP0 via --xml-base",
+                ));
+            };
+            schema::from_xml(&contents, parse_size(b)?)
+        } else {
+            schema::from_toml(&contents)
+        };
+        return res.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+    let family = match &fw.me {
+        Some(Ok(me)) => me
+            .version
+            .and_then(|v| ChipsetFamily::from_me(&me.generation, &v)),
+        _ => None,
+    };
+    match family {
+        Some(f) => {
+            info!("Using strap definitions for {f}");
+            Ok(f.fields())
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "chipset family unknown, please provide a strap schema",
+        )),
+    }
+}
+
+fn get_ifd(fw: &Firmware) -> Result<IFD, io::Error> {
+    fw.ifd
+        .clone()
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("no IFD found: {e:?}")))
+}
+
+fn parse_ifd(data: &[u8]) -> Result<IFD, io::Error> {
+    IFD::parse(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("no IFD found: {e:?}")))
+}
+
+/// Parse a size given in bytes, or with a K or M suffix.
+fn parse_size(s: &str) -> Result<usize, io::Error> {
+    let u = s.to_uppercase();
+    let (n, m) = match u.strip_suffix('M').or(u.strip_suffix("MB")) {
+        Some(n) => (n, 1024 * 1024),
+        None => match u.strip_suffix('K').or(u.strip_suffix("KB")) {
+            Some(n) => (n, 1024),
+            None => (u.as_str(), 1),
+        },
+    };
+    let n = match n.strip_prefix("0X") {
+        Some(h) => usize::from_str_radix(h, 16),
+        None => n.parse::<usize>(),
+    };
+    n.map(|n| n * m).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid size {s}: {e}"),
+        )
+    })
+}
+
+fn get_gbe(fw: &Firmware) -> Result<Gbe, io::Error> {
+    match &fw.gbe {
+        Some(Ok(gbe)) => Ok(gbe.clone()),
+        Some(Err(e)) => Err(io::Error::new(io::ErrorKind::InvalidData, e.clone())),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no GbE region found",
+        )),
+    }
+}
+
 fn main() -> Result<(), io::Error> {
     println!("Intel Firmware Tool 🔧");
     // Default to log level "info". Otherwise, you get no "regular" logs.
@@ -132,7 +481,347 @@ fn main() -> Result<(), io::Error> {
         verbose,
     } = Cli::parse();
     match cmd {
-        Command::Bg(_) => todo!(),
+        Command::Bg(cmd) => match cmd {
+            BootGuardCommand::Manifests { file_name } => {
+                let data = fs::read(file_name)?;
+                let fit = Fit::new(&data).map_err(|e| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no FIT found: {e:?}"))
+                })?;
+                let manifests = Manifests::from_fit(&fit, &data);
+                if verbose {
+                    println!("{manifests:#02x?}");
+                }
+                println!("{manifests}");
+            }
+            BootGuardCommand::Verify { file_name } => {
+                let data = fs::read(file_name)?;
+                let fit = Fit::new(&data).map_err(|e| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no FIT found: {e:?}"))
+                })?;
+                let findings = verify::verify(&fit, &data);
+                for f in &findings {
+                    println!("{f}");
+                }
+                println!();
+                if has_errors(&findings) {
+                    error!("Boot Guard verification failed");
+                    std::process::exit(EXIT_CHECK_FAILED);
+                }
+                info!("Boot Guard verification passed");
+            }
+        },
+        Command::Fit(cmd) => match cmd {
+            FitCommand::Show { file_name } => {
+                let data = fs::read(file_name)?;
+                let fit = Fit::new(&data).map_err(|e| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no FIT found: {e:?}"))
+                })?;
+                if verbose {
+                    println!("{fit:#02x?}");
+                }
+                show::print_fit(&fit);
+                show::print_fit_policies(&fit, &data);
+                println!();
+                for (addr, r) in microcode::from_fit(&fit, &data) {
+                    match r {
+                        Ok(m) => println!("Microcode update @ {addr:08x}: {m}"),
+                        Err(e) => warn!("Microcode update @ {addr:08x}: {e}"),
+                    }
+                }
+                for (addr, r) in acm::from_fit(&fit, &data) {
+                    match r {
+                        Ok(a) => {
+                            println!("ACM @ {addr:08x}: {a}");
+                            match a.verify() {
+                                Ok(()) => info!("ACM signature is valid"),
+                                Err(e) => error!("ACM signature error: {e}"),
+                            }
+                        }
+                        Err(e) => warn!("ACM @ {addr:08x}: {e}"),
+                    }
+                }
+            }
+            FitCommand::InsertMicrocode {
+                output,
+                microcode,
+                range,
+                fit_padding,
+                file_name,
+            } => {
+                let Some((start, end)) = range.split_once(':') else {
+                    let e = format!("expected START:END, got {range}");
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+                };
+                let free = parse_size(start)?..parse_size(end)?;
+                let padding = parse_size(&fit_padding)?;
+                let mut data = fs::read(file_name)?;
+                let blob = fs::read(microcode)?;
+                let mut fit = Fit::new(&data).map_err(|e| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no FIT found: {e:?}"))
+                })?;
+                let res = fit
+                    .insert_microcode(&mut data, &blob, free.clone())
+                    .and_then(|i| fit.write(&mut data, padding, Some(free)).map(|_| i));
+                match res {
+                    Ok(i) => info!("Microcode update is FIT entry {i}"),
+                    Err(e) => {
+                        error!("Could not insert microcode update: {e}");
+                        return Err(io::Error::other(e.to_string()));
+                    }
+                }
+                let mut file = fs::File::create(output)?;
+                file.write_all(&data)?;
+            }
+            FitCommand::Verify { file_name } => {
+                let data = fs::read(file_name)?;
+                let fit = Fit::new(&data).map_err(|e| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no FIT found: {e:?}"))
+                })?;
+                let findings = check_fit(&fit, &data);
+                for f in &findings {
+                    println!("{f}");
+                }
+                println!();
+                if has_errors(&findings) {
+                    error!("FIT verification failed");
+                    std::process::exit(EXIT_CHECK_FAILED);
+                }
+                info!("FIT verification passed");
+            }
+        },
+        Command::Straps(cmd) => match cmd {
+            StrapsCommand::Show {
+                schema,
+                xml_base,
+                file_name,
+            } => {
+                let data = fs::read(file_name)?;
+                let fw = Firmware::parse(&data, debug);
+                let ifd = get_ifd(&fw)?;
+                let fields = strap_fields(&schema, &xml_base, &fw)?;
+                show::print_straps(&ifd, &fields);
+            }
+            StrapsCommand::Set {
+                output,
+                schema,
+                xml_base,
+                assignments,
+                file_name,
+            } => {
+                let mut data = fs::read(file_name)?;
+                let fw = Firmware::parse(&data, debug);
+                let mut ifd = get_ifd(&fw)?;
+                let fields = strap_fields(&schema, &xml_base, &fw)?;
+                for a in assignments {
+                    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+                    let Some((name, value)) = a.split_once('=') else {
+                        return Err(invalid(format!("expected NAME=VALUE, got {a}")));
+                    };
+                    let value = match value.strip_prefix("0x") {
+                        Some(h) => u32::from_str_radix(h, 16),
+                        None => value.parse::<u32>(),
+                    }
+                    .map_err(|e| invalid(format!("invalid value for {name}: {e}")))?;
+                    ifd.set_strap(&fields, name, value)
+                        .map_err(|e| invalid(e.to_string()))?;
+                    info!("Set {name} to {value:#x}");
+                }
+                let new_ifd = ifd.to_vec();
+                data[..new_ifd.len()].copy_from_slice(&new_ifd);
+                let mut file = fs::File::create(output)?;
+                file.write_all(&data)?;
+            }
+        },
+        Command::Flash(cmd) => match cmd {
+            FlashCommand::Show { file_name } => {
+                let data = fs::read(file_name)?;
+                let ifd = parse_ifd(&data)?;
+                let sizes = ifd
+                    .component_sizes()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                for (i, s) in sizes.iter().enumerate() {
+                    println!("Component {}: {} ({s:#010x})", i + 1, format_size(*s));
+                }
+                match ifd.check_components(data.len()) {
+                    Ok(()) => info!("Components match the image size"),
+                    Err(e) => warn!("{e}"),
+                }
+            }
+            FlashCommand::SetComponents {
+                output,
+                sizes,
+                file_name,
+            } => {
+                let mut data = fs::read(file_name)?;
+                let mut ifd = parse_ifd(&data)?;
+                let sizes = sizes
+                    .iter()
+                    .map(|s| parse_size(s))
+                    .collect::<Result<Vec<usize>, io::Error>>()?;
+                ifd.set_component_sizes(&sizes)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+                if let Err(e) = ifd.check_components(data.len()) {
+                    warn!("{e}");
+                }
+                let new_ifd = ifd.to_vec();
+                data[..new_ifd.len()].copy_from_slice(&new_ifd);
+                let mut file = fs::File::create(output)?;
+                file.write_all(&data)?;
+            }
+            FlashCommand::Split { output, file_name } => {
+                let data = fs::read(file_name)?;
+                let ifd = parse_ifd(&data)?;
+                let chips = ifd
+                    .split(&data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                if chips.len() != output.len() {
+                    let (c, o) = (chips.len(), output.len());
+                    let e = format!("{c} component(s), but {o} output file(s) given");
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+                }
+                for (c, o) in chips.iter().zip(output) {
+                    info!("Writing {} to {o}", format_size(c.len()));
+                    let mut file = fs::File::create(o)?;
+                    file.write_all(c)?;
+                }
+            }
+            FlashCommand::Merge { output, file_names } => {
+                let chips = file_names
+                    .iter()
+                    .map(fs::read)
+                    .collect::<Result<Vec<Vec<u8>>, io::Error>>()?;
+                let chips = chips.iter().map(|c| c.as_slice()).collect::<Vec<&[u8]>>();
+                let data = components::merge(&chips)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let mut file = fs::File::create(output)?;
+                file.write_all(&data)?;
+            }
+            FlashCommand::ExportLayout {
+                output,
+                fmd,
+                file_name,
+            } => {
+                let data = fs::read(file_name)?;
+                let ifd = parse_ifd(&data)?;
+                let layout = if fmd {
+                    fmd::export(&ifd)
+                } else {
+                    flashprog::export(&ifd.regions)
+                };
+                let mut file = fs::File::create(output)?;
+                file.write_all(layout.as_bytes())?;
+            }
+            FlashCommand::BuildDescriptor { output, full, spec } => {
+                let contents = fs::read_to_string(&spec)?;
+                let spec = if spec.to_lowercase().ends_with(".json") {
+                    Spec::from_json(&contents)
+                } else {
+                    Spec::from_toml(&contents)
+                }
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let ifd = IFD::from_spec(&spec)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let size = ifd.flash_size().unwrap_or_default();
+                let mut data = ifd.to_vec();
+                if full {
+                    data.resize(size, EMPTY);
+                }
+                info!("Writing {} to {output}", format_size(data.len()));
+                let mut file = fs::File::create(output)?;
+                file.write_all(&data)?;
+            }
+            FlashCommand::CheckLayout { layout, file_name } => {
+                let data = fs::read(file_name)?;
+                let ifd = parse_ifd(&data)?;
+                let contents = fs::read_to_string(&layout)?;
+                let entries = if layout.to_lowercase().ends_with(".fmd") {
+                    fmd::import(&contents)
+                } else {
+                    flashprog::import(&contents)
+                }
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                for e in &entries {
+                    println!("{e}");
+                }
+                println!();
+                let findings = validate_layout(&ifd, &entries, data.len());
+                for f in &findings {
+                    println!("{f}");
+                }
+                if has_errors(&findings) {
+                    error!("Layout does not match the image");
+                    std::process::exit(EXIT_CHECK_FAILED);
+                }
+                info!("Layout matches the image");
+            }
+        },
+        Command::Ifd {
+            dump,
+            extract,
+            layout,
+            new_layout,
+            inject,
+            unlock,
+            lock,
+            altmedisable,
+            platform,
+            output,
+            file_name,
+        } => {
+            let opts = ifdtool::Options {
+                dump,
+                extract,
+                layout,
+                new_layout,
+                inject,
+                unlock,
+                lock,
+                altmedisable: altmedisable.map(|a| a == 1),
+                platform,
+            };
+            ifdtool::ifdtool(&file_name, output, opts, debug)?;
+        }
+        Command::Audit { file_name } => {
+            let data = fs::read(file_name)?;
+            let fw = Firmware::parse(&data, debug);
+            let findings = fw.audit(&data);
+            for f in &findings {
+                println!("{f}");
+            }
+            println!();
+            if has_errors(&findings) {
+                error!("Audit found security issues");
+                std::process::exit(EXIT_CHECK_FAILED);
+            }
+            info!("Audit found no security issues");
+        }
+        Command::Gbe(cmd) => match cmd {
+            GbeCommand::Show { file_name } => {
+                let data = fs::read(file_name)?;
+                let fw = Firmware::parse(&data, debug);
+                let gbe = get_gbe(&fw)?;
+                if verbose {
+                    println!("{gbe:#02x?}");
+                }
+                println!("{gbe}");
+            }
+            GbeCommand::SetMac {
+                output,
+                mac,
+                file_name,
+            } => {
+                let mut data = fs::read(file_name)?;
+                let fw = Firmware::parse(&data, debug);
+                let mut gbe = get_gbe(&fw)?;
+                let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+                let mac = mac.parse::<MacAddress>().map_err(invalid)?;
+                gbe.set_mac(mac).map_err(invalid)?;
+                gbe.write(&mut data).map_err(io::Error::other)?;
+                info!("Set MAC address to {mac}");
+                let mut file = fs::File::create(output)?;
+                file.write_all(&data)?;
+            }
+        },
         Command::Me(cmd) => match cmd {
             MeCommand::Clean {
                 descriptor,
@@ -141,6 +830,7 @@ fn main() -> Result<(), io::Error> {
                 soft_disable,
                 soft_disable_only,
                 truncate,
+                extend_bios,
                 whitelist,
                 blacklist,
                 file_name,
@@ -154,6 +844,7 @@ fn main() -> Result<(), io::Error> {
                 debug!("  Retain FTPR modules:     {keep_modules}");
                 debug!("  Relocate FTPR partition: {relocate}");
                 debug!("  Truncate empty parts:    {truncate}");
+                debug!("  Extend BIOS region:      {extend_bios}");
                 let disable_me = match (soft_disable, soft_disable_only) {
                     (true, false) => "yes",
                     (false, false) => "no",
@@ -181,7 +872,7 @@ fn main() -> Result<(), io::Error> {
                 info!("Reading {file_name}...");
                 let mut data = fs::read(file_name)?;
                 let fw = Firmware::parse(&data, debug);
-                show::show(&fw, verbose);
+                show::show(&fw, &data, verbose);
                 println!();
 
                 let me = fw
@@ -192,31 +883,26 @@ fn main() -> Result<(), io::Error> {
                     ))?
                     .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                 if check {
-                    let fpt = &me.fpt_area.fpt;
-                    let cs = fpt.header_checksum();
-                    if cs == fpt.header.checksum {
-                        println!("FPT checksum is correct");
-                    } else {
-                        println!(
-                            "FPT checksum error: is {:02x}, should be {cs:08x}",
-                            fpt.header.checksum
-                        );
+                    let region_size = match &fw.ifd {
+                        Ok(ifd) => ifd.regions.me_range().len(),
+                        Err(_) => me.fpt_area.original_size,
+                    };
+                    let findings = check_me(&me, region_size);
+                    for f in &findings {
+                        println!("{f}");
                     }
-                    match &me.fpt_area.check_ftpr_presence() {
-                        Ok(()) => println!("FTPR exists"),
-                        Err(e) => println!("FTPR error: {e:}"),
-                    }
-                    for (n, r) in me.fpt_area.check_dir_sigs() {
-                        match r {
-                            Ok(()) => println!("  {n}: signature is valid"),
-                            Err(e) => println!("  {n}: signature error: {e}"),
-                        }
+                    if has_errors(&findings) {
+                        error!("Integrity check failed");
+                        std::process::exit(EXIT_CHECK_FAILED);
                     }
                     return Ok(());
                 }
                 let opts = clean::Options {
                     keep_modules,
                     relocate,
+                    descriptor,
+                    truncate,
+                    extend_bios,
                     disable_me: soft_disable,
                     disable_me_only: soft_disable_only,
                     parts_force_retention: whitelist.unwrap_or(vec![]),
@@ -256,15 +942,26 @@ fn main() -> Result<(), io::Error> {
             MeCommand::Scan { file_name } => {
                 let data = fs::read(file_name)?;
                 let fw = Firmware::scan(&data, debug);
-                show::show(&fw, verbose);
+                show::show(&fw, &data, verbose);
             }
             MeCommand::Check { file_name } => {
-                todo!("check {file_name}")
+                let data = fs::read(file_name)?;
+                let fw = Firmware::parse(&data, debug);
+                let findings = fw.check(&data);
+                for f in &findings {
+                    println!("{f}");
+                }
+                println!();
+                if has_errors(&findings) {
+                    error!("Integrity check failed");
+                    std::process::exit(EXIT_CHECK_FAILED);
+                }
+                info!("Integrity check passed");
             }
             MeCommand::Show { file_name } => {
                 let data = fs::read(file_name)?;
                 let fw = Firmware::parse(&data, debug);
-                show::show(&fw, verbose);
+                show::show(&fw, &data, verbose);
             }
             MeCommand::Extract {
                 part_name,
@@ -12,7 +12,9 @@ use crate::dir::gen2::Module;
 use crate::dir::{
     gen2::Directory as Gen2Directory,
     gen3::{CPD_MAGIC_BYTES, CodePartitionDirectory},
+    man::Manifest,
 };
+use crate::meta::{Variant, get_meta_for_key};
 use crate::part::{
     fpt::{FPT, FTPR, MIN_FPT_SIZE},
     gen2::{DirPartition, Gen2Partition},
@@ -300,6 +302,50 @@ pub struct ME {
 }
 
 impl ME {
+    /// Get all signed manifests, along with the names of their partitions
+    /// or directories.
+    pub fn manifests(&self) -> Vec<(String, &Manifest)> {
+        let mut res = vec![];
+        match &self.fpt_area.partitions {
+            Partitions::Gen2(parts) => {
+                for p in parts {
+                    if let Gen2Partition::Dir(d) = p {
+                        res.push((d.entry.name(), &d.dir.manifest));
+                    }
+                }
+            }
+            Partitions::Gen3(parts) => {
+                for p in parts {
+                    if let Gen3Partition::Dir(d) = p
+                        && let Ok(m) = &d.cpd.manifest
+                    {
+                        res.push((d.entry.name(), m));
+                    }
+                }
+            }
+            Partitions::Unknown(_) => {}
+        }
+        for c in &self.cpds {
+            if let Ok(m) = &c.manifest {
+                res.push((c.name.clone(), m));
+            }
+        }
+        res
+    }
+
+    /// Get the firmware variant from the keys the manifests are signed with,
+    /// if any of them is known.
+    pub fn variant(&self) -> Option<Variant> {
+        self.manifests().iter().find_map(|(_, m)| {
+            let h = m
+                .hash_key()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>();
+            get_meta_for_key(&h).map(|meta| meta.variant)
+        })
+    }
+
     pub fn parse(data: &[u8], base: usize, debug: bool) -> Option<Result<Self, String>> {
         if let Some(r) = FPT::parse(data) {
             let fpt = match r {
@@ -5,12 +5,13 @@
 use std::fmt::Display;
 
 use phf::phf_map;
+use serde::{Deserialize, Serialize};
 
 /// Firmware variant:
 /// - regular (ME) <https://www.intel.com/content/www/us/en/support/articles/000030079/software/chipset-software.html>
 /// - Trusted Execution Engine (TXE) <https://www.intel.com/content/www/us/en/support/articles/000030081/software/chipset-software.html>
 /// - Server Platform Services (SPS) <https://designintools.intel.com/intel-server-platform-services-sps-manageability-engine-me-firmware-tools.html>
-#[derive(Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Variant {
     ME,
     TXE,
@@ -0,0 +1,405 @@
+//! CPU microcode updates
+//!
+//! Microcode updates are referenced by `MicrocodeUpdate` FIT entries, so that
+//! the CPU can load them before executing any host firmware. Each update has
+//! a 48 bytes header, followed by the encrypted data and, optionally, an
+//! extended signature table listing further processors it applies to.
+//!
+//! For the format, see the Intel SDM, Vol. 3A, section 10.11
+//! "Microcode Update Facilities", and Linux `arch/x86/kernel/cpu/microcode/`.
+
+use core::fmt::{self, Display};
+use core::num::Wrapping;
+use serde::{Deserialize, Serialize};
+use zerocopy::{FromBytes, IntoBytes, Ref};
+use zerocopy_derive::{FromBytes, Immutable, IntoBytes};
+
+use crate::fit::{EntryType, Fit};
+
+/// The only header version defined so far
+pub const HEADER_VERSION: u32 = 1;
+/// Data size to assume if the header says 0
+pub const DEFAULT_DATA_SIZE: usize = 2000;
+/// Total size to assume if the header says 0
+pub const DEFAULT_TOTAL_SIZE: usize = 2048;
+
+// There are typically only a few extended signatures.
+const MAX_EXT_SIGNATURES: usize = 64;
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct MicrocodeHeader {
+    pub header_version: u32,
+    pub revision: u32,
+    /// BCD encoded, mmddyyyy
+    pub date: u32,
+    /// CPUID signature, i.e., EAX of CPUID leaf 1
+    pub signature: u32,
+    pub checksum: u32,
+    pub loader_revision: u32,
+    /// Bit mask of platform IDs (MSR 0x17 bits 52:50)
+    pub processor_flags: u32,
+    pub data_size: u32,
+    pub total_size: u32,
+    pub _reserved: [u8; 12],
+}
+
+pub const HEADER_SIZE: usize = core::mem::size_of::<MicrocodeHeader>();
+
+impl MicrocodeHeader {
+    pub fn data_size(&self) -> usize {
+        match self.data_size {
+            0 => DEFAULT_DATA_SIZE,
+            s => s as usize,
+        }
+    }
+
+    pub fn total_size(&self) -> usize {
+        match self.total_size {
+            0 => DEFAULT_TOTAL_SIZE,
+            s => s as usize,
+        }
+    }
+
+    /// Format the BCD encoded date as yyyy-mm-dd.
+    pub fn date(&self) -> String {
+        let d = self.date;
+        let y = d & 0xffff;
+        let m = d >> 24;
+        let day = (d >> 16) & 0xff;
+        format!("{y:04x}-{m:02x}-{day:02x}")
+    }
+}
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct ExtSignatureTableHeader {
+    pub count: u32,
+    pub checksum: u32,
+    pub _reserved: [u8; 12],
+}
+
+const EXT_HEADER_SIZE: usize = core::mem::size_of::<ExtSignatureTableHeader>();
+
+#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct ExtSignature {
+    pub signature: u32,
+    pub processor_flags: u32,
+    pub checksum: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExtSignatureTable {
+    pub header: ExtSignatureTableHeader,
+    pub signatures: Vec<ExtSignature>,
+    /// Whether the table sums up to 0
+    pub checksum_valid: bool,
+    /// Per signature: whether its checksum matches the update with its
+    /// signature and processor flags swapped into the header
+    pub signature_checksums_valid: Vec<bool>,
+}
+
+/// Decoded CPUID signature
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cpuid {
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+}
+
+impl Cpuid {
+    pub fn from_signature(sig: u32) -> Self {
+        let stepping = sig & 0xf;
+        let base_model = (sig >> 4) & 0xf;
+        let base_family = (sig >> 8) & 0xf;
+        let ext_model = (sig >> 16) & 0xf;
+        let ext_family = (sig >> 20) & 0xff;
+        let family = if base_family == 0xf {
+            base_family + ext_family
+        } else {
+            base_family
+        };
+        let model = if base_family == 0x6 || base_family == 0xf {
+            (ext_model << 4) | base_model
+        } else {
+            base_model
+        };
+        Self {
+            family,
+            model,
+            stepping,
+        }
+    }
+}
+
+impl Display for Cpuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            family,
+            model,
+            stepping,
+        } = self;
+        write!(
+            f,
+            "family {family:x}, model {model:02x}, stepping {stepping:x}"
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Microcode {
+    pub header: MicrocodeHeader,
+    /// Whether the header and data sum up to 0
+    pub checksum_valid: bool,
+    pub ext_table: Option<ExtSignatureTable>,
+}
+
+fn sum32(data: &[u8]) -> u32 {
+    data.chunks_exact(4)
+        .map(|c| Wrapping(u32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+        .sum::<Wrapping<u32>>()
+        .0
+}
+
+impl Microcode {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let Ok((header, _)) = MicrocodeHeader::read_from_prefix(data) else {
+            return Err("could not read microcode header".into());
+        };
+        let v = header.header_version;
+        if v != HEADER_VERSION {
+            return Err(format!("unknown microcode header version {v:08x}"));
+        }
+        let data_size = header.data_size();
+        let total_size = header.total_size();
+        if !data_size.is_multiple_of(4) || !total_size.is_multiple_of(4) {
+            return Err(format!(
+                "sizes {data_size:08x} / {total_size:08x} not a multiple of 4"
+            ));
+        }
+        if HEADER_SIZE + data_size > total_size {
+            return Err(format!(
+                "data size {data_size:08x} exceeds total size {total_size:08x}"
+            ));
+        }
+        let Some(update) = data.get(..total_size) else {
+            return Err(format!(
+                "total size {total_size:08x} exceeds available {:08x}",
+                data.len()
+            ));
+        };
+        // The extended signature table is covered by its own checksum.
+        let checksum_valid = sum32(&update[..HEADER_SIZE + data_size]) == 0;
+
+        let ext = &update[HEADER_SIZE + data_size..];
+        let ext_table = if ext.len() >= EXT_HEADER_SIZE {
+            Some(Self::parse_ext_table(&header, ext)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            header,
+            checksum_valid,
+            ext_table,
+        })
+    }
+
+    fn parse_ext_table(main: &MicrocodeHeader, data: &[u8]) -> Result<ExtSignatureTable, String> {
+        let Ok((header, rest)) = ExtSignatureTableHeader::read_from_prefix(data) else {
+            return Err("could not read extended signature table header".into());
+        };
+        let count = header.count as usize;
+        if count > MAX_EXT_SIGNATURES {
+            return Err(format!("extended signature count {count} too big"));
+        }
+        let Ok((r, _)) = Ref::<_, [ExtSignature]>::from_prefix_with_elems(rest, count) else {
+            return Err(format!("could not read {count} extended signatures"));
+        };
+        let signatures = r.to_vec();
+        let size = EXT_HEADER_SIZE + signatures.as_bytes().len();
+        let checksum_valid = sum32(&data[..size]) == 0;
+        // Swapping in an entry's values must keep the header and data at 0.
+        let sum = |s: u32, p: u32, c: u32| s.wrapping_add(p).wrapping_add(c);
+        let expected = sum(main.signature, main.processor_flags, main.checksum);
+        let signature_checksums_valid = signatures
+            .iter()
+            .map(|e| sum(e.signature, e.processor_flags, e.checksum) == expected)
+            .collect();
+        Ok(ExtSignatureTable {
+            header,
+            signatures,
+            checksum_valid,
+            signature_checksums_valid,
+        })
+    }
+
+    /// Get all CPUID signatures and platform ID masks the update applies to.
+    pub fn signatures(&self) -> Vec<(u32, u32)> {
+        let h = self.header;
+        let mut res = vec![(h.signature, h.processor_flags)];
+        if let Some(t) = &self.ext_table {
+            res.extend(
+                t.signatures
+                    .iter()
+                    .map(|s| (s.signature, s.processor_flags)),
+            );
+        }
+        res
+    }
+}
+
+impl Display for Microcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let h = self.header;
+        let r = h.revision;
+        let d = h.date();
+        let ds = h.data_size();
+        let ts = h.total_size();
+        let cs = if self.checksum_valid {
+            "checksum valid"
+        } else {
+            "checksum INVALID"
+        };
+        write!(
+            f,
+            "revision {r:08x}, {d}, data size {ds:08x}, total size {ts:08x}, {cs}"
+        )?;
+        if let Some(t) = &self.ext_table {
+            let c = t.signatures.len();
+            let cs = if t.checksum_valid { "valid" } else { "INVALID" };
+            write!(
+                f,
+                "\n  extended signature table, {c} entries, checksum {cs}"
+            )?;
+        }
+        // The first signature is the one from the header.
+        let mut valid = vec![true];
+        if let Some(t) = &self.ext_table {
+            valid.extend(&t.signature_checksums_valid);
+        }
+        for ((s, p), v) in self.signatures().into_iter().zip(valid) {
+            let c = Cpuid::from_signature(s);
+            write!(f, "\n  CPUID {s:08x} ({c}), platform IDs {p:02x}")?;
+            if !v {
+                write!(f, ", checksum INVALID")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse all microcode updates referenced by the FIT, along with their
+/// physical addresses.
+pub fn from_fit(fit: &Fit, data: &[u8]) -> Vec<(u64, Result<Microcode, String>)> {
+    fit.entries
+        .iter()
+        .filter(|e| matches!(e.get_type(), Ok(EntryType::MicrocodeUpdate)))
+        .map(|e| {
+            let addr = e.addr;
+            let r = fit.offset_for(addr).and_then(|o| match data.get(o..) {
+                Some(d) => Microcode::parse(d),
+                None => Err(format!("{addr:08x} ({o:08x}) out of bounds")),
+            });
+            (addr, r)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) fn raw_update(signature: u32, ext: &[(u32, u32)]) -> Vec<u8> {
+    let data_size = 0x40usize;
+    let ext_size = if ext.is_empty() {
+        0
+    } else {
+        EXT_HEADER_SIZE + ext.len() * 12
+    };
+    let total_size = HEADER_SIZE + data_size + ext_size;
+    let header = MicrocodeHeader {
+        header_version: HEADER_VERSION,
+        revision: 0xf0,
+        date: 0x0903_2021,
+        signature,
+        checksum: 0,
+        loader_revision: 1,
+        processor_flags: 0x22,
+        data_size: data_size as u32,
+        total_size: total_size as u32,
+        _reserved: [0; 12],
+    };
+    let mut raw = [header.as_bytes(), &[0x5a; 0x40]].concat();
+    let cs = 0u32.wrapping_sub(sum32(&raw));
+    raw[16..20].copy_from_slice(&cs.to_le_bytes());
+    if !ext.is_empty() {
+        let mut t = (ext.len() as u32).to_le_bytes().to_vec();
+        t.extend([0; 16]);
+        for (s, p) in ext {
+            // Each entry's checksum is for the header with its values swapped in.
+            let c = cs.wrapping_add(signature).wrapping_add(0x22);
+            let c = c.wrapping_sub(*s).wrapping_sub(*p);
+            t.extend([s.to_le_bytes(), p.to_le_bytes(), c.to_le_bytes()].concat());
+        }
+        let tcs = 0u32.wrapping_sub(sum32(&t));
+        t[4..8].copy_from_slice(&tcs.to_le_bytes());
+        raw.extend(t);
+    }
+    raw
+}
+
+#[test]
+fn parse_microcode() {
+    let raw = raw_update(0x000906ea, &[]);
+    let m = Microcode::parse(&raw).unwrap();
+    assert!(m.checksum_valid);
+    assert!(m.ext_table.is_none());
+    assert_eq!(m.header.date(), "2021-09-03");
+    let c = Cpuid::from_signature(0x000906ea);
+    assert_eq!((c.family, c.model, c.stepping), (6, 0x9e, 0xa));
+}
+
+#[test]
+fn parse_microcode_ext_table() {
+    let raw = raw_update(0x000906ea, &[(0x000906eb, 0x02), (0x000906ec, 0x22)]);
+    let m = Microcode::parse(&raw).unwrap();
+    assert!(m.checksum_valid);
+    let t = m.ext_table.as_ref().unwrap();
+    assert!(t.checksum_valid);
+    assert_eq!(t.signature_checksums_valid, [true, true]);
+    assert_eq!(m.signatures().len(), 3);
+}
+
+#[test]
+fn parse_microcode_bad_ext_signature() {
+    let mut raw = raw_update(0x000906ea, &[(0x000906eb, 0x02), (0x000906ec, 0x22)]);
+    // Bump the second entry's checksum and compensate in the table checksum,
+    // so that only the entry itself is wrong.
+    let e = HEADER_SIZE + 0x40 + EXT_HEADER_SIZE + 12 + 8;
+    let c = u32::from_le_bytes([raw[e], raw[e + 1], raw[e + 2], raw[e + 3]]);
+    raw[e..e + 4].copy_from_slice(&c.wrapping_add(1).to_le_bytes());
+    let t = HEADER_SIZE + 0x40 + 4;
+    let c = u32::from_le_bytes([raw[t], raw[t + 1], raw[t + 2], raw[t + 3]]);
+    raw[t..t + 4].copy_from_slice(&c.wrapping_sub(1).to_le_bytes());
+    let m = Microcode::parse(&raw).unwrap();
+    assert!(m.checksum_valid);
+    let t = m.ext_table.as_ref().unwrap();
+    assert!(t.checksum_valid);
+    assert_eq!(t.signature_checksums_valid, [true, false]);
+}
+
+#[test]
+fn parse_microcode_checksum_excludes_ext_table() {
+    let mut raw = raw_update(0x000906ea, &[(0x000906eb, 0x02)]);
+    let t = HEADER_SIZE + 0x40 + 4;
+    raw[t] ^= 0xff;
+    let m = Microcode::parse(&raw).unwrap();
+    assert!(m.checksum_valid);
+    assert!(!m.ext_table.as_ref().unwrap().checksum_valid);
+}
+
+#[test]
+fn parse_microcode_bad_checksum() {
+    let mut raw = raw_update(0x000806ec, &[]);
+    raw[HEADER_SIZE] ^= 0xff;
+    let m = Microcode::parse(&raw).unwrap();
+    assert!(!m.checksum_valid);
+}
@@ -0,0 +1,474 @@
+//! Platform identification
+//!
+//! An image does not state which chipset and CPU it is made for. Several of
+//! its parts hint at it though: the ME version follows the chipset, the key
+//! that signed the ME manifests tells the firmware variant, the descriptor
+//! layout changed over time, and the microcode updates in the FIT name the
+//! CPUs supported. Each hint is recorded as an [`Evidence`] voting for a set
+//! of chipset families with a weight, which is split among the families. The
+//! family with the most votes wins, and the share of the votes it got is given
+//! as the confidence. If several families get the most votes, the evidence
+//! does not tell them apart, and no family is picked.
+
+use core::fmt::{self, Display};
+use core::ops::RangeInclusive;
+use serde::{Deserialize, Serialize};
+
+use crate::Firmware;
+use crate::ifd::{IFD, IfdVersion, straps::ChipsetFamily};
+use crate::me::{Generation, ME};
+use crate::meta::{Variant, get_meta_for_key};
+use crate::microcode::{self, Cpuid};
+use crate::ver::Version;
+
+use ChipsetFamily::*;
+
+const ICH: &[ChipsetFamily] = &[Ich8, Ich9, Ich10];
+const PCH_GEN2: &[ChipsetFamily] = &[Series5, Series6, Series7, Series8, Series9];
+const PCH_GEN3: &[ChipsetFamily] = &[Series100, Series300, Series400, Series500, Series600];
+
+// Weights of the different kinds of evidence
+const WEIGHT_ME_VERSION: u32 = 50;
+const WEIGHT_ME_GENERATION: u32 = 20;
+const WEIGHT_KEY: u32 = 20;
+const WEIGHT_CPU: u32 = 20;
+const WEIGHT_IFD: u32 = 10;
+const WEIGHT_WEAK: u32 = 5;
+
+/// A hint at the platform, found in the image
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Evidence {
+    /// Part of the image the hint comes from, e.g., "IFD"
+    pub source: String,
+    pub description: String,
+    pub weight: u32,
+    /// Chipset families the hint is in favor of, may be empty
+    pub families: Vec<ChipsetFamily>,
+}
+
+impl Evidence {
+    fn new(source: &str, description: String, weight: u32, families: &[ChipsetFamily]) -> Self {
+        Self {
+            source: source.to_string(),
+            description,
+            weight,
+            families: families.to_vec(),
+        }
+    }
+}
+
+impl Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            source,
+            description,
+            weight,
+            ..
+        } = self;
+        write!(f, "{source}: {description} (weight {weight})")
+    }
+}
+
+/// The detected platform
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Platform {
+    pub family: Option<ChipsetFamily>,
+    /// Families with the most votes, more than one if they are tied
+    pub candidates: Vec<ChipsetFamily>,
+    /// Firmware variant, as told by the ME signing key
+    pub variant: Option<Variant>,
+    /// CPU code names from the microcode updates
+    pub cpus: Vec<String>,
+    /// Share of the evidence in favor of the family, in percent
+    pub confidence: u8,
+    pub evidence: Vec<Evidence>,
+}
+
+impl Platform {
+    /// Weigh the evidence and pick the chipset family with the most votes,
+    /// unless there is a tie.
+    pub fn from_evidence(
+        evidence: Vec<Evidence>,
+        variant: Option<Variant>,
+        cpus: Vec<String>,
+    ) -> Self {
+        // Evidence for several families is split evenly among them.
+        let total: u32 = evidence.iter().map(|e| e.weight).sum();
+        let score = |f: ChipsetFamily| -> f64 {
+            evidence
+                .iter()
+                .filter(|e| e.families.contains(&f))
+                .map(|e| f64::from(e.weight) / e.families.len() as f64)
+                .sum()
+        };
+        let scores = [ICH, PCH_GEN2, PCH_GEN3]
+            .concat()
+            .into_iter()
+            .map(|f| (f, score(f)))
+            .filter(|(_, s)| *s > 0.0)
+            .collect::<Vec<(ChipsetFamily, f64)>>();
+        let best = scores.iter().map(|(_, s)| *s).fold(0.0, f64::max);
+        // Shares of the same votes add up to the same score exactly.
+        let candidates = scores
+            .iter()
+            .filter(|(_, s)| *s == best)
+            .map(|(f, _)| *f)
+            .collect::<Vec<ChipsetFamily>>();
+        let family = match candidates[..] {
+            [f] => Some(f),
+            _ => None,
+        };
+        let confidence = match total {
+            0 => 0,
+            t => (best * 100.0 / f64::from(t)).round() as u8,
+        };
+        Self {
+            family,
+            candidates,
+            variant,
+            cpus,
+            confidence,
+            evidence,
+        }
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpus = match self.family {
+            Some(fam) => {
+                let c = self
+                    .cpus
+                    .iter()
+                    .filter(|c| cpu_families(c).contains(&fam))
+                    .map(|c| c.as_str())
+                    .collect::<Vec<&str>>();
+                match c.is_empty() {
+                    true => family_cpu(fam).to_string(),
+                    false => c.join(", "),
+                }
+            }
+            None => self.cpus.join(", "),
+        };
+        let n = self.candidates.len();
+        match (self.family, cpus.is_empty()) {
+            (Some(fam), _) => write!(f, "{} / {cpus}", pch_name(fam))?,
+            (None, false) if n > 1 => write!(f, "one of {n} chipset families / {cpus}")?,
+            (None, true) if n > 1 => write!(f, "one of {n} chipset families")?,
+            (None, false) => write!(f, "{cpus}")?,
+            (None, true) => write!(f, "unknown")?,
+        }
+        if let Some(v) = self.variant
+            && v != Variant::ME
+        {
+            write!(f, " ({v:?})")?;
+        }
+        Ok(())
+    }
+}
+
+fn pch_name(f: ChipsetFamily) -> &'static str {
+    match f {
+        Ich8 => "ICH8",
+        Ich9 => "ICH9",
+        Ich10 => "ICH10",
+        Series5 => "Ibex Peak",
+        Series6 => "Cougar Point",
+        Series7 => "Panther Point",
+        Series8 => "Lynx Point",
+        Series9 => "Wildcat Point",
+        Series100 => "Sunrise Point",
+        Series300 => "Cannon Point",
+        Series400 => "Comet Point",
+        Series500 => "Tiger Point",
+        Series600 => "Alder Point",
+    }
+}
+
+// The CPU most commonly paired with the chipset family
+fn family_cpu(f: ChipsetFamily) -> &'static str {
+    match f {
+        Ich8 | Ich9 | Ich10 => "Core 2",
+        Series5 => "Nehalem",
+        Series6 => "Sandy Bridge",
+        Series7 => "Ivy Bridge",
+        Series8 => "Haswell",
+        Series9 => "Broadwell",
+        Series100 => "Skylake",
+        Series300 => "Coffee Lake",
+        Series400 => "Comet Lake",
+        Series500 => "Rocket Lake",
+        Series600 => "Alder Lake",
+    }
+}
+
+// family 6 model, steppings, code name, chipset families
+type CpuDef = (
+    u32,
+    RangeInclusive<u32>,
+    &'static str,
+    &'static [ChipsetFamily],
+);
+
+const ALL_STEPPINGS: RangeInclusive<u32> = 0..=0xf;
+
+const CPUS: &[CpuDef] = &[
+    (0x0f, ALL_STEPPINGS, "Merom", &[Ich8]),
+    (0x17, ALL_STEPPINGS, "Penryn", &[Ich9, Ich10]),
+    (0x1a, ALL_STEPPINGS, "Nehalem", &[Series5]),
+    (0x1e, ALL_STEPPINGS, "Nehalem", &[Series5]),
+    (0x1f, ALL_STEPPINGS, "Nehalem", &[Series5]),
+    (0x25, ALL_STEPPINGS, "Westmere", &[Series5]),
+    (0x2a, ALL_STEPPINGS, "Sandy Bridge", &[Series6, Series7]),
+    (0x3a, ALL_STEPPINGS, "Ivy Bridge", &[Series6, Series7]),
+    (0x3c, ALL_STEPPINGS, "Haswell", &[Series8, Series9]),
+    (0x45, ALL_STEPPINGS, "Haswell", &[Series8]),
+    (0x46, ALL_STEPPINGS, "Haswell", &[Series8, Series9]),
+    (0x3d, ALL_STEPPINGS, "Broadwell", &[Series9]),
+    (0x47, ALL_STEPPINGS, "Broadwell", &[Series9]),
+    (0x4e, ALL_STEPPINGS, "Skylake", &[Series100]),
+    (0x5e, ALL_STEPPINGS, "Skylake", &[Series100]),
+    (0x5c, ALL_STEPPINGS, "Apollo Lake", &[]),
+    (0x7a, ALL_STEPPINGS, "Gemini Lake", &[]),
+    (0x8e, 0..=0xa, "Kaby Lake", &[Series100]),
+    (0x8e, 0xb..=0xc, "Whiskey Lake", &[Series300]),
+    (0x9e, 0..=0x9, "Kaby Lake", &[Series100]),
+    (0x9e, 0xa..=0xf, "Coffee Lake", &[Series300]),
+    (0x66, ALL_STEPPINGS, "Cannon Lake", &[Series300]),
+    (0x7d, ALL_STEPPINGS, "Ice Lake", &[Series400]),
+    (0x7e, ALL_STEPPINGS, "Ice Lake", &[Series400]),
+    (0xa5, ALL_STEPPINGS, "Comet Lake", &[Series400]),
+    (0xa6, ALL_STEPPINGS, "Comet Lake", &[Series400]),
+    (0x8c, ALL_STEPPINGS, "Tiger Lake", &[Series500]),
+    (0x8d, ALL_STEPPINGS, "Tiger Lake", &[Series500]),
+    (0xa7, ALL_STEPPINGS, "Rocket Lake", &[Series500]),
+    (0x97, ALL_STEPPINGS, "Alder Lake", &[Series600]),
+    (0x9a, ALL_STEPPINGS, "Alder Lake", &[Series600]),
+    (0xb7, ALL_STEPPINGS, "Raptor Lake", &[Series600]),
+    (0xba, ALL_STEPPINGS, "Raptor Lake", &[Series600]),
+    (0xbf, ALL_STEPPINGS, "Raptor Lake", &[Series600]),
+];
+
+/// Look up the code name and chipset families of a CPU.
+pub fn cpu_info(c: &Cpuid) -> Option<(&'static str, &'static [ChipsetFamily])> {
+    if c.family != 6 {
+        return None;
+    }
+    CPUS.iter()
+        .find(|(m, s, _, _)| *m == c.model && s.contains(&c.stepping))
+        .map(|(_, _, n, f)| (*n, *f))
+}
+
+// All chipset families known to go with a CPU code name
+fn cpu_families(name: &str) -> Vec<ChipsetFamily> {
+    CPUS.iter()
+        .filter(|(_, _, n, _)| *n == name)
+        .flat_map(|(_, _, _, f)| f.iter().copied())
+        .collect()
+}
+
+fn families_for_generation(g: &Generation) -> &'static [ChipsetFamily] {
+    match g {
+        Generation::Gen1 => ICH,
+        Generation::Gen2 => PCH_GEN2,
+        Generation::Gen3 => PCH_GEN3,
+        Generation::Unknown => &[],
+    }
+}
+
+fn ifd_evidence(ifd: &IFD) -> Vec<Evidence> {
+    let mut res = vec![];
+    match ifd.version() {
+        Some(IfdVersion::V1) => res.push(Evidence::new(
+            "IFD",
+            "descriptor version 1".into(),
+            WEIGHT_IFD,
+            &[ICH, PCH_GEN2].concat(),
+        )),
+        Some(IfdVersion::V2) => res.push(Evidence::new(
+            "IFD",
+            "descriptor version 2".into(),
+            WEIGHT_IFD,
+            PCH_GEN3,
+        )),
+        None => {}
+    }
+    // Up to the 9 series, there are at most 5 regions.
+    let nr = ifd.regions.entries.len();
+    if nr > 5 {
+        let d = format!("{nr} regions in region table");
+        res.push(Evidence::new("IFD", d, WEIGHT_WEAK, PCH_GEN3));
+    }
+    // The number of PCH straps has grown with each generation.
+    let ns = ifd.pch_straps.len();
+    let families = match ns {
+        0 => &[],
+        1..=4 => ICH,
+        5..=24 => PCH_GEN2,
+        _ => PCH_GEN3,
+    };
+    if !families.is_empty() {
+        let d = format!("{ns} PCH straps");
+        res.push(Evidence::new("IFD", d, WEIGHT_WEAK, families));
+    }
+    res
+}
+
+// MD5 hashes of the keys that signed the manifests, as hex strings
+fn key_hashes(me: &ME) -> Vec<String> {
+    let mut res = me
+        .manifests()
+        .iter()
+        .map(|(_, m)| m.hash_key().iter().map(|b| format!("{b:02x}")).collect())
+        .collect::<Vec<String>>();
+    res.sort();
+    res.dedup();
+    res
+}
+
+// The generation follows from the major version for the regular ME.
+fn family_for_major(major: u16) -> Option<ChipsetFamily> {
+    let g = match major {
+        2..=5 => Generation::Gen1,
+        6..=10 => Generation::Gen2,
+        _ => Generation::Gen3,
+    };
+    let v = Version {
+        major,
+        minor: 0,
+        patch: 0,
+        build: 0,
+    };
+    ChipsetFamily::from_me(&g, &v)
+}
+
+fn me_evidence(me: &ME) -> (Vec<Evidence>, Option<Variant>) {
+    let mut res = vec![];
+    let mut variant = None;
+    let family = me
+        .version
+        .and_then(|v| ChipsetFamily::from_me(&me.generation, &v));
+    match (me.version, family) {
+        (Some(v), Some(f)) => {
+            let d = format!("ME version {v}");
+            res.push(Evidence::new("ME", d, WEIGHT_ME_VERSION, &[f]));
+        }
+        _ => {
+            let g = &me.generation;
+            let d = format!("ME generation {g:?}");
+            let families = families_for_generation(g);
+            res.push(Evidence::new("ME", d, WEIGHT_ME_GENERATION, families));
+        }
+    }
+    for h in key_hashes(me) {
+        let Some(meta) = get_meta_for_key(&h) else {
+            continue;
+        };
+        variant = Some(meta.variant);
+        let families = match meta.variant {
+            Variant::ME => meta
+                .version
+                .iter()
+                .filter_map(|v| v.split('.').next()?.parse::<u16>().ok())
+                .filter_map(family_for_major)
+                .collect(),
+            // TXE and SPS run on SoCs and server chipsets.
+            Variant::TXE | Variant::SPS => vec![],
+        };
+        let d = format!("manifest key {h}, {meta}");
+        res.push(Evidence::new("ME", d, WEIGHT_KEY, &families));
+    }
+    (res, variant)
+}
+
+impl Firmware {
+    /// Detect the platform the firmware is made for, given the data it was
+    /// parsed from.
+    pub fn platform(&self, data: &[u8]) -> Platform {
+        let mut evidence = vec![];
+        let mut variant = None;
+        let mut cpus = vec![];
+        if let Ok(ifd) = &self.ifd {
+            evidence.extend(ifd_evidence(ifd));
+        }
+        if let Some(Ok(me)) = &self.me {
+            let (e, v) = me_evidence(me);
+            evidence.extend(e);
+            variant = v;
+        }
+        if let Ok(fit) = &self.fit {
+            // Client platforms have a FIT since Haswell.
+            let families = [&[Series8, Series9], PCH_GEN3].concat();
+            let d = "FIT present".to_string();
+            evidence.push(Evidence::new("FIT", d, WEIGHT_WEAK, &families));
+            let mut cpuids = microcode::from_fit(fit, data)
+                .into_iter()
+                .filter_map(|(_, m)| m.ok())
+                .flat_map(|m| m.signatures())
+                .map(|(s, _)| s)
+                .collect::<Vec<u32>>();
+            cpuids.sort();
+            cpuids.dedup();
+            for s in cpuids {
+                let c = Cpuid::from_signature(s);
+                let Some((name, families)) = cpu_info(&c) else {
+                    continue;
+                };
+                let d = format!("microcode for {name}, CPUID {s:08x}");
+                evidence.push(Evidence::new("microcode", d, WEIGHT_CPU, families));
+                if !cpus.iter().any(|c| c == name) {
+                    cpus.push(name.to_string());
+                }
+            }
+        }
+        Platform::from_evidence(evidence, variant, cpus)
+    }
+}
+
+#[cfg(test)]
+static IFD_DATA: &[u8] = include_bytes!("../tests/me11.ifd");
+
+#[test]
+fn detect_from_ifd() {
+    let fw = Firmware {
+        ifd: IFD::parse(IFD_DATA),
+        me: None,
+        fit: crate::fit::Fit::new(&[]),
+        gbe: None,
+    };
+    let p = fw.platform(&[]);
+    // The descriptor alone only tells the generation.
+    assert_eq!(p.family, None);
+    assert_eq!(p.candidates, PCH_GEN3);
+    assert_eq!(p.confidence, 20);
+    assert_eq!(p.to_string(), "one of 5 chipset families");
+    assert_eq!(p.evidence.len(), 3);
+    assert!(p.evidence.iter().all(|e| e.source == "IFD"));
+}
+
+#[test]
+fn cpu_lookup() {
+    let kbl = Cpuid::from_signature(0x000906e9);
+    assert_eq!(cpu_info(&kbl).unwrap().0, "Kaby Lake");
+    let cfl = Cpuid::from_signature(0x000906ea);
+    assert_eq!(cpu_info(&cfl).unwrap(), ("Coffee Lake", &[Series300][..]));
+    assert!(cpu_info(&Cpuid::from_signature(0x00a20f10)).is_none());
+}
+
+#[test]
+fn weigh_evidence() {
+    let e = vec![
+        Evidence::new("ME", String::new(), WEIGHT_ME_VERSION, &[Series300]),
+        Evidence::new("microcode", String::new(), WEIGHT_CPU, &[Series100]),
+        Evidence::new("microcode", String::new(), WEIGHT_CPU, &[Series300]),
+        Evidence::new("IFD", String::new(), WEIGHT_IFD, PCH_GEN3),
+    ];
+    let cpus = vec!["Kaby Lake".to_string(), "Coffee Lake".to_string()];
+    let p = Platform::from_evidence(e, None, cpus);
+    assert_eq!(p.family, Some(Series300));
+    assert_eq!(p.candidates, [Series300]);
+    assert_eq!(p.confidence, 72);
+    assert_eq!(p.to_string(), "Cannon Point / Coffee Lake");
+    let p = Platform::from_evidence(vec![], Some(Variant::TXE), vec!["Apollo Lake".into()]);
+    assert_eq!(p.to_string(), "Apollo Lake (TXE)");
+}
@@ -4,7 +4,10 @@ use intel_fw::{
     Firmware,
     dir::gen2::{Directory as Gen2Dir, Module},
     fit::Fit,
-    ifd::{FlashMasterV1, FlashMasterV2, IFD},
+    ifd::{
+        FlashMasterV1, FlashMasterV2, IFD, IfdVersion,
+        straps::{ChipsetFamily, StrapField, StrapSection},
+    },
     me::{Generation, ME},
     part::{fpt::FTUP, gen2::Gen2Partition, gen3::Gen3Partition, partitions::Partitions},
 };
@@ -52,6 +55,35 @@ fn print_me_soft_config(me: &ME, ifd: &IFD) {
     }
 }
 
+/// Print the raw straps along with the decoded fields they contain.
+pub fn print_straps(ifd: &IFD, fields: &[StrapField]) {
+    let sections = [
+        ("PCH", StrapSection::Pch, &ifd.pch_straps),
+        ("MCH", StrapSection::Mch, &ifd.mch_straps),
+    ];
+    for (n, section, straps) in sections {
+        if straps.is_empty() {
+            continue;
+        }
+        println!("== {n} straps ==");
+        for (i, s) in straps.iter().enumerate() {
+            println!("  {i:2}: {s:08x}");
+            let fs = fields
+                .iter()
+                .filter(|f| f.section == section && f.strap == i);
+            for f in fs {
+                match ifd.strap_value(f) {
+                    Ok(v) => println!("      {f}: {v:#x}"),
+                    Err(e) => println!("      {f}: {e}"),
+                }
+                if !f.description.is_empty() {
+                    println!("        {}", f.description);
+                }
+            }
+        }
+    }
+}
+
 fn print_me(me: &ME) {
     println!("=== Intel (CS)ME ===");
     println!("{:?} detected", me.generation);
@@ -97,20 +129,29 @@ fn print_me(me: &ME) {
     }
 }
 
-fn print_fit(fit: &Fit) {
+pub fn print_fit(fit: &Fit) {
     println!("FIT @ {:08x}, {}", fit.offset, fit.header);
     for e in &fit.entries {
         println!("  {e}");
     }
 }
 
-enum IfdVersion {
-    V1,
-    V2,
+pub fn print_fit_policies(fit: &Fit, data: &[u8]) {
+    let records = fit.policy_records(data);
+    if records.is_empty() {
+        return;
+    }
+    println!("== Policy records ==");
+    for r in records {
+        println!("  {r}");
+    }
 }
 
-/// Get the IFD version based on ME generation.
-fn get_ifd_ver(me: &Option<Result<ME, String>>) -> Option<IfdVersion> {
+/// Get the IFD version, inferred from the ME generation if not detected.
+fn get_ifd_ver(ifd: &IFD, me: &Option<Result<ME, String>>) -> Option<IfdVersion> {
+    if let Some(v) = ifd.version() {
+        return Some(v);
+    }
     let Some(Ok(me)) = me else {
         return None;
     };
@@ -120,7 +161,7 @@ fn get_ifd_ver(me: &Option<Result<ME, String>>) -> Option<IfdVersion> {
     }
 }
 
-fn print_ifd(ifd: &IFD, ver: Option<IfdVersion>) {
+pub fn print_ifd(ifd: &IFD, ver: Option<IfdVersion>) {
     println!("=== Flash descriptor ===");
     println!("{ifd}");
     println!("== Masters ==");
@@ -139,14 +180,30 @@ fn print_ifd(ifd: &IFD, ver: Option<IfdVersion>) {
     }
 }
 
-pub fn show(fw: &Firmware, verbose: bool) {
+fn print_platform(fw: &Firmware, data: &[u8]) {
+    let p = fw.platform(data);
+    println!("== Platform ==");
+    println!("  {p}, confidence {}%", p.confidence);
+    if p.family.is_none() {
+        for c in &p.candidates {
+            println!("  ? {c}");
+        }
+    }
+    for e in &p.evidence {
+        println!("  - {e}");
+    }
+    println!();
+}
+
+pub fn show(fw: &Firmware, data: &[u8], verbose: bool) {
     if verbose {
         println!("{fw:#02x?}");
     }
     println!();
+    print_platform(fw, data);
     match &fw.ifd {
         Ok(ifd) => {
-            let ver = get_ifd_ver(&fw.me);
+            let ver = get_ifd_ver(ifd, &fw.me);
             print_ifd(ifd, ver);
         }
         Err(e) => warn!("Could not parse IFD: {e:?}"),
@@ -157,6 +214,14 @@ pub fn show(fw: &Firmware, verbose: bool) {
                 if let Ok(ifd) = &fw.ifd {
                     print_me_soft_config(me, ifd);
                     println!();
+                    let family = me
+                        .version
+                        .and_then(|v| ChipsetFamily::from_me(&me.generation, &v));
+                    if let Some(f) = family {
+                        println!("=== Soft straps, {f} ===");
+                        print_straps(ifd, &f.fields());
+                        println!();
+                    }
                 }
                 print_me(me);
             }
@@ -166,8 +231,20 @@ pub fn show(fw: &Firmware, verbose: bool) {
         error!("No ME firmware found");
     }
     println!();
+    match &fw.gbe {
+        Some(Ok(gbe)) => {
+            println!("=== GbE ===");
+            println!("{gbe}");
+            println!();
+        }
+        Some(Err(e)) => warn!("Could not parse GbE region: {e}"),
+        None => {}
+    }
     match &fw.fit {
-        Ok(fit) => print_fit(fit),
+        Ok(fit) => {
+            print_fit(fit);
+            print_fit_policies(fit, data);
+        }
         Err(e) => warn!("Could not parse FIT: {e:?}"),
     }
     println!();
@@ -6,7 +6,9 @@ use core::fmt::{self, Display};
 use serde::{Deserialize, Serialize};
 use zerocopy_derive::{FromBytes, Immutable, IntoBytes};
 
-#[derive(Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(
+    Immutable, IntoBytes, FromBytes, Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq,
+)]
 #[repr(C)]
 pub struct Version {
     pub major: u16,